@@ -0,0 +1,575 @@
+// Bounding volume hierarchy over a mesh's triangles, for raycasts, overlap
+// and closest point queries against meshes far too big to test triangle by
+// triangle.
+//
+// Built with binned surface area heuristic splits (Wald, "On fast
+// Construction of SAH-based Bounding Volume Hierarchies"). The BVH only holds
+// triangle indices, not the mesh, so every query takes the positions and
+// indices it was built from. When vertices move but the triangles stay the
+// same (deforming, smoothing, sculpting small areas), refit updates the
+// bounds much faster than a rebuild, though the tree gets worse the more
+// things move around.
+
+use math::{
+    add, closest_point_on_triangle, cross, dot, length, normalize, ray_triangle, scale, sub,
+    triangle,
+};
+use raycast::Hit;
+
+// Triangles per leaf the build stops at.
+const LEAF_SIZE: usize = 4;
+const BINS: usize = 16;
+
+pub struct Bvh {
+    nodes: Vec<Node>,
+    // Triangle indices, ordered so that every leaf's are next to each other.
+    triangles: Vec<usize>,
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    min: [f32; 3],
+    max: [f32; 3],
+    // For leaves the first of count triangles, for inner nodes the index of
+    // the first child, with the second right after it.
+    start: usize,
+    count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClosestPoint {
+    pub position: [f32; 3],
+    pub triangle: usize,
+    pub distance: f32,
+}
+
+impl Bvh {
+    pub fn new(positions: &[[f32; 3]], indices: &[usize]) -> Bvh {
+        let triangle_count = indices.len() / 3;
+        let bounds = (0..triangle_count)
+            .map(|index| triangle_bounds(positions, indices, index))
+            .collect::<Vec<_>>();
+        let centers = bounds
+            .iter()
+            .map(|&(min, max)| scale(add(min, max), 0.5))
+            .collect::<Vec<_>>();
+        let mut bvh = Bvh {
+            nodes: vec![Node {
+                min: [0.0; 3],
+                max: [0.0; 3],
+                start: 0,
+                count: triangle_count,
+            }],
+            triangles: (0..triangle_count).collect(),
+        };
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let Node { start, count, .. } = bvh.nodes[node];
+            let (min, max) = union_bounds(&bvh.triangles[start..start + count], &bounds);
+            bvh.nodes[node].min = min;
+            bvh.nodes[node].max = max;
+            if count <= LEAF_SIZE {
+                continue;
+            }
+            let split = match split(&mut bvh.triangles[start..start + count], &bounds, &centers) {
+                Some(split) => split,
+                None => continue,
+            };
+            let first = bvh.nodes.len();
+            bvh.nodes.push(Node {
+                min: [0.0; 3],
+                max: [0.0; 3],
+                start,
+                count: split,
+            });
+            bvh.nodes.push(Node {
+                min: [0.0; 3],
+                max: [0.0; 3],
+                start: start + split,
+                count: count - split,
+            });
+            bvh.nodes[node].start = first;
+            bvh.nodes[node].count = 0;
+            stack.push(first);
+            stack.push(first + 1);
+        }
+        bvh
+    }
+
+    // Updates the bounds after vertices moved. The indices have to be the
+    // same as the BVH was built with, and so does the number of triangles.
+    pub fn refit(&mut self, positions: &[[f32; 3]], indices: &[usize]) {
+        // The root of an empty BVH has no triangles and no children.
+        if self.triangles.is_empty() {
+            return;
+        }
+        // Children always come after their parents, so going backwards fits
+        // every node after its children.
+        for node in (0..self.nodes.len()).rev() {
+            let Node { start, count, .. } = self.nodes[node];
+            let (min, max) = if count > 0 {
+                let mut min = [f32::INFINITY; 3];
+                let mut max = [f32::NEG_INFINITY; 3];
+                for &index in &self.triangles[start..start + count] {
+                    let (low, high) = triangle_bounds(positions, indices, index);
+                    grow(&mut min, &mut max, low, high);
+                }
+                (min, max)
+            } else {
+                let (left, right) = (self.nodes[start], self.nodes[start + 1]);
+                let mut min = left.min;
+                let mut max = left.max;
+                grow(&mut min, &mut max, right.min, right.max);
+                (min, max)
+            };
+            self.nodes[node].min = min;
+            self.nodes[node].max = max;
+        }
+    }
+
+    // The closest hit from either side of the triangles. The normal is the
+    // triangle's, facing out for meshes wound like surface_net's. None for a
+    // zero or non-finite direction, like the other raycasts.
+    pub fn raycast(
+        &self,
+        positions: &[[f32; 3]],
+        indices: &[usize],
+        origin: [f32; 3],
+        direction: [f32; 3],
+        max_distance: f32,
+    ) -> Option<Hit> {
+        let length = length(direction);
+        if self.triangles.is_empty() || !(length > 0.0 && length.is_finite()) {
+            return None;
+        }
+        let direction = normalize(direction);
+        let inverse = direction.map(|value| 1.0 / value);
+        let mut best: Option<(f32, usize)> = None;
+        let mut limit = max_distance;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let Node { start, count, .. } = self.nodes[node];
+            if ray_box(&self.nodes[node], origin, inverse, limit).is_none() {
+                continue;
+            }
+            if count > 0 {
+                for &index in &self.triangles[start..start + count] {
+                    let corners = &indices[index * 3..index * 3 + 3];
+                    if let Some(distance) =
+                        ray_triangle(origin, direction, triangle(positions, corners))
+                    {
+                        if distance <= limit {
+                            limit = distance;
+                            best = Some((distance, index));
+                        }
+                    }
+                }
+                continue;
+            }
+            // Nearest child on top, so it's searched first and the other
+            // one can often be skipped.
+            let first = ray_box(&self.nodes[start], origin, inverse, limit);
+            let second = ray_box(&self.nodes[start + 1], origin, inverse, limit);
+            match (first, second) {
+                (Some(first), Some(second)) if second < first => {
+                    stack.push(start);
+                    stack.push(start + 1);
+                }
+                (Some(_), Some(_)) => {
+                    stack.push(start + 1);
+                    stack.push(start);
+                }
+                (Some(_), None) => stack.push(start),
+                (None, Some(_)) => stack.push(start + 1),
+                (None, None) => {}
+            }
+        }
+        best.map(|(distance, index)| {
+            let [a, b, c] = triangle(positions, &indices[index * 3..index * 3 + 3]);
+            Hit {
+                position: add(origin, scale(direction, distance)),
+                normal: normalize(cross(sub(b, a), sub(c, a))),
+                distance,
+                triangle: Some(index),
+            }
+        })
+    }
+
+    // Triangles whose bounds overlap the box, as candidates for exact
+    // collision tests.
+    pub fn overlapping(
+        &self,
+        positions: &[[f32; 3]],
+        indices: &[usize],
+        min: [f32; 3],
+        max: [f32; 3],
+    ) -> Vec<usize> {
+        let mut found = Vec::new();
+        if self.triangles.is_empty() {
+            return found;
+        }
+        let overlaps = |low: [f32; 3], high: [f32; 3]| {
+            (0..3).all(|axis| low[axis] <= max[axis] && high[axis] >= min[axis])
+        };
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let Node { start, count, .. } = self.nodes[node];
+            if !overlaps(self.nodes[node].min, self.nodes[node].max) {
+                continue;
+            }
+            if count > 0 {
+                for &index in &self.triangles[start..start + count] {
+                    let (low, high) = triangle_bounds(positions, indices, index);
+                    if overlaps(low, high) {
+                        found.push(index);
+                    }
+                }
+            } else {
+                stack.push(start);
+                stack.push(start + 1);
+            }
+        }
+        found
+    }
+
+    // Closest point on the mesh to the point, within max_distance (which can
+    // be infinite).
+    pub fn closest_point(
+        &self,
+        positions: &[[f32; 3]],
+        indices: &[usize],
+        point: [f32; 3],
+        max_distance: f32,
+    ) -> Option<ClosestPoint> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+        let mut best: Option<([f32; 3], usize)> = None;
+        let mut limit = max_distance * max_distance;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let Node { start, count, .. } = self.nodes[node];
+            if box_distance_squared(&self.nodes[node], point) > limit {
+                continue;
+            }
+            if count > 0 {
+                for &index in &self.triangles[start..start + count] {
+                    let corners = &indices[index * 3..index * 3 + 3];
+                    let closest = closest_point_on_triangle(point, triangle(positions, corners));
+                    let offset = sub(closest, point);
+                    let distance = dot(offset, offset);
+                    if distance <= limit {
+                        limit = distance;
+                        best = Some((closest, index));
+                    }
+                }
+                continue;
+            }
+            let first = box_distance_squared(&self.nodes[start], point);
+            let second = box_distance_squared(&self.nodes[start + 1], point);
+            if second < first {
+                stack.push(start);
+                stack.push(start + 1);
+            } else {
+                stack.push(start + 1);
+                stack.push(start);
+            }
+        }
+        best.map(|(position, triangle)| ClosestPoint {
+            position,
+            triangle,
+            distance: limit.sqrt(),
+        })
+    }
+}
+
+// Sorts the triangles so that the first ones (as many as returned) go in one
+// child and the rest in the other. None if splitting isn't worth it.
+fn split(
+    triangles: &mut [usize],
+    bounds: &[([f32; 3], [f32; 3])],
+    centers: &[[f32; 3]],
+) -> Option<usize> {
+    let mut center_min = [f32::INFINITY; 3];
+    let mut center_max = [f32::NEG_INFINITY; 3];
+    for &index in triangles.iter() {
+        grow(
+            &mut center_min,
+            &mut center_max,
+            centers[index],
+            centers[index],
+        );
+    }
+    let bin_of = |index: usize, axis: usize| {
+        let extent = center_max[axis] - center_min[axis];
+        (((centers[index][axis] - center_min[axis]) / extent * BINS as f32) as usize).min(BINS - 1)
+    };
+    let (min, max) = union_bounds(triangles, bounds);
+    // Best cost, axis and the first bin on the right side.
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        if center_max[axis] <= center_min[axis] {
+            continue;
+        }
+        let mut counts = [0; BINS];
+        let mut bin_bounds = [([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]); BINS];
+        for &index in triangles.iter() {
+            let bin = bin_of(index, axis);
+            counts[bin] += 1;
+            let (low, high) = bounds[index];
+            grow(&mut bin_bounds[bin].0, &mut bin_bounds[bin].1, low, high);
+        }
+        // Area and count of everything left of each split, then right.
+        let mut left_area = [0.0; BINS];
+        let mut left_count = [0; BINS];
+        let (mut low, mut high) = ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]);
+        let mut count = 0;
+        for bin in 0..BINS - 1 {
+            grow(&mut low, &mut high, bin_bounds[bin].0, bin_bounds[bin].1);
+            count += counts[bin];
+            left_area[bin] = area(low, high);
+            left_count[bin] = count;
+        }
+        let (mut low, mut high) = ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]);
+        let mut count = 0;
+        for bin in (1..BINS).rev() {
+            grow(&mut low, &mut high, bin_bounds[bin].0, bin_bounds[bin].1);
+            count += counts[bin];
+            if left_count[bin - 1] == 0 || count == 0 {
+                continue;
+            }
+            // Taking a traversal step to cost as much as a triangle test.
+            let cost = 1.0
+                + (left_area[bin - 1] * left_count[bin - 1] as f32
+                    + area(low, high) * count as f32)
+                    / area(min, max);
+            if best.is_none_or(|(best, _, _)| cost < best) {
+                best = Some((cost, axis, bin));
+            }
+        }
+    }
+    let (cost, axis, split_bin) = best?;
+    // Big nodes get split even when the heuristic says otherwise, big leaves
+    // would make the queries slow wherever it's wrong.
+    if cost >= triangles.len() as f32 && triangles.len() <= LEAF_SIZE * 4 {
+        return None;
+    }
+    let mut left = 0;
+    for i in 0..triangles.len() {
+        if bin_of(triangles[i], axis) < split_bin {
+            triangles.swap(i, left);
+            left += 1;
+        }
+    }
+    Some(left)
+}
+
+fn triangle_bounds(
+    positions: &[[f32; 3]],
+    indices: &[usize],
+    index: usize,
+) -> ([f32; 3], [f32; 3]) {
+    let [a, b, c] = triangle(positions, &indices[index * 3..index * 3 + 3]);
+    let mut min = a;
+    let mut max = a;
+    grow(&mut min, &mut max, b, b);
+    grow(&mut min, &mut max, c, c);
+    (min, max)
+}
+
+fn union_bounds(triangles: &[usize], bounds: &[([f32; 3], [f32; 3])]) -> ([f32; 3], [f32; 3]) {
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+    for &index in triangles {
+        grow(&mut min, &mut max, bounds[index].0, bounds[index].1);
+    }
+    (min, max)
+}
+
+fn grow(min: &mut [f32; 3], max: &mut [f32; 3], low: [f32; 3], high: [f32; 3]) {
+    for axis in 0..3 {
+        min[axis] = min[axis].min(low[axis]);
+        max[axis] = max[axis].max(high[axis]);
+    }
+}
+
+// Half the surface area, which is all the heuristic needs.
+fn area(min: [f32; 3], max: [f32; 3]) -> f32 {
+    let size = sub(max, min);
+    size[0] * size[1] + size[1] * size[2] + size[2] * size[0]
+}
+
+// Distance along the ray to where it enters the box (0 if it starts inside),
+// None if it misses it within the limit.
+fn ray_box(node: &Node, origin: [f32; 3], inverse: [f32; 3], limit: f32) -> Option<f32> {
+    let mut near = 0.0f32;
+    let mut far = limit;
+    for axis in 0..3 {
+        let a = (node.min[axis] - origin[axis]) * inverse[axis];
+        let b = (node.max[axis] - origin[axis]) * inverse[axis];
+        // NaN (from 0 * infinity, rays along the box's face) leaves the
+        // bounds alone through the way min and max treat it.
+        near = near.max(a.min(b));
+        far = far.min(a.max(b));
+    }
+    if near <= far {
+        Some(near)
+    } else {
+        None
+    }
+}
+
+fn box_distance_squared(node: &Node, point: [f32; 3]) -> f32 {
+    let mut distance = 0.0;
+    for (axis, &value) in point.iter().enumerate() {
+        let outside = (node.min[axis] - value)
+            .max(value - node.max[axis])
+            .max(0.0);
+        distance += outside * outside;
+    }
+    distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_meshes::sphere;
+
+    // Brute force versions of the queries, to check against.
+    fn raycast(
+        positions: &[[f32; 3]],
+        indices: &[usize],
+        origin: [f32; 3],
+        direction: [f32; 3],
+    ) -> Option<(f32, usize)> {
+        let direction = normalize(direction);
+        indices
+            .chunks(3)
+            .enumerate()
+            .filter_map(|(index, corners)| {
+                let distance = ray_triangle(origin, direction, triangle(positions, corners))?;
+                Some((distance, index))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    fn closest_distance(positions: &[[f32; 3]], indices: &[usize], point: [f32; 3]) -> f32 {
+        indices
+            .chunks(3)
+            .map(|corners| {
+                let closest = closest_point_on_triangle(point, triangle(positions, corners));
+                length(sub(closest, point))
+            })
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    // Points scattered through and around the sphere, the same every run.
+    fn points(count: usize) -> Vec<[f32; 3]> {
+        let mut state = 12345u32;
+        let mut next = || {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1 << 24) as f32 * 20.0 - 2.0
+        };
+        (0..count).map(|_| [next(), next(), next()]).collect()
+    }
+
+    fn check_queries(bvh: &Bvh, positions: &[[f32; 3]], indices: &[usize]) {
+        let points = points(200);
+        for pair in points.chunks(2) {
+            let (origin, target) = (pair[0], pair[1]);
+            let direction = sub(target, origin);
+            let hit = bvh.raycast(positions, indices, origin, direction, f32::INFINITY);
+            let expected = raycast(positions, indices, origin, direction);
+            assert_eq!(hit.is_some(), expected.is_some());
+            if let (Some(hit), Some((distance, _))) = (hit, expected) {
+                // Ties between neighbouring triangles can go either way.
+                assert!((hit.distance - distance).abs() < 1e-4);
+                let corners = &indices[hit.triangle.unwrap() * 3..][..3];
+                let on_triangle =
+                    ray_triangle(origin, normalize(direction), triangle(positions, corners));
+                assert!((on_triangle.unwrap() - distance).abs() < 1e-4);
+            }
+        }
+        for &point in &points {
+            let closest = bvh
+                .closest_point(positions, indices, point, f32::INFINITY)
+                .unwrap();
+            let distance = closest_distance(positions, indices, point);
+            assert!((closest.distance - distance).abs() < 1e-4);
+            assert!((length(sub(closest.position, point)) - distance).abs() < 1e-4);
+        }
+        for pair in points.chunks(2) {
+            let min = [0, 1, 2].map(|axis| pair[0][axis].min(pair[1][axis]) * 0.5 + 4.0);
+            let max = [0, 1, 2].map(|axis| pair[0][axis].max(pair[1][axis]) * 0.5 + 4.0);
+            let mut found = bvh.overlapping(positions, indices, min, max);
+            found.sort();
+            let expected = (0..indices.len() / 3)
+                .filter(|&index| {
+                    let (low, high) = triangle_bounds(positions, indices, index);
+                    (0..3).all(|axis| low[axis] <= max[axis] && high[axis] >= min[axis])
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let (positions, indices) = sphere();
+        let bvh = Bvh::new(&positions, &indices);
+        check_queries(&bvh, &positions, &indices);
+    }
+
+    #[test]
+    fn refit_follows_moved_vertices() {
+        let (mut positions, indices) = sphere();
+        let mut bvh = Bvh::new(&positions, &indices);
+        // Squash and shift the sphere, so the old bounds would be wrong.
+        for position in &mut positions {
+            *position = [
+                position[0] * 1.3 - 1.0,
+                position[1] * 0.6 + 3.0,
+                position[2],
+            ];
+        }
+        bvh.refit(&positions, &indices);
+        check_queries(&bvh, &positions, &indices);
+    }
+
+    #[test]
+    fn limits_are_kept() {
+        let (positions, indices) = sphere();
+        let bvh = Bvh::new(&positions, &indices);
+        let origin = [8.0, 8.0, -2.0];
+        let hit = bvh
+            .raycast(&positions, &indices, origin, [0.0, 0.0, 1.0], 100.0)
+            .unwrap();
+        assert!(hit.normal[2] < 0.0);
+        let short = hit.distance * 0.9;
+        assert_eq!(
+            bvh.raycast(&positions, &indices, origin, [0.0, 0.0, 1.0], short),
+            None
+        );
+        assert_eq!(
+            bvh.raycast(&positions, &indices, origin, [0.0; 3], 100.0),
+            None
+        );
+        assert_eq!(bvh.closest_point(&positions, &indices, origin, short), None);
+        let closest = bvh
+            .closest_point(&positions, &indices, origin, 100.0)
+            .unwrap();
+        assert!((closest.distance - hit.distance).abs() < 0.5);
+        assert!(bvh
+            .overlapping(&positions, &indices, [-5.0; 3], [-1.0; 3])
+            .is_empty());
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let mut bvh = Bvh::new(&[], &[]);
+        bvh.refit(&[], &[]);
+        assert_eq!(bvh.raycast(&[], &[], [0.0; 3], [1.0, 0.0, 0.0], 10.0), None);
+        assert_eq!(bvh.closest_point(&[], &[], [0.0; 3], f32::INFINITY), None);
+        assert!(bvh.overlapping(&[], &[], [-1.0; 3], [1.0; 3]).is_empty());
+    }
+}
@@ -0,0 +1,707 @@
+// Collision shapes for physics engines, which want something much simpler
+// than the render mesh: a decimated trimesh for static geometry, a
+// heightfield for 2.5D terrain, and a set of convex hulls for dynamic
+// objects.
+//
+// The heightfield and the convex decomposition start from a Grid. Fields can
+// be sampled into one, and meshes voxelized into one with voxelize::voxelize.
+
+use std::collections::{HashMap, VecDeque};
+
+use decimate::{decimate, Decimation};
+use edge_crossing;
+use grid::Grid;
+
+// The mesh simplified as far as it goes without moving the surface more
+// than tolerance, keeping only what physics needs: positions and indices.
+// None if the indices aren't triangles of the vertices.
+pub fn collision_trimesh(
+    positions: &[[f32; 3]],
+    indices: &[usize],
+    tolerance: f32,
+) -> Option<(Vec<[f32; 3]>, Vec<usize>)> {
+    // The normals are thrown away, so there's no need for real ones.
+    let normals = vec![[0.0; 3]; positions.len()];
+    let mut decimation = Decimation::new(0);
+    decimation.max_error = tolerance;
+    let (positions, _, indices, _) = decimate(positions, &normals, indices, &decimation)?;
+    Some((positions, indices))
+}
+
+// Heights along Y over a grid in the XZ plane, like heightmap.rs uses.
+pub struct Heightfield {
+    // Number of columns along X, and rows along Z.
+    pub width: usize,
+    pub depth: usize,
+    // World X and Z of column 0, row 0.
+    pub origin: [f32; 2],
+    pub spacing: [f32; 2],
+    // Row-major, rows along Z.
+    pub heights: Vec<f32>,
+}
+
+impl Heightfield {
+    pub fn height(&self, column: usize, row: usize) -> f32 {
+        self.heights[row * self.width + column]
+    }
+}
+
+// The top of the solid in every column of the grid, interpolated like
+// surface_net places the surface along cell edges. Overhangs and caves below
+// the top are lost, which is the price of a heightfield. Columns with no
+// solid at all get the height of the grid's bottom.
+pub fn grid_heightfield(grid: &Grid) -> Heightfield {
+    let mut heights = Vec::with_capacity(grid.size[0] * grid.size[2]);
+    for z in 0..grid.size[2] {
+        for x in 0..grid.size[0] {
+            let mut height = grid.origin[1];
+            // From the top down, starting with the background above the grid.
+            let mut above = grid.background;
+            for y in (0..grid.size[1]).rev() {
+                let value = grid.get(x as isize, y as isize, z as isize);
+                if let Some(interp) = edge_crossing(above, value) {
+                    if value < 0.0 {
+                        let top = grid.origin[1] + (y + 1) as f32 * grid.spacing[1];
+                        height = top - interp * grid.spacing[1];
+                        break;
+                    }
+                }
+                above = value;
+            }
+            heights.push(height);
+        }
+    }
+    Heightfield {
+        width: grid.size[0],
+        depth: grid.size[2],
+        origin: [grid.origin[0], grid.origin[2]],
+        spacing: [grid.spacing[0], grid.spacing[2]],
+        heights,
+    }
+}
+
+pub struct ConvexDecomposition {
+    // Stop splitting at this many hulls.
+    pub max_hulls: usize,
+    // Stop splitting once no hull covers more than this fraction of the
+    // whole shape's volume in empty space.
+    pub max_concavity: f32,
+    // Number of evenly spaced splitting planes tried along each axis, more
+    // finds better splits but takes longer.
+    pub planes_per_axis: usize,
+}
+
+impl ConvexDecomposition {
+    pub fn new(max_hulls: usize) -> ConvexDecomposition {
+        ConvexDecomposition {
+            max_hulls,
+            max_concavity: 0.01,
+            planes_per_axis: 8,
+        }
+    }
+}
+
+// Closed and wound like surface_net's meshes.
+pub struct ConvexHull {
+    pub positions: Vec<[f32; 3]>,
+    pub indices: Vec<usize>,
+}
+
+// Approximate convex decomposition of the solid samples of the grid, in the
+// spirit of V-HACD (Mamou, "Volumetric Hierarchical Approximate Convex
+// Decomposition"): starting with every connected piece of solid as a part,
+// the part whose convex hull has the most empty space in it is split in two
+// along whichever axis-aligned plane leaves the least empty space in the
+// halves' hulls, until there are enough hulls or all of them are tight
+// enough.
+//
+// Hulls are built from the same edge crossings surface_net places its
+// vertices with, so they hug the surface it would make instead of the
+// voxels' staircase. Where a part was split off from its neighbour, the hull
+// goes halfway to it.
+pub fn convex_decomposition(grid: &Grid, settings: &ConvexDecomposition) -> Vec<ConvexHull> {
+    let (mut labels, pieces) = solid_pieces(grid);
+    let total = pieces.iter().map(|piece| piece.len()).sum::<usize>() as f64;
+    let mut parts = pieces
+        .into_iter()
+        .enumerate()
+        .map(|(label, voxels)| Part::new(grid, voxels, &|voxel| labels[voxel] == label))
+        .collect::<Vec<_>>();
+    while parts.len() < settings.max_hulls {
+        let worst = match (0..parts.len())
+            .max_by(|&a, &b| parts[a].empty_space.total_cmp(&parts[b].empty_space))
+        {
+            Some(worst) => worst,
+            None => break,
+        };
+        if parts[worst].empty_space <= settings.max_concavity as f64 * total {
+            break;
+        }
+        match split_part(grid, &labels, &parts[worst], settings.planes_per_axis) {
+            Some((first, second)) => {
+                for &voxel in &second.voxels {
+                    labels[voxel] = parts.len();
+                }
+                parts[worst] = first;
+                parts.push(second);
+            }
+            None => break,
+        }
+    }
+    parts
+        .iter()
+        .map(|part| {
+            let mut new_index = HashMap::new();
+            let mut positions = Vec::new();
+            let mut indices = Vec::new();
+            for face in &part.hull {
+                for &corner in face {
+                    let index = *new_index.entry(corner).or_insert_with(|| {
+                        let point = part.points[corner];
+                        let mut position = [0.0; 3];
+                        for axis in 0..3 {
+                            position[axis] = grid.origin[axis]
+                                + point[axis] as f32 / SUBDIVISIONS as f32 * grid.spacing[axis];
+                        }
+                        positions.push(position);
+                        positions.len() - 1
+                    });
+                    indices.push(index);
+                }
+            }
+            ConvexHull { positions, indices }
+        })
+        .collect()
+}
+
+// Hull points are in fixed point, in these fractions of a voxel, so that the
+// hull can be computed exactly.
+const SUBDIVISIONS: i64 = 256;
+
+struct Part {
+    // Indices of the part's samples in the grid.
+    voxels: Vec<usize>,
+    // Points the hull is built from, and its triangles as indices into them.
+    points: Vec<[i64; 3]>,
+    hull: Vec<[usize; 3]>,
+    // Hull volume minus the part's volume, in voxels.
+    empty_space: f64,
+}
+
+impl Part {
+    // inside tells which samples belong to the part.
+    fn new(grid: &Grid, voxels: Vec<usize>, inside: &dyn Fn(usize) -> bool) -> Part {
+        // Only samples with a neighbour outside the part add points: the
+        // crossing towards air, or halfway towards another part.
+        let mut points = Vec::new();
+        for &voxel in &voxels {
+            let coord = grid_coord(grid, voxel);
+            let value = grid.data[voxel];
+            for (axis, step, neighbour) in neighbours(grid, voxel) {
+                let distance = match neighbour {
+                    Some(neighbour) if inside(neighbour) => continue,
+                    Some(neighbour) if grid.data[neighbour] < 0.0 => 0.5,
+                    Some(neighbour) => edge_crossing(value, grid.data[neighbour]).unwrap(),
+                    None => edge_crossing(value, grid.background).unwrap_or(0.5),
+                };
+                let mut point = coord.map(|value| value as i64 * SUBDIVISIONS);
+                point[axis] += step * (distance * SUBDIVISIONS as f32).round() as i64;
+                points.push(point);
+            }
+        }
+        let hull = convex_hull(&points);
+        let volume = hull
+            .iter()
+            .map(|&[a, b, c]| determinant(points[a], points[b], points[c]))
+            .sum::<i128>() as f64
+            / (6 * SUBDIVISIONS * SUBDIVISIONS * SUBDIVISIONS) as f64;
+        Part {
+            empty_space: volume - voxels.len() as f64,
+            voxels,
+            points,
+            hull,
+        }
+    }
+}
+
+// The best split of the part by a plane across one of the axes, None if
+// it's a single sample thick all ways.
+fn split_part(
+    grid: &Grid,
+    labels: &[usize],
+    part: &Part,
+    planes_per_axis: usize,
+) -> Option<(Part, Part)> {
+    let label = labels[*part.voxels.first()?];
+    let mut best: Option<(f64, Part, Part)> = None;
+    for axis in 0..3 {
+        let low = part
+            .voxels
+            .iter()
+            .map(|&voxel| grid_coord(grid, voxel)[axis])
+            .min()?;
+        let high = part
+            .voxels
+            .iter()
+            .map(|&voxel| grid_coord(grid, voxel)[axis])
+            .max()?;
+        let mut planes = (1..=planes_per_axis)
+            .map(|plane| {
+                low + (plane * (high - low + 1) + planes_per_axis / 2) / (planes_per_axis + 1)
+            })
+            .filter(|&plane| plane > low && plane <= high)
+            .collect::<Vec<_>>();
+        planes.dedup();
+        for plane in planes {
+            let below = |voxel| grid_coord(grid, voxel)[axis] < plane;
+            let (first, second): (Vec<_>, Vec<_>) =
+                part.voxels.iter().partition(|&&voxel| below(voxel));
+            let first = Part::new(grid, first, &|voxel| labels[voxel] == label && below(voxel));
+            let second = Part::new(grid, second, &|voxel| {
+                labels[voxel] == label && !below(voxel)
+            });
+            let cost = first.empty_space + second.empty_space;
+            if best.as_ref().is_none_or(|&(best, _, _)| cost < best) {
+                best = Some((cost, first, second));
+            }
+        }
+    }
+    best.map(|(_, first, second)| (first, second))
+}
+
+// Solid samples of the grid, grouped into face-connected pieces, and which
+// piece every sample is in (usize::MAX for air).
+fn solid_pieces(grid: &Grid) -> (Vec<usize>, Vec<Vec<usize>>) {
+    let mut labels = vec![usize::MAX; grid.data.len()];
+    let mut pieces = Vec::new();
+    for start in 0..grid.data.len() {
+        if labels[start] != usize::MAX || grid.data[start] >= 0.0 {
+            continue;
+        }
+        labels[start] = pieces.len();
+        let mut piece = Vec::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        while let Some(voxel) = queue.pop_front() {
+            piece.push(voxel);
+            for (_, _, neighbour) in neighbours(grid, voxel) {
+                if let Some(neighbour) = neighbour {
+                    if labels[neighbour] == usize::MAX && grid.data[neighbour] < 0.0 {
+                        labels[neighbour] = pieces.len();
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+        }
+        pieces.push(piece);
+    }
+    (labels, pieces)
+}
+
+fn grid_coord(grid: &Grid, index: usize) -> [usize; 3] {
+    [
+        index % grid.size[0],
+        index / grid.size[0] % grid.size[1],
+        index / (grid.size[0] * grid.size[1]),
+    ]
+}
+
+// The six face neighbours of a sample as (axis, direction, index), with
+// None for ones outside the grid.
+fn neighbours(grid: &Grid, index: usize) -> Vec<(usize, i64, Option<usize>)> {
+    let mut stride = 1;
+    let mut neighbours = Vec::with_capacity(6);
+    for (axis, &coord) in grid_coord(grid, index).iter().enumerate() {
+        let before = if coord > 0 {
+            Some(index - stride)
+        } else {
+            None
+        };
+        let after = if coord + 1 < grid.size[axis] {
+            Some(index + stride)
+        } else {
+            None
+        };
+        neighbours.push((axis, -1, before));
+        neighbours.push((axis, 1, after));
+        stride *= grid.size[axis];
+    }
+    neighbours
+}
+
+// Quickhull (Barber, Dobkin and Huhdanpaa, "The Quickhull Algorithm for
+// Convex Hulls") on integer points, which makes every orientation test exact
+// and saves all the usual epsilon trouble with the many coplanar points
+// grids give. Returns triangles wound counter-clockwise seen from outside,
+// or nothing if the points are all in a plane.
+fn convex_hull(points: &[[i64; 3]]) -> Vec<[usize; 3]> {
+    let first = match (0..points.len()).min_by_key(|&point| points[point]) {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    let second = (0..points.len())
+        .max_by_key(|&point| {
+            length_squared(difference(points[point], points[first]).map(i128::from))
+        })
+        .unwrap();
+    let line = difference(points[second], points[first]);
+    let third = (0..points.len())
+        .max_by_key(|&point| length_squared(cross(line, difference(points[point], points[first]))))
+        .unwrap();
+    let fourth = (0..points.len())
+        .max_by_key(|&point| orient(points, [first, second, third], point).abs())
+        .unwrap();
+    if orient(points, [first, second, third], fourth) == 0 {
+        return Vec::new();
+    }
+
+    let mut faces = Vec::new();
+    let mut edges = HashMap::new();
+    let simplex = [first, second, third, fourth];
+    for &(a, b, c, opposite) in &[(0, 1, 2, 3), (0, 3, 1, 2), (1, 3, 2, 0), (2, 3, 0, 1)] {
+        let mut corners = [simplex[a], simplex[b], simplex[c]];
+        if orient(points, corners, simplex[opposite]) > 0 {
+            corners.swap(0, 1);
+        }
+        add_face(&mut faces, &mut edges, corners);
+    }
+    let everything = (0..points.len()).collect::<Vec<_>>();
+    assign_outside(points, &mut faces, 0..4, everything);
+
+    let mut pending = (0..4).collect::<Vec<_>>();
+    while let Some(face) = pending.pop() {
+        if !faces[face].alive || faces[face].outside.is_empty() {
+            continue;
+        }
+        // The point furthest out from the face is certainly on the hull.
+        let eye = *faces[face]
+            .outside
+            .iter()
+            .max_by_key(|&&point| orient(points, faces[face].corners, point))
+            .unwrap();
+        // Everything the eye can see, which is a connected patch around the
+        // face.
+        let mut visible = vec![face];
+        faces[face].alive = false;
+        let mut next = 0;
+        let mut horizon = Vec::new();
+        while next < visible.len() {
+            let corners = faces[visible[next]].corners;
+            next += 1;
+            for i in 0..3 {
+                let (a, b) = (corners[i], corners[(i + 1) % 3]);
+                let neighbour = edges[&(b, a)];
+                if !faces[neighbour].alive {
+                    continue;
+                }
+                if orient(points, faces[neighbour].corners, eye) > 0 {
+                    faces[neighbour].alive = false;
+                    visible.push(neighbour);
+                } else {
+                    horizon.push((a, b));
+                }
+            }
+        }
+        // A neighbour can get marked visible after one of its edges went on
+        // the horizon, so the horizon is only final once all are known.
+        horizon.retain(|&(a, b)| faces[edges[&(b, a)]].alive);
+        let mut orphans = Vec::new();
+        for &dead in &visible {
+            let corners = faces[dead].corners;
+            for i in 0..3 {
+                edges.remove(&(corners[i], corners[(i + 1) % 3]));
+            }
+            orphans.append(&mut faces[dead].outside);
+        }
+        orphans.retain(|&point| point != eye);
+        let start = faces.len();
+        for &(a, b) in &horizon {
+            add_face(&mut faces, &mut edges, [a, b, eye]);
+        }
+        assign_outside(points, &mut faces, start..start + horizon.len(), orphans);
+        pending.extend(start..start + horizon.len());
+    }
+    faces
+        .into_iter()
+        .filter(|face| face.alive)
+        .map(|face| face.corners)
+        .collect()
+}
+
+struct Face {
+    corners: [usize; 3],
+    // Points in front of this face and no earlier one.
+    outside: Vec<usize>,
+    alive: bool,
+}
+
+fn add_face(
+    faces: &mut Vec<Face>,
+    edges: &mut HashMap<(usize, usize), usize>,
+    corners: [usize; 3],
+) {
+    for i in 0..3 {
+        edges.insert((corners[i], corners[(i + 1) % 3]), faces.len());
+    }
+    faces.push(Face {
+        corners,
+        outside: Vec::new(),
+        alive: true,
+    });
+}
+
+// Hands each point to the first of the faces it's in front of. Points
+// behind all of them are inside the hull and dropped.
+fn assign_outside(
+    points: &[[i64; 3]],
+    faces: &mut [Face],
+    candidates: ::std::ops::Range<usize>,
+    orphans: Vec<usize>,
+) {
+    for point in orphans {
+        if let Some(face) = candidates
+            .clone()
+            .find(|&face| orient(points, faces[face].corners, point) > 0)
+        {
+            faces[face].outside.push(point);
+        }
+    }
+}
+
+// Positive if the point is in front of the triangle (on the side it faces
+// when counter-clockwise), zero if in its plane.
+fn orient(points: &[[i64; 3]], [a, b, c]: [usize; 3], point: usize) -> i128 {
+    let a = points[a];
+    determinant(
+        difference(points[b], a),
+        difference(points[c], a),
+        difference(points[point], a),
+    )
+}
+
+// In 128 bits, as products of three fixed point coordinates overflow 64.
+fn determinant(a: [i64; 3], b: [i64; 3], c: [i64; 3]) -> i128 {
+    let [x, y, z] = cross(a, b);
+    x * c[0] as i128 + y * c[1] as i128 + z * c[2] as i128
+}
+
+fn cross(a: [i64; 3], b: [i64; 3]) -> [i128; 3] {
+    let a = a.map(|value| value as i128);
+    let b = b.map(|value| value as i128);
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn difference(a: [i64; 3], b: [i64; 3]) -> [i64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn length_squared([x, y, z]: [i128; 3]) -> i128 {
+    x * x + y * y + z * z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mass::enclosed_volume;
+    use test_meshes::sphere;
+    use validate::validate;
+
+    fn assert_valid_solid(positions: &[[f32; 3]], indices: &[usize]) {
+        assert!(validate(positions, indices).is_valid_solid());
+        // Wound counter-clockwise from outside.
+        assert!(enclosed_volume(positions, indices) > 0.0);
+    }
+
+    #[test]
+    fn trimesh_of_a_sphere() {
+        let (positions, indices) = sphere();
+        let (simple_positions, simple_indices) =
+            collision_trimesh(&positions, &indices, 0.1).unwrap();
+        assert!(simple_indices.len() < indices.len());
+        assert_valid_solid(&simple_positions, &simple_indices);
+        let volume = enclosed_volume(&positions, &indices);
+        assert!(
+            (enclosed_volume(&simple_positions, &simple_indices) - volume).abs() < volume * 0.05
+        );
+
+        assert_eq!(collision_trimesh(&positions, &indices[1..], 0.1), None);
+        assert_eq!(collision_trimesh(&positions[1..], &indices, 0.1), None);
+    }
+
+    #[test]
+    fn heightfield_of_a_slope() {
+        let mut grid = Grid::new([6, 8, 5], [-1.0, -2.0, 3.0], [0.5, 1.0, 0.5]);
+        let ground = |x: f32, z: f32| 0.8 - 0.5 * x + 0.25 * (z - 3.0);
+        for z in 0..5 {
+            for y in 0..8 {
+                for x in 0..6 {
+                    let [px, py, pz] = grid.position(x, y, z);
+                    grid.set(x, y, z, py - ground(px, pz));
+                }
+            }
+        }
+        // A column of nothing but air, and one with a ledge above the ground.
+        for y in 0..8 {
+            grid.set(0, y, 0, 1.0);
+        }
+        grid.set(2, 7, 3, -0.5);
+
+        let heightfield = grid_heightfield(&grid);
+        assert_eq!((heightfield.width, heightfield.depth), (6, 5));
+        assert_eq!(heightfield.origin, [-1.0, 3.0]);
+        assert_eq!(heightfield.spacing, [0.5, 0.5]);
+        assert_eq!(heightfield.height(0, 0), -2.0);
+        // Interpolated between the background above the grid and the ledge.
+        let ledge = 6.0 - grid.background / (grid.background + 0.5);
+        assert!((heightfield.height(2, 3) - ledge).abs() < 1e-5);
+        for row in 0..5 {
+            for column in 0..6 {
+                if (column, row) == (0, 0) || (column, row) == (2, 3) {
+                    continue;
+                }
+                let [x, _, z] = grid.position(column, 0, row);
+                assert!((heightfield.height(column, row) - ground(x, z)).abs() < 1e-5);
+            }
+        }
+        // The highest point of the slope.
+        assert!((heightfield.height(0, 4) - 1.8).abs() < 1e-5);
+    }
+
+    // An L of solid samples, one arm along X and one along Y.
+    fn l_shape() -> Grid {
+        let mut grid = Grid::new([12, 12, 6], [0.0; 3], [1.0; 3]);
+        for z in 0..6 {
+            for y in 0..12 {
+                for x in 0..12 {
+                    let along_x = (2..=9).contains(&x) && (2..=4).contains(&y);
+                    let along_y = (2..=4).contains(&x) && (2..=9).contains(&y);
+                    let solid = (along_x || along_y) && (1..=4).contains(&z);
+                    grid.set(x, y, z, if solid { -0.5 } else { 0.5 });
+                }
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn l_shape_splits_in_two() {
+        let grid = l_shape();
+        let hulls = convex_decomposition(&grid, &ConvexDecomposition::new(8));
+        assert_eq!(hulls.len(), 2);
+        let mut volume = 0.0;
+        for hull in &hulls {
+            assert_valid_solid(&hull.positions, &hull.indices);
+            volume += enclosed_volume(&hull.positions, &hull.indices);
+        }
+        // The arms' boxes reach halfway to the air around them, less the
+        // corners the hulls cut off between the crossings.
+        let boxes = (8.0 * 3.0 + 3.0 * 5.0) * 4.0;
+        assert!(volume < boxes && volume > boxes * 0.9);
+
+        // With just one allowed, the whole L goes in one hull.
+        let hulls = convex_decomposition(&grid, &ConvexDecomposition::new(1));
+        assert_eq!(hulls.len(), 1);
+        assert_valid_solid(&hulls[0].positions, &hulls[0].indices);
+        let volume = enclosed_volume(&hulls[0].positions, &hulls[0].indices);
+        assert!(volume > boxes + 1.0);
+    }
+
+    #[test]
+    fn separate_pieces_get_separate_hulls() {
+        let mut grid = Grid::new([10, 4, 4], [0.0; 3], [1.0; 3]);
+        for z in 0..4 {
+            for y in 0..4 {
+                for x in 0..10 {
+                    let solid = (1..=2).contains(&y) && (1..=2).contains(&z) && x != 5;
+                    grid.set(x, y, z, if solid { -0.5 } else { 0.5 });
+                }
+            }
+        }
+        let hulls = convex_decomposition(&grid, &ConvexDecomposition::new(1));
+        assert_eq!(hulls.len(), 2);
+        for hull in &hulls {
+            assert_valid_solid(&hull.positions, &hull.indices);
+        }
+        let empty = Grid::new([3; 3], [0.0; 3], [1.0; 3]);
+        assert!(convex_decomposition(&empty, &ConvexDecomposition::new(4)).is_empty());
+    }
+
+    // Points scattered through a cube, the same every run.
+    fn points(count: usize, size: i64) -> Vec<[i64; 3]> {
+        let mut state = 54321u64;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as i64 % size
+        };
+        (0..count).map(|_| [next(), next(), next()]).collect()
+    }
+
+    fn hull_mesh(points: &[[i64; 3]], hull: &[[usize; 3]]) -> (Vec<[f32; 3]>, Vec<usize>) {
+        let positions = points
+            .iter()
+            .map(|point| point.map(|value| value as f32))
+            .collect();
+        let indices = hull.iter().flat_map(|face| face.iter().cloned()).collect();
+        (positions, indices)
+    }
+
+    #[test]
+    fn quickhull_contains_every_point() {
+        for &(count, size) in &[(4, 10), (50, 1000), (500, 8), (2000, 1 << 40)] {
+            let points = points(count, size);
+            let hull = convex_hull(&points);
+            assert!(!hull.is_empty());
+            for &face in &hull {
+                for point in 0..points.len() {
+                    assert!(orient(&points, face, point) <= 0);
+                }
+            }
+            let (positions, indices) = hull_mesh(&points, &hull);
+            assert!(validate(&positions, &indices).is_closed());
+            assert!(validate(&positions, &indices).inconsistent_edges.is_empty());
+            assert!(enclosed_volume(&positions, &indices) > 0.0);
+        }
+    }
+
+    #[test]
+    fn quickhull_of_a_cube() {
+        // The corners, and points on the faces, edges and inside, all of
+        // which have to be left out.
+        let mut points = Vec::new();
+        for z in 0..5 {
+            for y in 0..5 {
+                for x in 0..5 {
+                    points.push([x * 2, y * 2, z * 2]);
+                }
+            }
+        }
+        let hull = convex_hull(&points);
+        assert_eq!(hull.len(), 12);
+        let (positions, indices) = hull_mesh(&points, &hull);
+        assert_valid_solid(&positions, &indices);
+        assert_eq!(enclosed_volume(&positions, &indices), 512.0);
+        for &index in &indices {
+            assert!(positions[index]
+                .iter()
+                .all(|&value| value == 0.0 || value == 8.0));
+        }
+    }
+
+    #[test]
+    fn quickhull_of_flat_points() {
+        assert!(convex_hull(&[]).is_empty());
+        assert!(convex_hull(&[[1, 2, 3]]).is_empty());
+        assert!(convex_hull(&[[0, 0, 0], [1, 1, 1], [2, 2, 2], [5, 5, 5]]).is_empty());
+        let plane = points(100, 50)
+            .into_iter()
+            .map(|[x, y, _]| [x, y, x - y])
+            .collect::<Vec<_>>();
+        assert!(convex_hull(&plane).is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+// Splitting meshes into their connected pieces, for culling the floating
+// debris noise fields tend to produce, or exporting pieces separately.
+
+use math::{signed_volume, triangle, triangle_area};
+use union_find::{find, union};
+
+pub struct Component {
+    // Indices of the triangles (not of the vertices) that make up the piece.
+    pub triangles: Vec<usize>,
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub area: f32,
+    // Enclosed volume. Only meaningful for closed pieces, which surface_net's
+    // are unless they run off the edge of the grid.
+    pub volume: f32,
+}
+
+// Pieces of the mesh that are connected through shared vertices, in the
+// order of their first triangle.
+pub fn connected_components(positions: &[[f32; 3]], indices: &[usize]) -> Vec<Component> {
+    let mut parents = (0..positions.len()).collect::<Vec<_>>();
+    for corners in indices.chunks(3) {
+        union(&mut parents, corners[0], corners[1]);
+        union(&mut parents, corners[0], corners[2]);
+    }
+    let mut component_of_root = vec![usize::MAX; positions.len()];
+    let mut components = Vec::<Component>::new();
+    for (index, corners) in indices.chunks(3).enumerate() {
+        let root = find(&mut parents, corners[0]);
+        if component_of_root[root] == usize::MAX {
+            component_of_root[root] = components.len();
+            components.push(Component {
+                triangles: Vec::new(),
+                min: positions[corners[0]],
+                max: positions[corners[0]],
+                area: 0.0,
+                volume: 0.0,
+            });
+        }
+        let component = &mut components[component_of_root[root]];
+        let points = triangle(positions, corners);
+        component.triangles.push(index);
+        component.area += triangle_area(points);
+        component.volume += signed_volume(points);
+        for point in &points {
+            for (axis, &value) in point.iter().enumerate() {
+                component.min[axis] = component.min[axis].min(value);
+                component.max[axis] = component.max[axis].max(value);
+            }
+        }
+    }
+    components
+}
+
+// A single piece as a mesh of its own, with only the vertices it uses.
+pub fn component_mesh(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    indices: &[usize],
+    component: &Component,
+) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>) {
+    extract(positions, normals, indices, &component.triangles)
+}
+
+// Keeps only the pieces keep returns true for, e.g.
+// `|component| component.volume > 10.0` to drop small islands, and returns
+// the resulting mesh. Vertices only used by dropped pieces are removed and
+// the indices remapped.
+pub fn retain_components<F: Fn(&Component) -> bool>(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    indices: &[usize],
+    keep: F,
+) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>) {
+    let mut triangles = connected_components(positions, indices)
+        .into_iter()
+        .filter(|component| keep(component))
+        .flat_map(|component| component.triangles)
+        .collect::<Vec<_>>();
+    // Keep the triangles in their original order.
+    triangles.sort_unstable();
+    extract(positions, normals, indices, &triangles)
+}
+
+fn extract(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    indices: &[usize],
+    triangles: &[usize],
+) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>) {
+    let mut new_index = vec![usize::MAX; positions.len()];
+    let mut new_positions = Vec::new();
+    let mut new_normals = Vec::new();
+    let mut new_indices = Vec::with_capacity(triangles.len() * 3);
+    for &triangle in triangles {
+        for &corner in &indices[triangle * 3..triangle * 3 + 3] {
+            if new_index[corner] == usize::MAX {
+                new_index[corner] = new_positions.len();
+                new_positions.push(positions[corner]);
+                new_normals.push(normals[corner]);
+            }
+            new_indices.push(new_index[corner]);
+        }
+    }
+    (new_positions, new_normals, new_indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use surface_net;
+    use validate::validate;
+
+    // A small sphere of radius 2.5 around (5, 8, 8) and a big one of radius
+    // 5 around (16, 8, 8). The small one comes first in the mesh.
+    fn two_spheres() -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>) {
+        surface_net(
+            24,
+            &|x, y, z| {
+                let distance = |center: f32, radius: f32| {
+                    let (x, y, z) = (x as f32 - center, y as f32 - 8.0, z as f32 - 8.0);
+                    (x * x + y * y + z * z).sqrt() - radius
+                };
+                distance(5.0, 2.5).min(distance(16.0, 5.0))
+            },
+            false,
+        )
+    }
+
+    #[test]
+    fn two_spheres_are_two_components() {
+        let (positions, normals, indices) = two_spheres();
+        let components = connected_components(&positions, &indices);
+        assert_eq!(components.len(), 2);
+        let total = components
+            .iter()
+            .map(|component| component.triangles.len())
+            .sum::<usize>();
+        assert_eq!(total, indices.len() / 3);
+        for (component, &(center, radius)) in components.iter().zip(&[(5.0, 2.5), (16.0, 5.0)]) {
+            let expected_min = [center - radius, 8.0 - radius, 8.0 - radius];
+            let expected_max = [center + radius, 8.0 + radius, 8.0 + radius];
+            for axis in 0..3 {
+                assert!((component.min[axis] - expected_min[axis]).abs() < 0.3);
+                assert!((component.max[axis] - expected_max[axis]).abs() < 0.3);
+            }
+            // Surface nets cuts a little off a sphere, up to about a quarter
+            // of a cell, and relatively more off a small one.
+            let inset = 0.25 / radius;
+            let area = 4.0 * std::f32::consts::PI * radius * radius;
+            let volume = area * radius / 3.0;
+            assert!((component.area / area - 1.0).abs() < 2.0 * inset);
+            assert!((component.volume / volume - 1.0).abs() < 3.0 * inset);
+
+            let (piece_positions, piece_normals, piece_indices) =
+                component_mesh(&positions, &normals, &indices, component);
+            assert_eq!(piece_normals.len(), piece_positions.len());
+            assert_eq!(piece_indices.len(), component.triangles.len() * 3);
+            let report = validate(&piece_positions, &piece_indices);
+            assert!(report.is_valid_solid());
+            assert_eq!(report.components, 1);
+            // Every vertex is used.
+            assert_eq!(report.vertex_count, piece_positions.len());
+        }
+    }
+
+    #[test]
+    fn dropping_the_small_sphere_remaps_the_rest() {
+        let (positions, normals, indices) = two_spheres();
+        let components = connected_components(&positions, &indices);
+        let (kept_positions, kept_normals, kept_indices) =
+            retain_components(&positions, &normals, &indices, |component| {
+                component.volume > 100.0
+            });
+        let big = &components[1];
+        assert_eq!(kept_indices.len(), big.triangles.len() * 3);
+        assert!(kept_positions.len() < positions.len());
+        // Triangle by triangle, the same corners as before.
+        for (&triangle, corners) in big.triangles.iter().zip(kept_indices.chunks(3)) {
+            for (&old, &new) in indices[triangle * 3..triangle * 3 + 3].iter().zip(corners) {
+                assert_eq!(kept_positions[new], positions[old]);
+                assert_eq!(kept_normals[new], normals[old]);
+            }
+        }
+        assert_eq!(
+            (kept_positions, kept_normals, kept_indices),
+            component_mesh(&positions, &normals, &indices, big)
+        );
+
+        let (none, _, no_indices) = retain_components(&positions, &normals, &indices, |_| false);
+        assert!(none.is_empty() && no_indices.is_empty());
+    }
+}
@@ -0,0 +1,555 @@
+// Mesh simplification by edge collapse with quadric error metrics, from
+// Garland and Heckbert's "Surface Simplification Using Quadric Error Metrics".
+//
+// Every vertex keeps a quadric measuring the squared distance to the planes
+// of the original triangles around it, and the edge whose collapse adds the
+// least error goes first. Flat areas, which surface nets fills with as many
+// triangles as anywhere else, collapse almost for free.
+//
+// Vertices on boundary edges never move, so the edges along which separately
+// meshed chunks meet stay exactly as they were. The same goes for vertices
+// between triangles of different materials, when materials are given.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use math::{add, cross, dot, normalize, scale, sub};
+
+pub struct Decimation<'a> {
+    // Stop once there are this many triangles or fewer.
+    pub target_triangles: usize,
+    // Stop once the cheapest collapse would move the surface further than
+    // this (measured as the square root of the quadric error).
+    pub max_error: f32,
+    // Material of each input triangle. Edges between materials are kept.
+    pub triangle_materials: Option<&'a [u32]>,
+}
+
+impl<'a> Decimation<'a> {
+    pub fn new(target_triangles: usize) -> Decimation<'a> {
+        Decimation {
+            target_triangles,
+            max_error: f32::INFINITY,
+            triangle_materials: None,
+        }
+    }
+}
+
+// Returns (positions, normals, indices, triangles), where triangles[i] is the
+// input triangle that output triangle i came from, for carrying over
+// per-triangle data like materials.
+// None unless there's one normal per vertex and one material (if any) per
+// triangle, and the indices are triangles of the vertices.
+#[allow(clippy::type_complexity)]
+pub fn decimate(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    indices: &[usize],
+    decimation: &Decimation,
+) -> Option<(Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>, Vec<usize>)> {
+    if normals.len() != positions.len()
+        || !indices.len().is_multiple_of(3)
+        || indices.iter().any(|&index| index >= positions.len())
+        || decimation
+            .triangle_materials
+            .is_some_and(|materials| materials.len() != indices.len() / 3)
+    {
+        return None;
+    }
+    let mut mesh = Mesh::new(positions, normals, indices, decimation.triangle_materials);
+    let mut heap = BinaryHeap::new();
+    for vertex in 0..positions.len() {
+        for neighbour in mesh.neighbours(vertex) {
+            if vertex < neighbour {
+                mesh.push_candidate(&mut heap, vertex, neighbour);
+            }
+        }
+    }
+    let max_cost = decimation.max_error as f64 * decimation.max_error as f64;
+    let mut live_triangles = indices.len() / 3;
+    while live_triangles > decimation.target_triangles {
+        let candidate = match heap.pop() {
+            Some(candidate) => candidate,
+            None => break,
+        };
+        if candidate.cost > max_cost {
+            break;
+        }
+        let (a, b) = (candidate.keep, candidate.remove);
+        if mesh.removed[a]
+            || mesh.removed[b]
+            || mesh.versions[a] != candidate.versions[0]
+            || mesh.versions[b] != candidate.versions[1]
+            || !mesh.can_collapse(a, b, candidate.position)
+        {
+            continue;
+        }
+        live_triangles -= mesh.collapse(a, b, candidate.position);
+        for neighbour in mesh.neighbours(a) {
+            mesh.push_candidate(&mut heap, a, neighbour);
+        }
+    }
+    Some(mesh.compact())
+}
+
+// Symmetric 4x4 matrix, upper triangle row by row.
+#[derive(Clone, Copy)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn plane(normal: [f32; 3], point: [f32; 3]) -> Quadric {
+        let [a, b, c] = [normal[0] as f64, normal[1] as f64, normal[2] as f64];
+        let d = -dot(normal, point) as f64;
+        Quadric([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn add(self, other: Quadric) -> Quadric {
+        let mut result = self.0;
+        for (value, other) in result.iter_mut().zip(&other.0) {
+            *value += other;
+        }
+        Quadric(result)
+    }
+
+    fn error(&self, point: [f32; 3]) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = [point[0] as f64, point[1] as f64, point[2] as f64];
+        (q[0] * x * x + q[4] * y * y + q[7] * z * z + q[9])
+            + 2.0 * (q[1] * x * y + q[2] * x * z + q[5] * y * z)
+            + 2.0 * (q[3] * x + q[6] * y + q[8] * z)
+    }
+
+    // The point of least error, if it's well defined (it isn't on flat or
+    // cylindrical areas, where a whole plane or line has the same error).
+    fn minimum(&self) -> Option<[f32; 3]> {
+        let q = &self.0;
+        let m = [[q[0], q[1], q[2]], [q[1], q[4], q[5]], [q[2], q[5], q[7]]];
+        let rhs = [-q[3], -q[6], -q[8]];
+        let determinant = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        if determinant.abs() < 1e-9 {
+            return None;
+        }
+        // Cramer's rule.
+        let mut result = [0.0; 3];
+        for (column, value) in result.iter_mut().enumerate() {
+            let mut replaced = m;
+            for row in 0..3 {
+                replaced[row][column] = rhs[row];
+            }
+            let r = replaced;
+            *value = ((r[0][0] * (r[1][1] * r[2][2] - r[1][2] * r[2][1])
+                - r[0][1] * (r[1][0] * r[2][2] - r[1][2] * r[2][0])
+                + r[0][2] * (r[1][0] * r[2][1] - r[1][1] * r[2][0]))
+                / determinant) as f32;
+        }
+        Some(result)
+    }
+}
+
+struct Candidate {
+    cost: f64,
+    keep: usize,
+    remove: usize,
+    position: [f32; 3],
+    // Versions of keep and remove when this was computed. If either has
+    // changed since, the candidate is stale.
+    versions: [usize; 2],
+}
+
+// Ordered so that BinaryHeap (a max-heap) pops the cheapest collapse first.
+impl Ord for Candidate {
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+struct Mesh {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    quadrics: Vec<Quadric>,
+    // Locked vertices are never moved or removed.
+    locked: Vec<bool>,
+    removed: Vec<bool>,
+    versions: Vec<usize>,
+    triangles: Vec<[usize; 3]>,
+    live: Vec<bool>,
+    // Triangles around each vertex, possibly including dead ones.
+    vertex_triangles: Vec<Vec<usize>>,
+}
+
+impl Mesh {
+    fn new(
+        positions: &[[f32; 3]],
+        normals: &[[f32; 3]],
+        indices: &[usize],
+        materials: Option<&[u32]>,
+    ) -> Mesh {
+        let triangles = indices
+            .chunks(3)
+            .map(|corners| [corners[0], corners[1], corners[2]])
+            .collect::<Vec<_>>();
+        let mut quadrics = vec![Quadric([0.0; 10]); positions.len()];
+        let mut vertex_triangles = vec![Vec::new(); positions.len()];
+        let mut edges = HashMap::new();
+        for (index, triangle) in triangles.iter().enumerate() {
+            let [a, b, c] = [
+                positions[triangle[0]],
+                positions[triangle[1]],
+                positions[triangle[2]],
+            ];
+            let plane = Quadric::plane(normalize(cross(sub(b, a), sub(c, a))), a);
+            for i in 0..3 {
+                let vertex = triangle[i];
+                quadrics[vertex] = quadrics[vertex].add(plane);
+                vertex_triangles[vertex].push(index);
+                let next = triangle[(i + 1) % 3];
+                *edges
+                    .entry((vertex.min(next), vertex.max(next)))
+                    .or_insert(0) += 1;
+            }
+        }
+        let mut locked = vec![false; positions.len()];
+        for (&(a, b), &count) in &edges {
+            if count != 2 {
+                locked[a] = true;
+                locked[b] = true;
+            }
+        }
+        if let Some(materials) = materials {
+            for (vertex, around) in vertex_triangles.iter().enumerate() {
+                if let Some(&first) = around.first() {
+                    let material = materials[first];
+                    if around
+                        .iter()
+                        .any(|&triangle| materials[triangle] != material)
+                    {
+                        locked[vertex] = true;
+                    }
+                }
+            }
+        }
+        Mesh {
+            positions: positions.to_vec(),
+            normals: normals.to_vec(),
+            quadrics,
+            locked,
+            removed: vec![false; positions.len()],
+            versions: vec![0; positions.len()],
+            live: vec![true; triangles.len()],
+            triangles,
+            vertex_triangles,
+        }
+    }
+
+    fn live_triangles<'a>(&'a self, vertex: usize) -> impl Iterator<Item = usize> + 'a {
+        self.vertex_triangles[vertex]
+            .iter()
+            .cloned()
+            .filter(move |&triangle| self.live[triangle])
+    }
+
+    fn neighbours(&self, vertex: usize) -> Vec<usize> {
+        let mut result = self
+            .live_triangles(vertex)
+            .flat_map(|triangle| self.triangles[triangle].to_vec())
+            .filter(|&other| other != vertex)
+            .collect::<Vec<_>>();
+        result.sort_unstable();
+        result.dedup();
+        result
+    }
+
+    fn push_candidate(&self, heap: &mut BinaryHeap<Candidate>, a: usize, b: usize) {
+        // Removing a locked vertex would move it, so it has to be the one
+        // that's kept.
+        let (keep, remove) = match (self.locked[a], self.locked[b]) {
+            (true, true) => return,
+            (false, true) => (b, a),
+            _ => (a, b),
+        };
+        let quadric = self.quadrics[keep].add(self.quadrics[remove]);
+        let position = if self.locked[keep] {
+            self.positions[keep]
+        } else {
+            let (p, q) = (self.positions[keep], self.positions[remove]);
+            let midpoint = scale(add(p, q), 0.5);
+            let mut options = vec![p, q, midpoint];
+            // The optimum can be far away for nearly degenerate quadrics, so
+            // it's only used when it's near the edge.
+            if let Some(minimum) = quadric.minimum() {
+                let edge = sub(q, p);
+                let reach = dot(edge, edge);
+                if dot(sub(minimum, midpoint), sub(minimum, midpoint)) <= reach {
+                    options.push(minimum);
+                }
+            }
+            options
+                .into_iter()
+                .min_by(|&x, &y| {
+                    quadric
+                        .error(x)
+                        .partial_cmp(&quadric.error(y))
+                        .unwrap_or(Ordering::Equal)
+                })
+                .unwrap()
+        };
+        // Flat areas cost nothing at all, and without a tie breaker one vertex
+        // ends up swallowing everything around it. A tiny bit of edge length
+        // makes short edges go first, which keeps triangles evenly shaped.
+        let edge = sub(self.positions[keep], self.positions[remove]);
+        let cost = quadric.error(position).max(0.0) + 1e-6 * dot(edge, edge) as f64;
+        heap.push(Candidate {
+            cost,
+            keep,
+            remove,
+            position,
+            versions: [self.versions[keep], self.versions[remove]],
+        });
+    }
+
+    fn can_collapse(&self, keep: usize, remove: usize, position: [f32; 3]) -> bool {
+        // Link condition: an interior edge has exactly two triangles, so its
+        // endpoints must share exactly two neighbours. Anything else would
+        // pinch the surface into something non-manifold.
+        let keep_neighbours = self.neighbours(keep);
+        let shared = self
+            .neighbours(remove)
+            .iter()
+            .filter(|neighbour| keep_neighbours.binary_search(neighbour).is_ok())
+            .count();
+        if shared != 2 {
+            return false;
+        }
+        // No triangle that survives may flip over or collapse to nothing.
+        for &vertex in &[keep, remove] {
+            for triangle in self.live_triangles(vertex) {
+                let corners = self.triangles[triangle];
+                if corners.contains(&keep) && corners.contains(&remove) {
+                    continue;
+                }
+                let old = corners.map(|corner| self.positions[corner]);
+                let new = corners.map(|corner| {
+                    if corner == vertex {
+                        position
+                    } else {
+                        self.positions[corner]
+                    }
+                });
+                let old_normal = cross(sub(old[1], old[0]), sub(old[2], old[0]));
+                let new_normal = cross(sub(new[1], new[0]), sub(new[2], new[0]));
+                if dot(old_normal, new_normal) <= 1e-3 * dot(old_normal, old_normal) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    // Merges remove into keep, and returns how many triangles that removed.
+    fn collapse(&mut self, keep: usize, remove: usize, position: [f32; 3]) -> usize {
+        let mut removed_triangles = 0;
+        let around = self.live_triangles(remove).collect::<Vec<_>>();
+        for triangle in around {
+            let corners = &mut self.triangles[triangle];
+            if corners.contains(&keep) {
+                self.live[triangle] = false;
+                removed_triangles += 1;
+            } else {
+                for corner in corners.iter_mut() {
+                    if *corner == remove {
+                        *corner = keep;
+                    }
+                }
+                self.vertex_triangles[keep].push(triangle);
+            }
+        }
+        let live = &self.live;
+        self.vertex_triangles[keep].retain(|&triangle| live[triangle]);
+        self.vertex_triangles[remove].clear();
+
+        // Interpolate the normal by how far along the edge the new position
+        // ended up.
+        let (p, q) = (self.positions[keep], self.positions[remove]);
+        let edge = sub(q, p);
+        let t = if dot(edge, edge) > 0.0 {
+            (dot(sub(position, p), edge) / dot(edge, edge)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        self.normals[keep] = normalize(add(
+            scale(self.normals[keep], 1.0 - t),
+            scale(self.normals[remove], t),
+        ));
+        self.positions[keep] = position;
+        self.quadrics[keep] = self.quadrics[keep].add(self.quadrics[remove]);
+        self.versions[keep] += 1;
+        self.removed[remove] = true;
+        removed_triangles
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn compact(self) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>, Vec<usize>) {
+        let mut new_index = vec![usize::MAX; self.positions.len()];
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut indices = Vec::new();
+        let mut kept = Vec::new();
+        for (triangle, corners) in self.triangles.iter().enumerate() {
+            if !self.live[triangle] {
+                continue;
+            }
+            for &corner in corners {
+                if new_index[corner] == usize::MAX {
+                    new_index[corner] = positions.len();
+                    positions.push(self.positions[corner]);
+                    normals.push(self.normals[corner]);
+                }
+                indices.push(new_index[corner]);
+            }
+            kept.push(triangle);
+        }
+        (positions, normals, indices, kept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::{closest_point_on_triangle, length, triangle};
+    use smooth::vertex_normals;
+    use test_meshes::sphere;
+    use validate::validate;
+
+    // Distance from the point to the nearest of the mesh's triangles.
+    fn distance(point: [f32; 3], positions: &[[f32; 3]], indices: &[usize]) -> f32 {
+        indices
+            .chunks(3)
+            .map(|corners| {
+                let closest = closest_point_on_triangle(point, triangle(positions, corners));
+                length(sub(point, closest))
+            })
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    #[test]
+    fn closed_sphere_stays_a_valid_solid() {
+        let (positions, indices) = sphere();
+        let normals = vertex_normals(&positions, &indices);
+        let target = indices.len() / 3 / 4;
+        let (new_positions, new_normals, new_indices, triangles) =
+            decimate(&positions, &normals, &indices, &Decimation::new(target)).unwrap();
+        assert!(new_indices.len() / 3 <= target);
+        assert_eq!(triangles.len(), new_indices.len() / 3);
+        assert_eq!(new_normals.len(), new_positions.len());
+        let report = validate(&new_positions, &new_indices);
+        assert!(report.is_valid_solid(), "{:?}", report);
+        assert_eq!(report.genus, Some(0));
+    }
+
+    #[test]
+    fn error_bound_is_kept() {
+        let (positions, indices) = sphere();
+        let normals = vertex_normals(&positions, &indices);
+        let mut decimation = Decimation::new(0);
+        decimation.max_error = 0.05;
+        let (new_positions, _, new_indices, _) =
+            decimate(&positions, &normals, &indices, &decimation).unwrap();
+        // It got somewhere, but not down to nothing.
+        assert!(new_indices.len() < indices.len());
+        assert!(new_indices.len() > indices.len() / 20);
+        assert!(validate(&new_positions, &new_indices).is_valid_solid());
+        for &position in &new_positions {
+            assert!(distance(position, &positions, &indices) <= 0.05 + 1e-4);
+        }
+    }
+
+    #[test]
+    fn boundary_and_material_vertices_are_locked() {
+        // The sphere with its top cut off, and two materials on the left and
+        // right halves.
+        let (positions, indices) = sphere();
+        let indices = indices
+            .chunks(3)
+            .filter(|corners| corners.iter().any(|&corner| positions[corner][1] < 11.0))
+            .flat_map(|corners| corners.to_vec())
+            .collect::<Vec<_>>();
+        let materials = indices
+            .chunks(3)
+            .map(|corners| (positions[corners[0]][0] < 8.0) as u32)
+            .collect::<Vec<_>>();
+        let mut locked = validate(&positions, &indices).boundary_loops.concat();
+        assert!(!locked.is_empty());
+        let mut vertex_materials = vec![Vec::new(); positions.len()];
+        for (corners, &material) in indices.chunks(3).zip(&materials) {
+            for &corner in corners {
+                vertex_materials[corner].push(material);
+            }
+        }
+        locked.extend((0..positions.len()).filter(|&vertex| {
+            vertex_materials[vertex]
+                .windows(2)
+                .any(|pair| pair[0] != pair[1])
+        }));
+        let normals = vertex_normals(&positions, &indices);
+        let mut decimation = Decimation::new(0);
+        decimation.triangle_materials = Some(&materials);
+        let (new_positions, _, new_indices, triangles) =
+            decimate(&positions, &normals, &indices, &decimation).unwrap();
+        assert!(new_indices.len() < indices.len() / 4);
+        for vertex in locked {
+            assert!(new_positions.contains(&positions[vertex]));
+        }
+        // Both materials are still there, and the hole is still one hole.
+        let new_materials = triangles.iter().map(|&t| materials[t]).collect::<Vec<_>>();
+        assert!(new_materials.contains(&0) && new_materials.contains(&1));
+        let report = validate(&new_positions, &new_indices);
+        assert!(report.is_manifold() && report.inconsistent_edges.is_empty());
+        assert_eq!(report.boundary_loops.len(), 1);
+    }
+
+    #[test]
+    fn refuses_inconsistent_meshes() {
+        let (positions, indices) = sphere();
+        let normals = vertex_normals(&positions, &indices);
+        let decimation = Decimation::new(0);
+        assert!(decimate(&positions, &normals[1..], &indices, &decimation).is_none());
+        assert!(decimate(&positions, &normals, &indices[1..], &decimation).is_none());
+        let mut out_of_range = indices.clone();
+        out_of_range[4] = positions.len();
+        assert!(decimate(&positions, &normals, &out_of_range, &decimation).is_none());
+        let materials = vec![0; indices.len() / 3 - 1];
+        let mut with_materials = Decimation::new(0);
+        with_materials.triangle_materials = Some(&materials);
+        assert!(decimate(&positions, &normals, &indices, &with_materials).is_none());
+    }
+}
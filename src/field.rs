@@ -0,0 +1,42 @@
+// Continuous fields, as opposed to the integer-grid SDF closures that
+// surface_net takes. Same sign convention: positive is "air", negative is
+// "solid".
+
+pub trait Field {
+    fn get(&self, point: [f32; 3]) -> f32;
+}
+
+impl<F: Fn([f32; 3]) -> f32> Field for F {
+    fn get(&self, point: [f32; 3]) -> f32 {
+        self(point)
+    }
+}
+
+// Adapts a continuous field to the grid closure surface_net wants. Grid point
+// (x, y, z) is sampled at origin + (x, y, z) * spacing, so a resolution of N
+// covers the cube from origin to origin + N * spacing.
+// Note that surface_net's output positions are still in grid units, use
+// grid_to_world to map them back.
+pub fn grid_sampler<'a>(
+    field: &'a dyn Field,
+    origin: [f32; 3],
+    spacing: f32,
+) -> impl Fn(usize, usize, usize) -> f32 + 'a {
+    move |x, y, z| {
+        field.get([
+            origin[0] + x as f32 * spacing,
+            origin[1] + y as f32 * spacing,
+            origin[2] + z as f32 * spacing,
+        ])
+    }
+}
+
+// The inverse of grid_sampler's mapping, applied in place to surface_net's
+// vertex positions.
+pub fn grid_to_world(positions: &mut [[f32; 3]], origin: [f32; 3], spacing: f32) {
+    for position in positions {
+        for axis in 0..3 {
+            position[axis] = origin[axis] + position[axis] * spacing;
+        }
+    }
+}
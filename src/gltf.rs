@@ -0,0 +1,437 @@
+// glTF 2.0 binary (.glb) export, for getting meshes into game engines.
+// Every mesh becomes its own node in a single scene, so a world made of
+// several surface_net chunks can be written as one file.
+
+use std::io::{self, Write};
+
+pub struct GltfMesh<'a> {
+    pub name: &'a str,
+    // Where the node is placed in the scene.
+    pub translation: [f32; 3],
+    pub positions: &'a [[f32; 3]],
+    pub normals: &'a [[f32; 3]],
+    // xyz tangent plus w bitangent sign, as glTF wants them.
+    pub tangents: Option<&'a [[f32; 4]]>,
+    pub uvs: Option<&'a [[f32; 2]]>,
+    // Linear RGBA.
+    pub colors: Option<&'a [[f32; 4]]>,
+    pub indices: &'a [usize],
+}
+
+impl<'a> GltfMesh<'a> {
+    pub fn new(
+        positions: &'a [[f32; 3]],
+        normals: &'a [[f32; 3]],
+        indices: &'a [usize],
+    ) -> GltfMesh<'a> {
+        GltfMesh {
+            name: "surface-net",
+            translation: [0.0; 3],
+            positions,
+            normals,
+            tangents: None,
+            uvs: None,
+            colors: None,
+            indices,
+        }
+    }
+}
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+// Meshes with a missing normal, tangent, UV or color somewhere, values that
+// aren't finite, or indices that aren't triangles of their vertices, are
+// refused with an InvalidInput error before anything is written.
+// Meshes without triangles (like all-air chunks) are left out, since glTF
+// has no such thing as an empty mesh. If that leaves nothing to write, that's
+// an InvalidInput error too.
+pub fn write_glb<W: Write>(writer: &mut W, meshes: &[GltfMesh]) -> io::Result<()> {
+    for mesh in meshes {
+        check_mesh(mesh)?;
+    }
+    let meshes = meshes
+        .iter()
+        .filter(|mesh| !mesh.indices.is_empty())
+        .collect::<Vec<_>>();
+    if meshes.is_empty() {
+        return Err(invalid_input("no triangles to write"));
+    }
+    let mut binary = Vec::new();
+    let mut buffer_views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut json_meshes = Vec::new();
+    for mesh in &meshes {
+        let vertex_count = mesh.positions.len();
+        let mut attributes = Vec::new();
+
+        let (min, max) = bounds(mesh.positions);
+        let view = push_view(
+            &mut binary,
+            &mut buffer_views,
+            floats(mesh.positions),
+            ARRAY_BUFFER,
+        );
+        attributes.push(format!("\"POSITION\":{}", accessors.len()));
+        accessors.push(format!(
+            "{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"VEC3\",\"min\":[{},{},{}],\"max\":[{},{},{}]}}",
+            view, FLOAT, vertex_count, min[0], min[1], min[2], max[0], max[1], max[2]
+        ));
+
+        let view = push_view(
+            &mut binary,
+            &mut buffer_views,
+            floats(mesh.normals),
+            ARRAY_BUFFER,
+        );
+        attributes.push(format!("\"NORMAL\":{}", accessors.len()));
+        accessors.push(float_accessor(view, vertex_count, "VEC3"));
+
+        if let Some(tangents) = mesh.tangents {
+            let view = push_view(
+                &mut binary,
+                &mut buffer_views,
+                floats(tangents),
+                ARRAY_BUFFER,
+            );
+            attributes.push(format!("\"TANGENT\":{}", accessors.len()));
+            accessors.push(float_accessor(view, vertex_count, "VEC4"));
+        }
+        if let Some(uvs) = mesh.uvs {
+            let view = push_view(&mut binary, &mut buffer_views, floats(uvs), ARRAY_BUFFER);
+            attributes.push(format!("\"TEXCOORD_0\":{}", accessors.len()));
+            accessors.push(float_accessor(view, vertex_count, "VEC2"));
+        }
+        if let Some(colors) = mesh.colors {
+            let view = push_view(&mut binary, &mut buffer_views, floats(colors), ARRAY_BUFFER);
+            attributes.push(format!("\"COLOR_0\":{}", accessors.len()));
+            accessors.push(float_accessor(view, vertex_count, "VEC4"));
+        }
+
+        // 16-bit indices whenever they fit. The largest value of the index
+        // type is reserved (for primitive restart), hence the < rather than
+        // <=.
+        let (index_bytes, component_type) = if vertex_count < 0xFFFF {
+            let bytes = mesh
+                .indices
+                .iter()
+                .flat_map(|&index| (index as u16).to_le_bytes().to_vec())
+                .collect();
+            (bytes, UNSIGNED_SHORT)
+        } else {
+            let bytes = mesh
+                .indices
+                .iter()
+                .flat_map(|&index| (index as u32).to_le_bytes().to_vec())
+                .collect();
+            (bytes, UNSIGNED_INT)
+        };
+        let view = push_view(
+            &mut binary,
+            &mut buffer_views,
+            index_bytes,
+            ELEMENT_ARRAY_BUFFER,
+        );
+        let indices_accessor = accessors.len();
+        accessors.push(format!(
+            "{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"SCALAR\"}}",
+            view,
+            component_type,
+            mesh.indices.len()
+        ));
+
+        json_meshes.push(format!(
+            "{{\"name\":{},\"primitives\":[{{\"attributes\":{{{}}},\"indices\":{},\"mode\":4}}]}}",
+            json_string(mesh.name),
+            attributes.join(","),
+            indices_accessor
+        ));
+    }
+    let nodes = meshes
+        .iter()
+        .enumerate()
+        .map(|(index, mesh)| {
+            format!(
+                "{{\"name\":{},\"mesh\":{},\"translation\":[{},{},{}]}}",
+                json_string(mesh.name),
+                index,
+                mesh.translation[0],
+                mesh.translation[1],
+                mesh.translation[2]
+            )
+        })
+        .collect::<Vec<_>>();
+    let scene_nodes = (0..meshes.len())
+        .map(|index| index.to_string())
+        .collect::<Vec<_>>();
+    let mut json = format!(
+        "{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"surface-nets\"}},\"scene\":0,\"scenes\":[{{\"nodes\":[{}]}}],\"nodes\":[{}],\"meshes\":[{}],\"accessors\":[{}],\"bufferViews\":[{}],\"buffers\":[{{\"byteLength\":{}}}]}}",
+        scene_nodes.join(","),
+        nodes.join(","),
+        json_meshes.join(","),
+        accessors.join(","),
+        buffer_views.join(","),
+        binary.len()
+    )
+    .into_bytes();
+    // Both chunks must be 4-byte aligned: JSON is padded with spaces, the
+    // binary chunk with zeros.
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    while !binary.len().is_multiple_of(4) {
+        binary.push(0);
+    }
+    let total_length = 12 + 8 + json.len() + 8 + binary.len();
+    if total_length > u32::MAX as usize {
+        return Err(invalid_input("meshes don't fit in a 4 GB GLB file"));
+    }
+    writer.write_all(b"glTF")?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&(total_length as u32).to_le_bytes())?;
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(b"JSON")?;
+    writer.write_all(&json)?;
+    writer.write_all(&(binary.len() as u32).to_le_bytes())?;
+    writer.write_all(b"BIN\0")?;
+    writer.write_all(&binary)?;
+    Ok(())
+}
+
+fn check_mesh(mesh: &GltfMesh) -> io::Result<()> {
+    let vertex_count = mesh.positions.len();
+    if mesh.normals.len() != vertex_count {
+        return Err(invalid_input("mesh needs one normal per vertex"));
+    }
+    if mesh
+        .tangents
+        .is_some_and(|tangents| tangents.len() != vertex_count)
+    {
+        return Err(invalid_input("mesh needs one tangent per vertex"));
+    }
+    if mesh.uvs.is_some_and(|uvs| uvs.len() != vertex_count) {
+        return Err(invalid_input("mesh needs one UV per vertex"));
+    }
+    if mesh
+        .colors
+        .is_some_and(|colors| colors.len() != vertex_count)
+    {
+        return Err(invalid_input("mesh needs one color per vertex"));
+    }
+    if !mesh.indices.len().is_multiple_of(3)
+        || mesh.indices.iter().any(|&index| index >= vertex_count)
+    {
+        return Err(invalid_input(
+            "mesh indices are not triangles of its vertices",
+        ));
+    }
+    // The largest 32-bit index is reserved, see write_glb.
+    if vertex_count > u32::MAX as usize {
+        return Err(invalid_input(
+            "mesh has too many vertices for 32-bit indices",
+        ));
+    }
+    // JSON has no NaN or infinity for the bounds and translation, and
+    // engines don't want them in the buffers either.
+    let finite = |values: &[f32]| values.iter().all(|value| value.is_finite());
+    if !finite(&mesh.translation)
+        || !mesh.positions.iter().all(|value| finite(value))
+        || !mesh.normals.iter().all(|value| finite(value))
+        || !mesh
+            .tangents
+            .unwrap_or(&[])
+            .iter()
+            .all(|value| finite(value))
+        || !mesh.uvs.unwrap_or(&[]).iter().all(|value| finite(value))
+        || !mesh.colors.unwrap_or(&[]).iter().all(|value| finite(value))
+    {
+        return Err(invalid_input("mesh has values that aren't finite"));
+    }
+    Ok(())
+}
+
+fn floats<A: AsRef<[f32]>>(values: &[A]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.as_ref().iter().flat_map(|v| v.to_le_bytes().to_vec()))
+        .collect()
+}
+
+// Appends the data to the binary chunk as a new (4-byte aligned) buffer view,
+// and returns the view's index.
+fn push_view(binary: &mut Vec<u8>, views: &mut Vec<String>, data: Vec<u8>, target: u32) -> usize {
+    while !binary.len().is_multiple_of(4) {
+        binary.push(0);
+    }
+    views.push(format!(
+        "{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":{}}}",
+        binary.len(),
+        data.len(),
+        target
+    ));
+    binary.extend(data);
+    views.len() - 1
+}
+
+fn float_accessor(view: usize, count: usize, kind: &str) -> String {
+    format!(
+        "{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"{}\"}}",
+        view, FLOAT, count, kind
+    )
+}
+
+fn bounds(positions: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    let mut min = [0.0; 3];
+    let mut max = [0.0; 3];
+    if let Some(first) = positions.first() {
+        min = *first;
+        max = *first;
+    }
+    for position in positions {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(position[axis]);
+            max[axis] = max[axis].max(position[axis]);
+        }
+    }
+    (min, max)
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            ch if (ch as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.5, -2.0]];
+    const NORMALS: [[f32; 3]; 3] = [[0.0, 0.0, 1.0]; 3];
+    const UVS: [[f32; 2]; 3] = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]];
+    const INDICES: [usize; 3] = [0, 1, 2];
+
+    fn mesh() -> GltfMesh<'static> {
+        GltfMesh::new(&POSITIONS, &NORMALS, &INDICES)
+    }
+
+    fn uint(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ])
+    }
+
+    // Splits a GLB file into its JSON and binary chunks, checking the
+    // framing on the way.
+    fn chunks(output: &[u8]) -> (String, &[u8]) {
+        assert_eq!(&output[..4], b"glTF");
+        assert_eq!(uint(output, 4), 2);
+        assert_eq!(uint(output, 8) as usize, output.len());
+        let json_length = uint(output, 12) as usize;
+        assert_eq!(&output[16..20], b"JSON");
+        let json = String::from_utf8(output[20..20 + json_length].to_vec()).unwrap();
+        let binary_start = 20 + json_length;
+        let binary_length = uint(output, binary_start) as usize;
+        assert_eq!(&output[binary_start + 4..binary_start + 8], b"BIN\0");
+        assert_eq!(binary_start + 8 + binary_length, output.len());
+        assert!(json_length.is_multiple_of(4) && binary_length.is_multiple_of(4));
+        (json, &output[binary_start + 8..])
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut textured = mesh();
+        textured.name = "chunk \"0\"";
+        textured.translation = [16.0, 0.0, -16.0];
+        textured.uvs = Some(&UVS);
+        let mut output = Vec::new();
+        write_glb(&mut output, &[textured, mesh()]).unwrap();
+        let (json, binary) = chunks(&output);
+
+        assert!(
+            json.contains("{\"name\":\"chunk \\\"0\\\"\",\"mesh\":0,\"translation\":[16,0,-16]}")
+        );
+        assert!(json.contains("\"scenes\":[{\"nodes\":[0,1]}]"));
+        assert!(json.contains("\"count\":3,\"type\":\"VEC3\",\"min\":[0,0,-2],\"max\":[1,1.5,0]"));
+        assert!(json.contains("\"TEXCOORD_0\":2"));
+        assert!(json.contains(&format!(
+            "\"componentType\":{},\"count\":3,\"type\":\"SCALAR\"",
+            UNSIGNED_SHORT
+        )));
+
+        // The first mesh's positions come first, then its normals.
+        for (vertex, position) in POSITIONS.iter().enumerate() {
+            for axis in 0..3 {
+                let value = uint(binary, (vertex * 3 + axis) * 4);
+                assert_eq!(f32::from_bits(value), position[axis]);
+                let value = uint(binary, 36 + (vertex * 3 + axis) * 4);
+                assert_eq!(f32::from_bits(value), NORMALS[vertex][axis]);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_meshes_are_left_out() {
+        let empty = GltfMesh::new(&[], &[], &[]);
+        let mut output = Vec::new();
+        write_glb(&mut output, &[GltfMesh::new(&[], &[], &[]), mesh()]).unwrap();
+        let (json, _) = chunks(&output);
+        assert!(json.contains("\"mesh\":0"));
+        assert!(!json.contains("\"mesh\":1"));
+        assert!(!json.contains("\"count\":0"));
+
+        for meshes in &[&[][..], &[empty][..]] {
+            let mut output = Vec::new();
+            let error = write_glb(&mut output, meshes).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            assert!(output.is_empty());
+        }
+    }
+
+    #[test]
+    fn invalid_meshes_are_refused() {
+        let nan_positions = [[0.0, 0.0, 0.0], [f32::NAN, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let infinite_normals = [[0.0, 0.0, f32::INFINITY]; 3];
+        let short_uvs = [[0.0, 0.0]; 2];
+        let nan_uvs = [[0.0, f32::NAN]; 3];
+        let mut meshes = vec![
+            GltfMesh::new(&POSITIONS, &NORMALS[..2], &INDICES),
+            GltfMesh::new(&POSITIONS, &NORMALS, &INDICES[..2]),
+            GltfMesh::new(&POSITIONS, &NORMALS, &[0, 1, 3]),
+            GltfMesh::new(&nan_positions, &NORMALS, &INDICES),
+            GltfMesh::new(&POSITIONS, &infinite_normals, &INDICES),
+        ];
+        for &uvs in &[&short_uvs[..], &nan_uvs[..]] {
+            let mut textured = mesh();
+            textured.uvs = Some(uvs);
+            meshes.push(textured);
+        }
+        let mut moved = mesh();
+        moved.translation[1] = f32::NEG_INFINITY;
+        meshes.push(moved);
+        for invalid in meshes {
+            let mut output = Vec::new();
+            let error = write_glb(&mut output, &[mesh(), invalid]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            assert!(output.is_empty());
+        }
+    }
+}
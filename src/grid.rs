@@ -0,0 +1,126 @@
+// A dense, already-sampled field with a position in the world, for when the
+// values come from somewhere other than a function (a voxelized mesh, a
+// volume file, ...).
+
+use field::Field;
+use math::normalize;
+use surface_net_impl;
+
+pub struct Grid {
+    // Number of samples along each axis.
+    pub size: [usize; 3],
+    // World position of sample (0, 0, 0).
+    pub origin: [f32; 3],
+    // World distance between neighbouring samples along each axis.
+    pub spacing: [f32; 3],
+    // X varies fastest, then Y, then Z.
+    pub data: Vec<f32>,
+    // Value of everything outside the grid. Keep it positive (air), so that
+    // meshes of solids touching the edge of the grid get closed off.
+    pub background: f32,
+}
+
+impl Grid {
+    pub fn new(size: [usize; 3], origin: [f32; 3], spacing: [f32; 3]) -> Grid {
+        Grid {
+            size,
+            origin,
+            spacing,
+            data: vec![0.0; size[0] * size[1] * size[2]],
+            background: spacing[0].max(spacing[1]).max(spacing[2]),
+        }
+    }
+
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (z * self.size[1] + y) * self.size[0] + x
+    }
+
+    // Returns the background value outside of the grid.
+    pub fn get(&self, x: isize, y: isize, z: isize) -> f32 {
+        if x < 0
+            || y < 0
+            || z < 0
+            || x as usize >= self.size[0]
+            || y as usize >= self.size[1]
+            || z as usize >= self.size[2]
+        {
+            self.background
+        } else {
+            self.data[self.index(x as usize, y as usize, z as usize)]
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: f32) {
+        let index = self.index(x, y, z);
+        self.data[index] = value;
+    }
+
+    pub fn position(&self, x: usize, y: usize, z: usize) -> [f32; 3] {
+        [
+            self.origin[0] + x as f32 * self.spacing[0],
+            self.origin[1] + y as f32 * self.spacing[1],
+            self.origin[2] + z as f32 * self.spacing[2],
+        ]
+    }
+
+    // Trilinear interpolation at a world position, which matches surface_net's
+    // linear interpolation along cell edges.
+    pub fn sample(&self, point: [f32; 3]) -> f32 {
+        let mut cell = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let coordinate = (point[axis] - self.origin[axis]) / self.spacing[axis];
+            let floor = coordinate.floor();
+            cell[axis] = floor as isize;
+            fraction[axis] = coordinate - floor;
+        }
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut sample = cell;
+            for axis in 0..3 {
+                if (corner >> axis) & 1 == 1 {
+                    weight *= fraction[axis];
+                    sample[axis] += 1;
+                } else {
+                    weight *= 1.0 - fraction[axis];
+                }
+            }
+            if weight != 0.0 {
+                value += weight * self.get(sample[0], sample[1], sample[2]);
+            }
+        }
+        value
+    }
+
+    // Meshes the grid, with positions and normals in world space.
+    // The grid is padded by one sample of background on every side, so
+    // anything solid at the edge of the grid still gets a closed surface.
+    pub fn surface_net(&self) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>) {
+        let cells = [self.size[0] + 1, self.size[1] + 1, self.size[2] + 1];
+        let (mut positions, mut normals, _, indices) = surface_net_impl(
+            cells,
+            &|x, y, z| self.get(x as isize - 1, y as isize - 1, z as isize - 1),
+            &[],
+        );
+        for position in &mut positions {
+            for (axis, value) in position.iter_mut().enumerate() {
+                *value = self.origin[axis] + (*value - 1.0) * self.spacing[axis];
+            }
+        }
+        // Normals are gradients, so they scale inversely with the spacing.
+        for normal in &mut normals {
+            for (axis, value) in normal.iter_mut().enumerate() {
+                *value /= self.spacing[axis];
+            }
+            *normal = normalize(*normal);
+        }
+        (positions, normals, indices)
+    }
+}
+
+impl Field for Grid {
+    fn get(&self, point: [f32; 3]) -> f32 {
+        self.sample(point)
+    }
+}
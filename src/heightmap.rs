@@ -0,0 +1,374 @@
+// Turns a 2.5D heightmap into a 3d field that surface_net can mesh, so
+// heightmap terrain can get overhangs and caves mixed into it.
+// The heightmap lies in the XZ plane with Y up: pixel (column, row) is at
+// world position (column * spacing, row * spacing).
+
+use std::io::{self, Read};
+
+use field::Field;
+
+pub struct Heightmap {
+    width: usize,
+    depth: usize,
+    data: Vec<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Bilinear,
+    // Catmull-Rom, which passes through the pixel values and doesn't show the
+    // pixel grid as creases like bilinear does.
+    Bicubic,
+}
+
+impl Heightmap {
+    // Pixels are in row-major order, rows along Z. InvalidInput if data isn't
+    // width * depth pixels or there are none.
+    pub fn new(width: usize, depth: usize, data: Vec<u16>) -> io::Result<Heightmap> {
+        if width == 0 || depth == 0 {
+            return Err(invalid_input("heightmap must not be empty"));
+        }
+        if width.checked_mul(depth) != Some(data.len()) {
+            return Err(invalid_input(
+                "heightmap data does not match its dimensions",
+            ));
+        }
+        Ok(Heightmap { width, depth, data })
+    }
+
+    // Headerless 16-bit samples, as exported by most terrain tools. The bytes
+    // must be exactly width * depth samples.
+    pub fn from_raw(
+        width: usize,
+        depth: usize,
+        bytes: &[u8],
+        big_endian: bool,
+    ) -> io::Result<Heightmap> {
+        if width == 0 || depth == 0 {
+            return Err(invalid_data("heightmap must not be empty"));
+        }
+        let expected = width
+            .checked_mul(depth)
+            .and_then(|pixels| pixels.checked_mul(2))
+            .ok_or_else(|| invalid_data("heightmap dimensions too large"))?;
+        if bytes.len() != expected {
+            return Err(invalid_data(
+                "raw heightmap size does not match its dimensions",
+            ));
+        }
+        let data = bytes
+            .chunks_exact(2)
+            .map(|pair| {
+                if big_endian {
+                    u16::from_be_bytes([pair[0], pair[1]])
+                } else {
+                    u16::from_le_bytes([pair[0], pair[1]])
+                }
+            })
+            .collect();
+        Heightmap::new(width, depth, data)
+    }
+
+    // Binary PGM (P5), 8 or 16 bit. 8-bit images are scaled up to the full
+    // 16-bit range.
+    pub fn read_pgm<R: Read>(reader: &mut R) -> io::Result<Heightmap> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let mut position = 0;
+        let mut header = [0usize; 4];
+        for (index, value) in header.iter_mut().enumerate() {
+            let token = pgm_token(&bytes, &mut position)?;
+            *value = if index == 0 {
+                if token != b"P5" {
+                    return Err(invalid_data("not a binary PGM file"));
+                }
+                0
+            } else {
+                std::str::from_utf8(token)
+                    .ok()
+                    .and_then(|token| token.parse().ok())
+                    .ok_or_else(|| invalid_data("malformed PGM header"))?
+            };
+        }
+        let (width, depth, max_value) = (header[1], header[2], header[3]);
+        if width == 0 || depth == 0 || max_value == 0 || max_value > 65535 {
+            return Err(invalid_data("unsupported PGM dimensions or depth"));
+        }
+        // Exactly one whitespace character separates the header from the
+        // pixels.
+        position += 1;
+        let bytes_per_pixel = if max_value < 256 { 1 } else { 2 };
+        let pixel_count = width
+            .checked_mul(depth)
+            .filter(|count| count.checked_mul(bytes_per_pixel).is_some())
+            .ok_or_else(|| invalid_data("PGM dimensions too large"))?;
+        let pixels = bytes
+            .get(position..)
+            .filter(|pixels| pixels.len() >= pixel_count * bytes_per_pixel)
+            .ok_or_else(|| invalid_data("PGM pixel data is truncated"))?;
+        let data = (0..pixel_count)
+            .map(|index| {
+                let value = if bytes_per_pixel == 1 {
+                    pixels[index] as u32
+                } else {
+                    u16::from_be_bytes([pixels[index * 2], pixels[index * 2 + 1]]) as u32
+                };
+                (value.min(max_value as u32) * 65535 / max_value as u32) as u16
+            })
+            .collect();
+        Heightmap::new(width, depth, data)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // Raw pixel value in [0, 1], clamped to the edge of the image.
+    pub fn pixel(&self, column: isize, row: isize) -> f32 {
+        let column = column.max(0).min(self.width as isize - 1) as usize;
+        let row = row.max(0).min(self.depth as isize - 1) as usize;
+        self.data[row * self.width + column] as f32 / 65535.0
+    }
+
+    // Interpolated value in (roughly, bicubic overshoots a little) [0, 1] at
+    // a fractional pixel coordinate.
+    pub fn sample(&self, column: f32, row: f32, interpolation: Interpolation) -> f32 {
+        let (column_floor, row_floor) = (column.floor(), row.floor());
+        let (tx, tz) = (column - column_floor, row - row_floor);
+        let (column, row) = (column_floor as isize, row_floor as isize);
+        match interpolation {
+            Interpolation::Bilinear => {
+                let top = lerp(self.pixel(column, row), self.pixel(column + 1, row), tx);
+                let bottom = lerp(
+                    self.pixel(column, row + 1),
+                    self.pixel(column + 1, row + 1),
+                    tx,
+                );
+                lerp(top, bottom, tz)
+            }
+            Interpolation::Bicubic => {
+                let mut rows = [0.0; 4];
+                for (offset, value) in rows.iter_mut().enumerate() {
+                    let row = row + offset as isize - 1;
+                    *value = catmull_rom(
+                        [
+                            self.pixel(column - 1, row),
+                            self.pixel(column, row),
+                            self.pixel(column + 1, row),
+                            self.pixel(column + 2, row),
+                        ],
+                        tx,
+                    );
+                }
+                catmull_rom(rows, tz)
+            }
+        }
+    }
+}
+
+pub struct HeightmapField<'a> {
+    pub heightmap: Heightmap,
+    // World distance between neighbouring pixels, along both X and Z.
+    spacing: f32,
+    // World height of a full-white pixel.
+    pub vertical_scale: f32,
+    pub interpolation: Interpolation,
+    // Unioned with the terrain, for overhangs, arches and floating bits that
+    // a heightmap can't express.
+    pub overhangs: Option<&'a dyn Field>,
+    // Subtracted from the terrain: wherever this field is negative becomes
+    // air.
+    pub caves: Option<&'a dyn Field>,
+}
+
+impl<'a> HeightmapField<'a> {
+    // None unless spacing is positive and finite, the slopes are measured
+    // over half of it.
+    pub fn new(
+        heightmap: Heightmap,
+        spacing: f32,
+        vertical_scale: f32,
+    ) -> Option<HeightmapField<'a>> {
+        if !(spacing > 0.0 && spacing.is_finite()) {
+            return None;
+        }
+        Some(HeightmapField {
+            heightmap,
+            spacing,
+            vertical_scale,
+            interpolation: Interpolation::Bilinear,
+            overhangs: None,
+            caves: None,
+        })
+    }
+
+    pub fn spacing(&self) -> f32 {
+        self.spacing
+    }
+
+    // Terrain height in world units at a world XZ position.
+    pub fn height(&self, x: f32, z: f32) -> f32 {
+        self.heightmap
+            .sample(x / self.spacing, z / self.spacing, self.interpolation)
+            * self.vertical_scale
+    }
+}
+
+impl<'a> Field for HeightmapField<'a> {
+    fn get(&self, point: [f32; 3]) -> f32 {
+        // Height above the terrain isn't a distance on slopes, so divide by
+        // the length of the surface gradient to get a better approximation:
+        // the distance to the tangent plane.
+        let height = self.height(point[0], point[2]);
+        let step = self.spacing * 0.5;
+        let slope_x = (self.height(point[0] + step, point[2])
+            - self.height(point[0] - step, point[2]))
+            / (2.0 * step);
+        let slope_z = (self.height(point[0], point[2] + step)
+            - self.height(point[0], point[2] - step))
+            / (2.0 * step);
+        let mut value = (point[1] - height) / (1.0 + slope_x * slope_x + slope_z * slope_z).sqrt();
+        if let Some(overhangs) = self.overhangs {
+            value = value.min(overhangs.get(point));
+        }
+        if let Some(caves) = self.caves {
+            value = value.max(-caves.get(point));
+        }
+        value
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn catmull_rom(p: [f32; 4], t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p[1]
+        + (p[2] - p[0]) * t
+        + (2.0 * p[0] - 5.0 * p[1] + 4.0 * p[2] - p[3]) * t2
+        + (3.0 * p[1] - p[0] - 3.0 * p[2] + p[3]) * t3)
+}
+
+// Next whitespace-separated token of a PGM header, skipping # comments.
+fn pgm_token<'a>(bytes: &'a [u8], position: &mut usize) -> io::Result<&'a [u8]> {
+    loop {
+        match bytes.get(*position) {
+            Some(b'#') => {
+                while bytes.get(*position).is_some_and(|&b| b != b'\n') {
+                    *position += 1;
+                }
+            }
+            Some(b) if b.is_ascii_whitespace() => *position += 1,
+            Some(_) => break,
+            None => return Err(invalid_data("PGM header is truncated")),
+        }
+    }
+    let start = *position;
+    while bytes
+        .get(*position)
+        .is_some_and(|b| !b.is_ascii_whitespace())
+    {
+        *position += 1;
+    }
+    Ok(&bytes[start..*position])
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pgm_round_trip() {
+        let mut bytes = b"P5\n# comment\n2 3\n65535\n".to_vec();
+        for value in &[0u16, 1, 256, 1000, 40000, 65535] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        let heightmap = Heightmap::read_pgm(&mut &bytes[..]).unwrap();
+        assert_eq!((heightmap.width(), heightmap.depth()), (2, 3));
+        assert_eq!(heightmap.pixel(1, 2), 1.0);
+        assert_eq!(heightmap.pixel(0, 1), 256.0 / 65535.0);
+
+        let eight_bit = Heightmap::read_pgm(&mut &b"P5 1 1 255 \xff"[..]).unwrap();
+        assert_eq!(eight_bit.pixel(0, 0), 1.0);
+    }
+
+    #[test]
+    fn malformed_pgm() {
+        let inputs: &[&[u8]] = &[
+            b"",
+            b"P6 1 1 255 \x00",
+            b"P5 1 1",
+            b"P5 x 1 255 \x00",
+            b"P5 0 1 255 ",
+            b"P5 1 1 70000 \x00\x00",
+            b"P5 2 2 255 \x00\x00\x00",
+            b"P5 18446744073709551615 2 65535 \x00",
+            b"P5 4294967296 4294967296 255 \x00",
+        ];
+        for input in inputs {
+            let error = Heightmap::read_pgm(&mut &input[..]).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let bytes = [0x01, 0x02, 0xff, 0xff];
+        let little = Heightmap::from_raw(2, 1, &bytes, false).unwrap();
+        assert_eq!(little.pixel(0, 0), 0x0201 as f32 / 65535.0);
+        let big = Heightmap::from_raw(1, 2, &bytes, true).unwrap();
+        assert_eq!(big.pixel(0, 0), 0x0102 as f32 / 65535.0);
+        assert_eq!(big.pixel(0, 1), 1.0);
+    }
+
+    #[test]
+    fn malformed_raw() {
+        let bytes = [0; 5];
+        for &(width, depth, length) in &[(2, 1, 5), (2, 1, 2), (0, 2, 0), (usize::MAX, 2, 4)] {
+            let error = Heightmap::from_raw(width, depth, &bytes[..length], false)
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn refuses_bad_dimensions() {
+        for &(width, depth, length) in &[(2, 2, 3), (0, 0, 0), (usize::MAX, 2, 2)] {
+            let error = Heightmap::new(width, depth, vec![0; length]).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn field_of_a_ramp() {
+        // Rises by 0.5 world units per pixel along X, so 45 degrees with a
+        // spacing of 0.5.
+        let data = (0..4 * 4).map(|index| (index % 4) as u16 * 1000).collect();
+        let heightmap = Heightmap::new(4, 4, data).unwrap();
+        let field = HeightmapField::new(heightmap, 0.5, 65.535 / 2.0).unwrap();
+        assert!((field.height(0.75, 1.0) - 0.75).abs() < 1e-4);
+        let distance = field.get([0.75, 1.75, 1.0]);
+        assert!((distance - 0.5f32.sqrt()).abs() < 1e-4, "{}", distance);
+        assert!(field.get([0.75, 0.25, 1.0]) < 0.0);
+
+        for &spacing in &[0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let heightmap = Heightmap::new(1, 1, vec![0]).unwrap();
+            assert!(HeightmapField::new(heightmap, spacing, 1.0).is_none());
+        }
+    }
+}
@@ -1,9 +1,44 @@
 use std::collections::HashMap;
 
+pub mod bvh;
+pub mod collision;
+pub mod components;
+pub mod decimate;
+pub mod field;
+pub mod gltf;
+pub mod grid;
+pub mod heightmap;
+pub mod manifold;
+pub mod mass;
+mod math;
+pub mod noise;
+pub mod obj;
+pub mod occlusion;
+pub mod ply;
+pub mod pointcloud;
+pub mod raycast;
+pub mod scene;
+pub mod slice;
+pub mod smooth;
+pub mod stl;
+pub mod storage;
+pub mod tangents;
+pub mod terrain;
+#[cfg(test)]
+mod test_meshes;
+mod union_find;
+pub mod uv;
+pub mod validate;
+pub mod volume;
+pub mod vox;
+pub mod voxelize;
+
 // Positive is "air"
 // Negative is "solid"
 
-type SDF = Fn(usize, usize, usize) -> f32;
+#[allow(clippy::upper_case_acronyms)]
+type SDF<'a> = dyn Fn(usize, usize, usize) -> f32 + 'a;
+type Attribute<'a> = dyn Fn(usize, usize, usize) -> f32 + 'a;
 
 // Implements memoization (if memoize is true, copy the function into a vec and
 // use that instead of the function)
@@ -12,53 +47,94 @@ pub fn surface_net(
     signed_distance_field: &SDF,
     memoize: bool,
 ) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>) {
+    let (positions, normals, _, indices) =
+        surface_net_with_attributes(resolution, signed_distance_field, &[], memoize);
+    (positions, normals, indices)
+}
+
+// Same as surface_net, plus any number of auxiliary scalar fields (color
+// channels, temperature, wetness, ...) sampled on the same grid. Each one is
+// interpolated at the vertices with the same edge crossings that place them,
+// so the values match the surface exactly. Vector fields are passed as one
+// field per component.
+// Returns (positions, normals, attributes, indices), where attributes[i][v]
+// is the value of attribute field i at vertex v. memoize works like it does
+// for surface_net, and applies to the attributes too.
+#[allow(clippy::type_complexity)]
+pub fn surface_net_with_attributes(
+    resolution: usize,
+    signed_distance_field: &SDF,
+    attributes: &[&Attribute],
+    memoize: bool,
+) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<Vec<f32>>, Vec<usize>) {
     if memoize {
         let axis_length = resolution + 1;
-        let arr = coords(axis_length)
-            .map(|(x, y, z)| signed_distance_field(x, y, z))
+        let memoized = |field: &SDF| {
+            // coords goes through X slowest and Z fastest.
+            let arr = coords([axis_length; 3])
+                .map(|(x, y, z)| field(x, y, z))
+                .collect::<Vec<_>>();
+            move |x: usize, y: usize, z: usize| {
+                arr[x * axis_length * axis_length + y * axis_length + z]
+            }
+        };
+        let signed_distance_field = memoized(signed_distance_field);
+        let attributes = attributes
+            .iter()
+            .map(|&attribute| memoized(attribute))
             .collect::<Vec<_>>();
-        surface_net_impl(resolution, &move |x, y, z| {
-            arr[z * axis_length * axis_length + y * axis_length + x]
-        })
+        let attributes = attributes
+            .iter()
+            .map(|attribute| attribute as &Attribute)
+            .collect::<Vec<_>>();
+        surface_net_impl([resolution; 3], &signed_distance_field, &attributes)
     } else {
-        surface_net_impl(resolution, signed_distance_field)
+        surface_net_impl([resolution; 3], signed_distance_field, attributes)
     }
 }
 
-// Main algorithm driver.
+// Main algorithm driver. size is the number of cubes along each axis, so the
+// SDF is sampled from 0 to size inclusive.
+#[allow(clippy::type_complexity)]
 fn surface_net_impl(
-    resolution: usize,
+    size: [usize; 3],
     grid_values: &SDF,
-) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>) {
+    attributes: &[&Attribute],
+) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<Vec<f32>>, Vec<usize>) {
     let mut vertex_positions = Vec::new();
     let mut normals = Vec::new();
+    let mut attribute_values = vec![Vec::new(); attributes.len()];
     let mut grid_to_index = HashMap::new();
     // Find all vertex positions. Addtionally, create a hashmap from grid
     // position to index (i.e. OpenGL vertex index).
-    for coords in coords(resolution) {
-        if let Some((center, normal)) = find_center(grid_values, coords) {
+    for coords in coords(size) {
+        let corners = corner_values(grid_values, coords);
+        if let Some((center, normal)) = find_center(&corners, coords) {
             grid_to_index.insert(coords, vertex_positions.len());
             vertex_positions.push(center);
             normals.push(normal);
+            for (values, attribute) in attribute_values.iter_mut().zip(attributes) {
+                values.push(find_attribute(&corners, *attribute, coords));
+            }
         }
     }
     // Find all triangles, in the form of [index, index, index] triples.
     let mut indicies = Vec::new();
     make_all_triangles(
         grid_values,
-        resolution,
+        size,
         &grid_to_index,
         &vertex_positions,
         &mut indicies,
     );
-    (vertex_positions, normals, indicies)
+    (vertex_positions, normals, attribute_values, indicies)
 }
 
-// Iterator over all integer points in a 3d cube from 0 to size
-fn coords(size: usize) -> impl Iterator<Item = (usize, usize, usize)> {
-    (0..size)
-        .flat_map(move |x| (0..size).map(move |y| (x, y)))
-        .flat_map(move |(x, y)| (0..size).map(move |z| (x, y, z)))
+// Iterator over all integer points in a 3d box from 0 to size
+fn coords(size: [usize; 3]) -> impl Iterator<Item = (usize, usize, usize)> {
+    (0..size[0])
+        .flat_map(move |x| (0..size[1]).map(move |y| (x, y)))
+        .flat_map(move |(x, y)| (0..size[2]).map(move |z| (x, y, z)))
 }
 
 // List of all edges in a cube.
@@ -87,19 +163,9 @@ const OFFSETS: [(usize, usize); 12] = [
 // have crossings).
 // There are more complicated and better algorithms than this, but this is
 // simple and easy to implement.
+// values are the SDF at the cube's corners, from corner_values.
 // Returns: (pos, normal)
-fn find_center(
-    grid_values: &SDF,
-    coord: (usize, usize, usize),
-) -> Option<([f32; 3], [f32; 3])> {
-    let mut values = [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
-    for (x, value) in values.iter_mut().enumerate() {
-        *value = grid_values(
-            coord.0 + (x & 1),
-            coord.1 + ((x >> 1) & 1),
-            coord.2 + ((x >> 2) & 1),
-        );
-    }
+fn find_center(values: &[f32; 8], coord: (usize, usize, usize)) -> Option<([f32; 3], [f32; 3])> {
     let edges = OFFSETS.iter().filter_map(|&(offset1, offset2)| {
         find_edge(offset1, offset2, values[offset1], values[offset2])
     });
@@ -136,15 +202,26 @@ fn find_center(
     }
 }
 
+// The SDF at the eight corners of the cube at coord, indexed by the corner's
+// offset bits like OFFSETS.
+fn corner_values(grid_values: &SDF, coord: (usize, usize, usize)) -> [f32; 8] {
+    let mut values = [0.0; 8];
+    for (x, value) in values.iter_mut().enumerate() {
+        *value = grid_values(
+            coord.0 + (x & 1),
+            coord.1 + ((x >> 1) & 1),
+            coord.2 + ((x >> 2) & 1),
+        );
+    }
+    values
+}
+
 // Given two points, A and B, find the point between them where the SDF is zero.
 // (This might not exist).
 // A and B are specified via A=coord+offset1 and B=coord+offset2, because code
 // is weird.
 fn find_edge(offset1: usize, offset2: usize, value1: f32, value2: f32) -> Option<[f32; 3]> {
-    if (value1 < 0.0) == (value2 < 0.0) {
-        return None;
-    }
-    let interp = value1 / (value1 - value2);
+    let interp = edge_crossing(value1, value2)?;
     let point = [
         (offset1 & 1) as f32 * (1.0 - interp) + (offset2 & 1) as f32 * interp,
         ((offset1 >> 1) & 1) as f32 * (1.0 - interp) + ((offset2 >> 1) & 1) as f32 * interp,
@@ -153,6 +230,32 @@ fn find_edge(offset1: usize, offset2: usize, value1: f32, value2: f32) -> Option
     Some(point)
 }
 
+// How far along the edge from A to B the SDF crosses zero, from 0 to 1. None
+// if it doesn't.
+fn edge_crossing(value1: f32, value2: f32) -> Option<f32> {
+    if (value1 < 0.0) == (value2 < 0.0) {
+        None
+    } else {
+        Some(value1 / (value1 - value2))
+    }
+}
+
+// Value of an auxiliary field at this cube's vertex: the same average of edge
+// crossings as find_center, but interpolating the attribute instead of the
+// position. values are the same corner values find_center was given.
+fn find_attribute(values: &[f32; 8], attribute: &Attribute, coord: (usize, usize, usize)) -> f32 {
+    let attribute_values = corner_values(attribute, coord);
+    let mut count = 0;
+    let mut sum = 0.0;
+    for &(offset1, offset2) in OFFSETS.iter() {
+        if let Some(interp) = edge_crossing(values[offset1], values[offset2]) {
+            count += 1;
+            sum += attribute_values[offset1] * (1.0 - interp) + attribute_values[offset2] * interp;
+        }
+    }
+    sum / count as f32
+}
+
 // For every edge that crosses the boundary, make a quad between the
 // "centers" of the four cubes touching that boundary. (Well, really, two
 // triangles) The "centers" are actually the vertex positions, found earlier.
@@ -161,12 +264,12 @@ fn find_edge(offset1: usize, offset2: usize, value1: f32, value2: f32) -> Option
 // really gross.
 fn make_all_triangles(
     grid_values: &SDF,
-    resolution: usize,
+    size: [usize; 3],
     grid_to_index: &HashMap<(usize, usize, usize), usize>,
     vertex_positions: &[[f32; 3]],
     indicies: &mut Vec<usize>,
 ) {
-    for coord in coords(resolution) {
+    for coord in coords(size) {
         // TODO: Cache grid_values(coord), it's called three times here.
         // Do edges parallel with the X axis
         if coord.1 != 0 && coord.2 != 0 {
@@ -210,7 +313,7 @@ fn make_all_triangles(
     }
 }
 
-#[allow(too_many_arguments)]
+#[allow(clippy::too_many_arguments)]
 fn make_triangle(
     grid_values: &SDF,
     grid_to_index: &HashMap<(usize, usize, usize), usize>,
@@ -322,3 +425,24 @@ fn is_face(
         _ => FaceResult::NoFace,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lopsided along every axis, so that mixing up axes changes the mesh.
+    fn ellipsoid(x: usize, y: usize, z: usize) -> f32 {
+        let x = (x as f32 - 6.0) / 5.0;
+        let y = (y as f32 - 7.5) / 3.5;
+        let z = (z as f32 - 4.0) / 2.5;
+        (x * x + y * y + z * z).sqrt() - 1.0
+    }
+
+    #[test]
+    fn memoized_matches_direct() {
+        let memoized = surface_net(14, &ellipsoid, true);
+        let direct = surface_net(14, &ellipsoid, false);
+        assert!(!direct.2.is_empty());
+        assert_eq!(memoized, direct);
+    }
+}
@@ -0,0 +1,472 @@
+// A variant of surface_net that always gives a manifold mesh.
+//
+// find_center puts a single vertex in every cell, which glues surfaces
+// together wherever one cell holds several separate pieces of surface (think
+// two diagonal solid corners), and the result has edges shared by four
+// triangles. Here the crossing edges of a cell are first grouped into the
+// pieces of surface they belong to, the same loops marching cubes would make,
+// and every piece gets its own vertex (as in manifold dual contouring).
+//
+// Within a cube face, the surface joins up crossing edges in pairs. Faces
+// with four crossing edges are ambiguous, and are always resolved by keeping
+// the two solid corners apart. Both cells sharing a face see the same four
+// values, so they always agree on it.
+//
+// One case is left: if on both sides of an ambiguous face a single piece of
+// surface passes through the face twice (a thin tube), the two vertices would
+// be joined by four triangles. Those faces get an extra vertex for each of
+// their two crossings.
+//
+// Cells with a single piece of surface get exactly the vertex surface_net
+// would give them. Every edge is shared by two triangles, apart from where
+// the surface runs off the edge of the grid.
+
+use std::collections::HashMap;
+
+use math::{add, normalize, scale, sub};
+use union_find::{find, union};
+use {coords, corner_values, find_center, find_edge, OFFSETS, SDF};
+
+type Coord = (usize, usize, usize);
+// A grid edge, as its lower end and its axis.
+type Edge = (usize, usize, usize, usize);
+
+struct CellVertices {
+    // Vertex of the piece of surface that crosses each edge (indexed like
+    // OFFSETS), or usize::MAX for edges without a crossing.
+    edges: [usize; 12],
+    // Faces crossed twice by the same piece of surface, with their two
+    // segments as pairs of edges.
+    doubled_faces: Vec<(usize, [(usize, usize); 2])>,
+}
+
+pub fn manifold_surface_net(
+    resolution: usize,
+    grid_values: &SDF,
+) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>) {
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut cells = HashMap::new();
+    for coord in coords([resolution; 3]) {
+        if let Some(cell) = cell_vertices(grid_values, coord, &mut positions, &mut normals) {
+            cells.insert(coord, cell);
+        }
+    }
+
+    // As in make_all_triangles: a polygon around every grid edge with a
+    // crossing, through the vertices of the four cells around it.
+    let mut face_vertices = HashMap::new();
+    let mut indices = Vec::new();
+    for coord in coords([resolution; 3]) {
+        for axis in 0..3 {
+            let (axis1, axis2) = ((axis + 1) % 3, (axis + 2) % 3);
+            if component(coord, axis1) == 0 || component(coord, axis2) == 0 {
+                continue;
+            }
+            let other = step(coord, axis, 1);
+            let solid_first = grid_values(coord.0, coord.1, coord.2) < 0.0;
+            if solid_first == (grid_values(other.0, other.1, other.2) < 0.0) {
+                continue;
+            }
+            let edge = (coord.0, coord.1, coord.2, axis);
+            // Viewed face-front: coord, then around the edge.
+            let ring = [
+                coord,
+                step(coord, axis1, -1),
+                step(step(coord, axis1, -1), axis2, -1),
+                step(coord, axis2, -1),
+            ];
+            let mut polygon = Vec::new();
+            let mut split = false;
+            for i in 0..4 {
+                let cell = &cells[&ring[i]];
+                polygon.push(cell.edges[local_edge(ring[i], edge)]);
+                if let Some(vertex) = face_vertex(
+                    grid_values,
+                    &cells,
+                    ring[i],
+                    ring[(i + 1) % 4],
+                    edge,
+                    &mut face_vertices,
+                    &mut positions,
+                    &mut normals,
+                ) {
+                    polygon.push(vertex);
+                    split = true;
+                }
+            }
+            let start = indices.len();
+            if split {
+                // Fan around the middle of the polygon.
+                let mut center = [0.0; 3];
+                let mut normal = [0.0; 3];
+                for &vertex in &polygon {
+                    center = add(center, positions[vertex]);
+                    normal = add(normal, normals[vertex]);
+                }
+                let middle = positions.len();
+                positions.push(scale(center, 1.0 / polygon.len() as f32));
+                normals.push(normalize(normal));
+                for i in 0..polygon.len() {
+                    indices.extend_from_slice(&[
+                        middle,
+                        polygon[i],
+                        polygon[(i + 1) % polygon.len()],
+                    ]);
+                }
+            } else {
+                // Split along the shorter diagonal, like make_triangle.
+                let distance = |a: usize, b: usize| {
+                    let d = sub(positions[polygon[a]], positions[polygon[b]]);
+                    d[0] * d[0] + d[1] * d[1] + d[2] * d[2]
+                };
+                let corners = if distance(0, 2) < distance(1, 3) {
+                    [0, 1, 2, 0, 2, 3]
+                } else {
+                    [1, 2, 3, 1, 3, 0]
+                };
+                indices.extend(corners.iter().map(|&corner| polygon[corner]));
+            }
+            if !solid_first {
+                for triangle in indices[start..].chunks_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+        }
+    }
+    (positions, normals, indices)
+}
+
+// Finds the pieces of surface in a cell and adds a vertex for each of them.
+fn cell_vertices(
+    grid_values: &SDF,
+    coord: Coord,
+    positions: &mut Vec<[f32; 3]>,
+    normals: &mut Vec<[f32; 3]>,
+) -> Option<CellVertices> {
+    let values = corner_values(grid_values, coord);
+    let crossings = OFFSETS
+        .iter()
+        .map(|&(offset1, offset2)| find_edge(offset1, offset2, values[offset1], values[offset2]))
+        .collect::<Vec<_>>();
+    if crossings.iter().all(Option::is_none) {
+        return None;
+    }
+
+    let mut parents = (0..12).collect::<Vec<_>>();
+    let mut doubled_faces = Vec::new();
+    for face in 0..6 {
+        let segments = face_segments(face, &values);
+        for &(a, b) in &segments {
+            union(&mut parents, a, b);
+        }
+        if segments.len() == 2 {
+            doubled_faces.push((face, [segments[0], segments[1]]));
+        }
+    }
+    // Only faces whose two segments ended up in the same piece count.
+    doubled_faces.retain(|&(_, [first, second])| {
+        find(&mut parents, first.0) == find(&mut parents, second.0)
+    });
+
+    let mut roots = (0..12)
+        .filter(|&edge| crossings[edge].is_some())
+        .map(|edge| find(&mut parents, edge))
+        .collect::<Vec<_>>();
+    roots.sort_unstable();
+    roots.dedup();
+
+    let mut edges = [usize::MAX; 12];
+    for &root in &roots {
+        let vertex = positions.len();
+        if roots.len() == 1 {
+            let (center, normal) = find_center(&values, coord).unwrap();
+            positions.push(center);
+            normals.push(normal);
+        } else {
+            // Average of this piece's crossings, and a normal pointing from
+            // the solid end of each of its edges to the air end.
+            let mut sum = [0.0; 3];
+            let mut normal = [0.0; 3];
+            let mut count = 0;
+            for (edge, crossing) in crossings.iter().enumerate() {
+                if let Some(point) = *crossing {
+                    if find(&mut parents, edge) == root {
+                        let (offset1, offset2) = OFFSETS[edge];
+                        let axis = (offset1 ^ offset2).trailing_zeros() as usize;
+                        normal[axis] += if values[offset1] < 0.0 { 1.0 } else { -1.0 };
+                        sum = add(sum, point);
+                        count += 1;
+                    }
+                }
+            }
+            let center = scale(sum, 1.0 / count as f32);
+            positions.push([
+                center[0] + coord.0 as f32,
+                center[1] + coord.1 as f32,
+                center[2] + coord.2 as f32,
+            ]);
+            normals.push(normalize(normal));
+        }
+        for edge in 0..12 {
+            if crossings[edge].is_some() && find(&mut parents, edge) == root {
+                edges[edge] = vertex;
+            }
+        }
+    }
+    Some(CellVertices {
+        edges,
+        doubled_faces,
+    })
+}
+
+// How the surface joins up the crossing edges of a cube face, as pairs of
+// edges (indexed like OFFSETS). Faces are numbered axis * 2 + side.
+fn face_segments(face: usize, values: &[f32; 8]) -> Vec<(usize, usize)> {
+    let (axis, side) = (face / 2, face % 2);
+    let (u, v) = (1 << ((axis + 1) % 3), 1 << ((axis + 2) % 3));
+    let base = side << axis;
+    let cycle = [base, base | u, base | u | v, base | v];
+    // Edge i goes from cycle[i] to cycle[i + 1].
+    let face_edges = [0, 1, 2, 3].map(|i| edge_index(cycle[i], cycle[(i + 1) % 4]));
+    let crossing =
+        [0, 1, 2, 3].map(|i| (values[cycle[i]] < 0.0) != (values[cycle[(i + 1) % 4]] < 0.0));
+    let crossing_edges = (0..4)
+        .filter(|&i| crossing[i])
+        .map(|i| face_edges[i])
+        .collect::<Vec<_>>();
+    match crossing_edges.len() {
+        2 => vec![(crossing_edges[0], crossing_edges[1])],
+        4 => {
+            // Cut off each solid corner on its own, joining the two edges
+            // next to it.
+            (0..4)
+                .filter(|&i| values[cycle[i]] < 0.0)
+                .map(|i| (face_edges[(i + 3) % 4], face_edges[i]))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+// The extra vertex between two neighbouring cells around a grid edge, if the
+// face between them is crossed twice by the same piece of surface on both
+// sides. One vertex per crossing (so per pair of grid edges), created the
+// first time either of its two grid edges asks for it.
+#[allow(clippy::too_many_arguments)]
+fn face_vertex(
+    grid_values: &SDF,
+    cells: &HashMap<Coord, CellVertices>,
+    first: Coord,
+    second: Coord,
+    edge: Edge,
+    face_vertices: &mut HashMap<(Edge, Edge), usize>,
+    positions: &mut Vec<[f32; 3]>,
+    normals: &mut Vec<[f32; 3]>,
+) -> Option<usize> {
+    let axis = (0..3)
+        .find(|&axis| component(first, axis) != component(second, axis))
+        .unwrap();
+    let (lower, upper) = if component(first, axis) < component(second, axis) {
+        (first, second)
+    } else {
+        (second, first)
+    };
+    let (lower_cell, upper_cell) = (&cells[&lower], &cells[&upper]);
+    let doubled = |cell: &CellVertices, face: usize| {
+        cell.doubled_faces
+            .iter()
+            .find(|&&(doubled, _)| doubled == face)
+            .map(|&(_, segments)| segments)
+    };
+    doubled(upper_cell, axis * 2)?;
+    let segments = doubled(lower_cell, axis * 2 + 1)?;
+    let local = local_edge(lower, edge);
+    let partner = segments
+        .iter()
+        .filter_map(|&(a, b)| {
+            if a == local {
+                Some(b)
+            } else if b == local {
+                Some(a)
+            } else {
+                None
+            }
+        })
+        .next()
+        .unwrap();
+    let partner = global_edge(lower, partner);
+    let key = (edge.min(partner), edge.max(partner));
+    let vertex = *face_vertices.entry(key).or_insert_with(|| {
+        let middle = scale(
+            add(
+                edge_point(grid_values, key.0),
+                edge_point(grid_values, key.1),
+            ),
+            0.5,
+        );
+        let normal = add(
+            normals[lower_cell.edges[local]],
+            normals[upper_cell.edges[local_edge(upper, edge)]],
+        );
+        positions.push(middle);
+        normals.push(normalize(normal));
+        positions.len() - 1
+    });
+    Some(vertex)
+}
+
+// Where the surface crosses a grid edge.
+fn edge_point(grid_values: &SDF, edge: Edge) -> [f32; 3] {
+    let (x, y, z, axis) = edge;
+    let end = step((x, y, z), axis, 1);
+    let local = find_edge(
+        0,
+        1 << axis,
+        grid_values(x, y, z),
+        grid_values(end.0, end.1, end.2),
+    )
+    .unwrap();
+    [
+        local[0] + x as f32,
+        local[1] + y as f32,
+        local[2] + z as f32,
+    ]
+}
+
+// Index into OFFSETS of the edge between two corners.
+fn edge_index(corner1: usize, corner2: usize) -> usize {
+    let key = (corner1.min(corner2), corner1.max(corner2));
+    OFFSETS.iter().position(|&offsets| offsets == key).unwrap()
+}
+
+// Index into OFFSETS of a grid edge within a cell that has it.
+fn local_edge(cell: Coord, edge: Edge) -> usize {
+    let corner = (edge.0 - cell.0) | ((edge.1 - cell.1) << 1) | ((edge.2 - cell.2) << 2);
+    edge_index(corner, corner | (1 << edge.3))
+}
+
+fn global_edge(cell: Coord, local: usize) -> Edge {
+    let (offset1, offset2) = OFFSETS[local];
+    (
+        cell.0 + (offset1 & 1),
+        cell.1 + ((offset1 >> 1) & 1),
+        cell.2 + ((offset1 >> 2) & 1),
+        (offset1 ^ offset2).trailing_zeros() as usize,
+    )
+}
+
+fn component(coord: Coord, axis: usize) -> usize {
+    match axis {
+        0 => coord.0,
+        1 => coord.1,
+        _ => coord.2,
+    }
+}
+
+fn step(coord: Coord, axis: usize, amount: isize) -> Coord {
+    let moved = |value: usize, this: usize| {
+        if this == axis {
+            (value as isize + amount) as usize
+        } else {
+            value
+        }
+    };
+    (moved(coord.0, 0), moved(coord.1, 1), moved(coord.2, 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validate::validate;
+
+    // Returns the number of triangles.
+    fn assert_manifold(resolution: usize, field: &SDF) -> usize {
+        let (positions, normals, indices) = manifold_surface_net(resolution, field);
+        assert_eq!(normals.len(), positions.len());
+        let report = validate(&positions, &indices);
+        assert!(report.is_manifold(), "{:?}", report);
+        assert!(report.is_closed(), "{:?}", report);
+        assert!(report.inconsistent_edges.is_empty(), "{:?}", report);
+        indices.len() / 3
+    }
+
+    // Air all around the edge of the grid, so that every surface is closed.
+    fn padded(resolution: usize, values: &[f32]) -> impl Fn(usize, usize, usize) -> f32 + '_ {
+        move |x, y, z| {
+            if [x, y, z].iter().any(|&i| i == 0 || i >= resolution - 1) {
+                1.0
+            } else {
+                values[(x * resolution + y) * resolution + z]
+            }
+        }
+    }
+
+    #[test]
+    fn random_fields() {
+        let mut state = 12345u32;
+        for resolution in 4..10 {
+            for _ in 0..20 {
+                let values = (0..resolution * resolution * resolution)
+                    .map(|_| {
+                        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                        // Nudged away from zero, and a bit more solid than not.
+                        let value = (state >> 8) as f32 / (1 << 24) as f32 - 0.6;
+                        if value.abs() < 1e-3 {
+                            0.5
+                        } else {
+                            value
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                assert!(assert_manifold(resolution, &padded(resolution, &values)) > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn diagonal_corners() {
+        // Two solid samples touching at a corner only: plain surface_net
+        // glues them into one vertex per cell.
+        let field = |x, y, z| {
+            if (x, y, z) == (2, 2, 2) || (x, y, z) == (3, 3, 3) {
+                -1.0
+            } else {
+                1.0
+            }
+        };
+        assert!(assert_manifold(6, &field) > 0);
+        let (positions, _, indices) = manifold_surface_net(6, &field);
+        assert_eq!(validate(&positions, &indices).components, 2);
+        let (positions, _, indices) = ::surface_net(6, &field, false);
+        assert!(!validate(&positions, &indices).is_manifold());
+    }
+
+    #[test]
+    fn thin_tube() {
+        // A ring of solid samples one sample thick: its cross-section in the
+        // z = 3 plane is two diagonal samples, joined through (3, 2) below the
+        // plane and through (2, 3) above it. The same piece of surface passes
+        // through the face between them twice, from both sides.
+        let solid = [
+            (2, 2, 3),
+            (3, 3, 3),
+            (2, 2, 2),
+            (3, 2, 2),
+            (3, 3, 2),
+            (2, 2, 4),
+            (2, 3, 4),
+            (3, 3, 4),
+        ];
+        let field = |x, y, z| {
+            if solid.contains(&(x, y, z)) {
+                -1.0
+            } else {
+                1.0
+            }
+        };
+        assert!(assert_manifold(7, &field) > 0);
+        let (positions, _, indices) = ::surface_net(7, &field, false);
+        assert!(!validate(&positions, &indices).is_manifold());
+    }
+}
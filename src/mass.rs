@@ -0,0 +1,436 @@
+// Mass properties of shapes, for physics: volume, center of mass and inertia
+// tensor. All for a uniform density of 1, so multiply volume and inertia by
+// the actual density to get mass and the real tensor.
+//
+// From a closed mesh, these are exact (up to rounding), using the polyhedral
+// integrals from David Eberly's "Polyhedral Mass Properties (Revisited)".
+// From a grid they're estimates, but need no meshing: every sample counts as
+// a voxel, partly filled when the surface passes through it.
+
+use std::collections::HashMap;
+
+use grid::Grid;
+use math::{signed_volume, triangle, triangle_area};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MassProperties {
+    pub volume: f32,
+    pub center_of_mass: [f32; 3],
+    // About the center of mass, in world axes.
+    pub inertia: [[f32; 3]; 3],
+}
+
+pub fn surface_area(positions: &[[f32; 3]], indices: &[usize]) -> f32 {
+    indices
+        .chunks(3)
+        .map(|corners| triangle_area(triangle(positions, corners)))
+        .sum()
+}
+
+// Positive for closed meshes wound the way surface_net winds them
+// (counter-clockwise seen from outside), negative if inside out.
+pub fn enclosed_volume(positions: &[[f32; 3]], indices: &[usize]) -> f32 {
+    indices
+        .chunks(3)
+        .map(|corners| signed_volume(triangle(positions, corners)))
+        .sum()
+}
+
+// None unless the mesh is closed, wound like surface_net winds it and
+// encloses some volume: the integrals only add up to the solid's for closed
+// meshes, and there's no center of mass without any mass.
+pub fn mass_properties(positions: &[[f32; 3]], indices: &[usize]) -> Option<MassProperties> {
+    if !indices.len().is_multiple_of(3)
+        || indices.iter().any(|&index| index >= positions.len())
+        || !closed(indices)
+    {
+        return None;
+    }
+    // Integrals of 1, x, y, z, x^2, y^2, z^2, xy, yz and zx over the volume.
+    let mut integrals = [0.0f64; 10];
+    for corners in indices.chunks(3) {
+        let [p0, p1, p2] = triangle(positions, corners).map(|p| p.map(|v| v as f64));
+        let edge1 = [p1[0] - p0[0], p1[1] - p0[1], p1[2] - p0[2]];
+        let edge2 = [p2[0] - p0[0], p2[1] - p0[1], p2[2] - p0[2]];
+        let d = [
+            edge1[1] * edge2[2] - edge2[1] * edge1[2],
+            edge2[0] * edge1[2] - edge1[0] * edge2[2],
+            edge1[0] * edge2[1] - edge2[0] * edge1[1],
+        ];
+        let x = subexpressions(p0[0], p1[0], p2[0]);
+        let y = subexpressions(p0[1], p1[1], p2[1]);
+        let z = subexpressions(p0[2], p1[2], p2[2]);
+        integrals[0] += d[0] * x.f1;
+        integrals[1] += d[0] * x.f2;
+        integrals[2] += d[1] * y.f2;
+        integrals[3] += d[2] * z.f2;
+        integrals[4] += d[0] * x.f3;
+        integrals[5] += d[1] * y.f3;
+        integrals[6] += d[2] * z.f3;
+        integrals[7] += d[0] * (p0[1] * x.g[0] + p1[1] * x.g[1] + p2[1] * x.g[2]);
+        integrals[8] += d[1] * (p0[2] * y.g[0] + p1[2] * y.g[1] + p2[2] * y.g[2]);
+        integrals[9] += d[2] * (p0[0] * z.g[0] + p1[0] * z.g[1] + p2[0] * z.g[2]);
+    }
+    let factors = [
+        1.0 / 6.0,
+        1.0 / 24.0,
+        1.0 / 24.0,
+        1.0 / 24.0,
+        1.0 / 60.0,
+        1.0 / 60.0,
+        1.0 / 60.0,
+        1.0 / 120.0,
+        1.0 / 120.0,
+        1.0 / 120.0,
+    ];
+    for (integral, factor) in integrals.iter_mut().zip(&factors) {
+        *integral *= factor;
+    }
+    let volume = integrals[0];
+    if volume <= 0.0 {
+        return None;
+    }
+    let center = [
+        integrals[1] / volume,
+        integrals[2] / volume,
+        integrals[3] / volume,
+    ];
+    // Moments about the origin, moved to the center of mass.
+    let xx = integrals[4] - volume * center[0] * center[0];
+    let yy = integrals[5] - volume * center[1] * center[1];
+    let zz = integrals[6] - volume * center[2] * center[2];
+    let xy = integrals[7] - volume * center[0] * center[1];
+    let yz = integrals[8] - volume * center[1] * center[2];
+    let zx = integrals[9] - volume * center[2] * center[0];
+    Some(MassProperties {
+        volume: volume as f32,
+        center_of_mass: center.map(|v| v as f32),
+        inertia: tensor(yy + zz, xx + zz, xx + yy, xy, yz, zx),
+    })
+}
+
+// Every edge is used once in each direction, so that there's no hole and no
+// flipped triangle for the volume to leak through.
+fn closed(indices: &[usize]) -> bool {
+    let mut edges = HashMap::new();
+    for corners in indices.chunks(3) {
+        for i in 0..3 {
+            let (a, b) = (corners[i], corners[(i + 1) % 3]);
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += if a < b { 1 } else { -1 };
+        }
+    }
+    edges.values().all(|&balance| balance == 0)
+}
+
+// Estimate of the surface area from the grid alone: the surface's share of
+// every voxel it passes through.
+pub fn grid_surface_area(grid: &Grid) -> f32 {
+    let size = voxel_size(grid);
+    let voxel_volume = grid.spacing[0] * grid.spacing[1] * grid.spacing[2];
+    let mut area = 0.0;
+    for z in 0..grid.size[2] {
+        for y in 0..grid.size[1] {
+            for x in 0..grid.size[0] {
+                // A band of surface one voxel thick, with area = volume /
+                // thickness.
+                if distance(grid, x, y, z).abs() < size * 0.5 {
+                    area += voxel_volume / size;
+                }
+            }
+        }
+    }
+    area
+}
+
+// Estimate of mass_properties from the grid alone. Samples well inside count
+// as full voxels, and ones within half a voxel of the surface as partly full,
+// going by their (gradient corrected) distance to it.
+pub fn grid_mass_properties(grid: &Grid) -> MassProperties {
+    let size = voxel_size(grid);
+    let voxel_volume = grid.spacing[0] * grid.spacing[1] * grid.spacing[2];
+    let mut fills = Vec::with_capacity(grid.data.len());
+    let mut volume = 0.0f64;
+    let mut moment = [0.0f64; 3];
+    for z in 0..grid.size[2] {
+        for y in 0..grid.size[1] {
+            for x in 0..grid.size[0] {
+                let fill = (0.5 - distance(grid, x, y, z) / size).clamp(0.0, 1.0) as f64;
+                let mass = fill * voxel_volume as f64;
+                let position = grid.position(x, y, z);
+                volume += mass;
+                for axis in 0..3 {
+                    moment[axis] += mass * position[axis] as f64;
+                }
+                fills.push(mass);
+            }
+        }
+    }
+    if volume == 0.0 {
+        return MassProperties {
+            volume: 0.0,
+            center_of_mass: [0.0; 3],
+            inertia: [[0.0; 3]; 3],
+        };
+    }
+    let center = moment.map(|value| value / volume);
+    // Every voxel as a small box: its own inertia, plus the parallel axis
+    // term for its offset from the center of mass.
+    let own = grid
+        .spacing
+        .map(|spacing| spacing as f64 * spacing as f64 / 12.0);
+    let (mut xx, mut yy, mut zz, mut xy, mut yz, mut zx) = (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+    let mut masses = fills.into_iter();
+    for z in 0..grid.size[2] {
+        for y in 0..grid.size[1] {
+            for x in 0..grid.size[0] {
+                let mass = masses.next().unwrap();
+                if mass == 0.0 {
+                    continue;
+                }
+                let position = grid.position(x, y, z);
+                let r = [
+                    position[0] as f64 - center[0],
+                    position[1] as f64 - center[1],
+                    position[2] as f64 - center[2],
+                ];
+                xx += mass * (r[0] * r[0] + own[0]);
+                yy += mass * (r[1] * r[1] + own[1]);
+                zz += mass * (r[2] * r[2] + own[2]);
+                xy += mass * r[0] * r[1];
+                yz += mass * r[1] * r[2];
+                zx += mass * r[2] * r[0];
+            }
+        }
+    }
+    MassProperties {
+        volume: volume as f32,
+        center_of_mass: center.map(|v| v as f32),
+        inertia: tensor(yy + zz, xx + zz, xx + yy, xy, yz, zx),
+    }
+}
+
+struct Subexpressions {
+    f1: f64,
+    f2: f64,
+    f3: f64,
+    g: [f64; 3],
+}
+
+fn subexpressions(w0: f64, w1: f64, w2: f64) -> Subexpressions {
+    let temp0 = w0 + w1;
+    let f1 = temp0 + w2;
+    let temp1 = w0 * w0;
+    let temp2 = temp1 + w1 * temp0;
+    let f2 = temp2 + w2 * f1;
+    let f3 = w0 * temp1 + w1 * temp2 + w2 * f2;
+    Subexpressions {
+        f1,
+        f2,
+        f3,
+        g: [
+            f2 + w0 * (f1 + w0),
+            f2 + w1 * (f1 + w1),
+            f2 + w2 * (f1 + w2),
+        ],
+    }
+}
+
+// Inertia tensor from its diagonal and the (positive) products of inertia.
+fn tensor(xx: f64, yy: f64, zz: f64, xy: f64, yz: f64, zx: f64) -> [[f32; 3]; 3] {
+    [
+        [xx as f32, -xy as f32, -zx as f32],
+        [-xy as f32, yy as f32, -yz as f32],
+        [-zx as f32, -yz as f32, zz as f32],
+    ]
+}
+
+// Edge length of a cube with the volume of one voxel.
+fn voxel_size(grid: &Grid) -> f32 {
+    (grid.spacing[0] * grid.spacing[1] * grid.spacing[2]).cbrt()
+}
+
+// The sample's value divided by the field's gradient there, which is the
+// distance to the surface for any field that's smooth near it, not just for
+// exact distance fields.
+fn distance(grid: &Grid, x: usize, y: usize, z: usize) -> f32 {
+    let (x, y, z) = (x as isize, y as isize, z as isize);
+    let value = grid.get(x, y, z);
+    let gradient = [
+        (grid.get(x + 1, y, z) - grid.get(x - 1, y, z)) / (2.0 * grid.spacing[0]),
+        (grid.get(x, y + 1, z) - grid.get(x, y - 1, z)) / (2.0 * grid.spacing[1]),
+        (grid.get(x, y, z + 1) - grid.get(x, y, z - 1)) / (2.0 * grid.spacing[2]),
+    ];
+    let length =
+        (gradient[0] * gradient[0] + gradient[1] * gradient[1] + gradient[2] * gradient[2]).sqrt();
+    if length == 0.0 {
+        value.signum() * f32::INFINITY
+    } else {
+        value / length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use test_meshes::sphere;
+
+    // An outwards-wound 3 x 2 x 2 box around (1, 2, 3).
+    fn cuboid() -> (Vec<[f32; 3]>, Vec<usize>) {
+        let positions = (0..8)
+            .map(|i| {
+                [
+                    if i & 1 == 0 { -0.5 } else { 2.5 },
+                    if i & 2 == 0 { 1.0 } else { 3.0 },
+                    if i & 4 == 0 { 2.0 } else { 4.0 },
+                ]
+            })
+            .collect();
+        let indices = vec![
+            0, 4, 6, 0, 6, 2, 1, 3, 7, 1, 7, 5, 0, 1, 5, 0, 5, 4, 2, 6, 7, 2, 7, 3, 0, 2, 3, 0, 3,
+            1, 4, 5, 7, 4, 7, 6,
+        ];
+        (positions, indices)
+    }
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance * expected.abs().max(1.0),
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    // The box and sphere have diagonal inertia tensors. Off the diagonal,
+    // the tolerance is relative to the diagonal.
+    fn assert_tensor(inertia: [[f32; 3]; 3], diagonal: [f32; 3], tolerance: f32) {
+        for (row, values) in inertia.iter().enumerate() {
+            for (column, &value) in values.iter().enumerate() {
+                if row == column {
+                    assert_near(value, diagonal[row], tolerance);
+                } else {
+                    assert!(value.abs() <= tolerance * diagonal[row], "{:?}", inertia);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn box_mesh() {
+        let (positions, indices) = cuboid();
+        assert_near(surface_area(&positions, &indices), 32.0, 1e-5);
+        assert_near(enclosed_volume(&positions, &indices), 12.0, 1e-5);
+        let properties = mass_properties(&positions, &indices).unwrap();
+        assert_near(properties.volume, 12.0, 1e-5);
+        for (&center, &expected) in properties.center_of_mass.iter().zip(&[1.0, 2.0, 3.0]) {
+            assert_near(center, expected, 1e-5);
+        }
+        // mass / 12 * (b^2 + c^2) and so on.
+        assert_tensor(properties.inertia, [8.0, 13.0, 13.0], 1e-4);
+    }
+
+    #[test]
+    fn sphere_mesh() {
+        // The mesh sits a little inside the radius 5.5 sphere, so compare
+        // with a sphere of the same volume instead.
+        let (positions, indices) = sphere();
+        let properties = mass_properties(&positions, &indices).unwrap();
+        assert_near(
+            properties.volume,
+            enclosed_volume(&positions, &indices),
+            1e-4,
+        );
+        let radius = (properties.volume * 3.0 / (4.0 * PI)).cbrt();
+        assert!(radius > 5.2 && radius < 5.5, "{}", radius);
+        for &center in &properties.center_of_mass {
+            assert_near(center, 8.0, 1e-3);
+        }
+        let moment = 0.4 * properties.volume * radius * radius;
+        assert_tensor(properties.inertia, [moment; 3], 0.01);
+        assert_near(
+            surface_area(&positions, &indices),
+            4.0 * PI * radius * radius,
+            0.02,
+        );
+    }
+
+    #[test]
+    fn inside_out_and_open_meshes() {
+        let (positions, mut indices) = cuboid();
+        for corners in indices.chunks_mut(3) {
+            corners.swap(1, 2);
+        }
+        assert_near(enclosed_volume(&positions, &indices), -12.0, 1e-5);
+        assert_near(surface_area(&positions, &indices), 32.0, 1e-5);
+        assert_eq!(mass_properties(&positions, &indices), None);
+
+        let (positions, indices) = cuboid();
+        assert_eq!(mass_properties(&positions, &indices[3..]), None);
+        assert_eq!(mass_properties(&positions, &indices[1..]), None);
+        let mut flipped = indices.clone();
+        flipped.swap(0, 1);
+        assert_eq!(mass_properties(&positions, &flipped), None);
+        assert_eq!(mass_properties(&positions, &[]), None);
+    }
+
+    fn sampled(field: &dyn Fn([f32; 3]) -> f32) -> Grid {
+        let mut grid = Grid::new([29, 29, 29], [-7.0, -7.0, -7.0], [0.5; 3]);
+        for z in 0..29 {
+            for y in 0..29 {
+                for x in 0..29 {
+                    let value = field(grid.position(x, y, z));
+                    grid.set(x, y, z, value);
+                }
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn sphere_grid() {
+        let offset = [0.3, -0.6, 0.2];
+        let grid = sampled(&|p| {
+            let (x, y, z) = (p[0] - offset[0], p[1] - offset[1], p[2] - offset[2]);
+            (x * x + y * y + z * z).sqrt() - 5.0
+        });
+        let properties = grid_mass_properties(&grid);
+        let volume = 4.0 / 3.0 * PI * 125.0;
+        assert_near(properties.volume, volume, 0.02);
+        for (&center, &expected) in properties.center_of_mass.iter().zip(&offset) {
+            assert!((center - expected).abs() < 0.02, "{:?}", properties);
+        }
+        assert_tensor(properties.inertia, [0.4 * volume * 25.0; 3], 0.05);
+        assert_near(grid_surface_area(&grid), 4.0 * PI * 25.0, 0.05);
+    }
+
+    #[test]
+    fn box_grid() {
+        // Faces at different offsets from the samples.
+        let half = [3.1, 2.2, 2.35];
+        let grid = sampled(&|p| {
+            let q = [
+                p[0].abs() - half[0],
+                p[1].abs() - half[1],
+                p[2].abs() - half[2],
+            ];
+            let outside = q.iter().map(|&q| q.max(0.0).powi(2)).sum::<f32>().sqrt();
+            outside + q[0].max(q[1]).max(q[2]).min(0.0)
+        });
+        let properties = grid_mass_properties(&grid);
+        let [a, b, c] = half.map(|half| 2.0 * half);
+        let volume = a * b * c;
+        assert_near(properties.volume, volume, 0.02);
+        for &center in &properties.center_of_mass {
+            assert!(center.abs() < 1e-3);
+        }
+        let moments = [b * b + c * c, a * a + c * c, a * a + b * b].map(|sum| volume / 12.0 * sum);
+        assert_tensor(properties.inertia, moments, 0.05);
+        // Samples along the edges are shared by two faces, so the band comes
+        // up short on a box.
+        let area = grid_surface_area(&grid);
+        let expected = 2.0 * (a * b + b * c + c * a);
+        assert!(area < expected);
+        assert_near(area, expected, 0.15);
+    }
+}
@@ -0,0 +1,134 @@
+// Tiny vector helpers shared by the mesh processing modules.
+
+pub fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: [f32; 3], factor: f32) -> [f32; 3] {
+    [a[0] * factor, a[1] * factor, a[2] * factor]
+}
+
+pub fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn length(a: [f32; 3]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+// Returns the zero vector for zero-length input rather than NaNs.
+pub fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = length(a);
+    if length == 0.0 {
+        a
+    } else {
+        scale(a, 1.0 / length)
+    }
+}
+
+pub fn triangle(positions: &[[f32; 3]], indices: &[usize]) -> [[f32; 3]; 3] {
+    [
+        positions[indices[0]],
+        positions[indices[1]],
+        positions[indices[2]],
+    ]
+}
+
+// Closest point to p on the triangle abc. From Christer Ericson's "Real-Time
+// Collision Detection", section 5.1.5.
+pub fn closest_point_on_triangle(p: [f32; 3], [a, b, c]: [[f32; 3]; 3]) -> [f32; 3] {
+    let ab = sub(b, a);
+    let ac = sub(c, a);
+    let ap = sub(p, a);
+    let d1 = dot(ab, ap);
+    let d2 = dot(ac, ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = sub(p, b);
+    let d3 = dot(ab, bp);
+    let d4 = dot(ac, bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return add(a, scale(ab, d1 / (d1 - d3)));
+    }
+    let cp = sub(p, c);
+    let d5 = dot(ab, cp);
+    let d6 = dot(ac, cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return add(a, scale(ac, d2 / (d2 - d6)));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return add(b, scale(sub(c, b), (d4 - d3) / ((d4 - d3) + (d5 - d6))));
+    }
+    let denominator = 1.0 / (va + vb + vc);
+    add(
+        a,
+        add(scale(ab, vb * denominator), scale(ac, vc * denominator)),
+    )
+}
+
+pub fn triangle_area([a, b, c]: [[f32; 3]; 3]) -> f32 {
+    length(cross(sub(b, a), sub(c, a))) * 0.5
+}
+
+// Volume of the tetrahedron between the triangle and the origin, positive if
+// the triangle faces away from the origin. Summed over a closed mesh, that's
+// the volume it encloses.
+pub fn signed_volume([a, b, c]: [[f32; 3]; 3]) -> f32 {
+    dot(a, cross(b, c)) / 6.0
+}
+
+// Distance along the ray (origin + t * direction) to where it hits the
+// triangle, from either side. Möller and Trumbore's "Fast, Minimum Storage
+// Ray/Triangle Intersection".
+pub fn ray_triangle(
+    origin: [f32; 3],
+    direction: [f32; 3],
+    [a, b, c]: [[f32; 3]; 3],
+) -> Option<f32> {
+    let edge1 = sub(b, a);
+    let edge2 = sub(c, a);
+    let p = cross(direction, edge2);
+    let determinant = dot(edge1, p);
+    if determinant == 0.0 {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let to_origin = sub(origin, a);
+    let u = dot(to_origin, p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = cross(to_origin, edge1);
+    let v = dot(direction, q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = dot(edge2, q) * inverse;
+    if t >= 0.0 {
+        Some(t)
+    } else {
+        None
+    }
+}
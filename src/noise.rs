@@ -0,0 +1,94 @@
+// Seedable 3d gradient noise (Ken Perlin's "improved noise"), so that nothing
+// in this crate needs an external noise crate. Output is roughly in [-1, 1].
+
+pub struct Perlin {
+    permutation: Vec<u8>,
+}
+
+impl Perlin {
+    pub fn new(seed: u32) -> Perlin {
+        let mut table = (0..=255).collect::<Vec<u8>>();
+        // Fisher-Yates shuffle driven by a tiny xorshift generator. The exact
+        // generator doesn't matter, it just needs to be deterministic.
+        let mut state = seed as u64 ^ 0x9E37_79B9_7F4A_7C15;
+        for i in (1..table.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let j = (state % (i as u64 + 1)) as usize;
+            table.swap(i, j);
+        }
+        let mut permutation = table.clone();
+        permutation.extend_from_slice(&table);
+        Perlin { permutation }
+    }
+
+    pub fn get(&self, point: [f32; 3]) -> f32 {
+        let floor = [point[0].floor(), point[1].floor(), point[2].floor()];
+        let cell = [
+            (floor[0] as i32 & 255) as usize,
+            (floor[1] as i32 & 255) as usize,
+            (floor[2] as i32 & 255) as usize,
+        ];
+        let x = point[0] - floor[0];
+        let y = point[1] - floor[1];
+        let z = point[2] - floor[2];
+        let u = fade(x);
+        let v = fade(y);
+        let w = fade(z);
+        let p = &self.permutation;
+        let a = p[cell[0]] as usize + cell[1];
+        let aa = p[a] as usize + cell[2];
+        let ab = p[a + 1] as usize + cell[2];
+        let b = p[cell[0] + 1] as usize + cell[1];
+        let ba = p[b] as usize + cell[2];
+        let bb = p[b + 1] as usize + cell[2];
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad(p[aa], x, y, z), grad(p[ba], x - 1.0, y, z)),
+                lerp(
+                    u,
+                    grad(p[ab], x, y - 1.0, z),
+                    grad(p[bb], x - 1.0, y - 1.0, z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(p[aa + 1], x, y, z - 1.0),
+                    grad(p[ba + 1], x - 1.0, y, z - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(p[ab + 1], x, y - 1.0, z - 1.0),
+                    grad(p[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+// Dot product of (x, y, z) with one of the 12 cube-edge gradient directions.
+fn grad(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
@@ -0,0 +1,179 @@
+// Wavefront OBJ export, mostly so meshes can be looked at in Blender and
+// friends.
+
+use std::io::{self, Write};
+
+// Per-triangle materials. OBJ only stores material names, the materials
+// themselves live in a separate .mtl file (see write_mtl).
+pub struct ObjMaterials<'a> {
+    // File name of the .mtl library, as it should be referenced from the .obj.
+    pub library: &'a str,
+    pub names: &'a [&'a str],
+    // One index into `names` per triangle.
+    pub triangle_materials: &'a [usize],
+}
+
+// Writes the mesh as it comes out of surface_net. UVs, if given, are
+// per-vertex like the normals. OBJ indices are 1-based, surface_net's are
+// 0-based, this takes care of the difference.
+// Meshes whose parts don't fit together (a missing normal, an index past the
+// last vertex, ...) are refused with an InvalidInput error before anything is
+// written.
+pub fn write_obj<W: Write>(
+    writer: &mut W,
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    uvs: Option<&[[f32; 2]]>,
+    indices: &[usize],
+    materials: Option<&ObjMaterials>,
+) -> io::Result<()> {
+    if normals.len() != positions.len() {
+        return Err(invalid_input("mesh needs one normal per vertex"));
+    }
+    if uvs.is_some_and(|uvs| uvs.len() != positions.len()) {
+        return Err(invalid_input("mesh needs one UV per vertex"));
+    }
+    if !indices.len().is_multiple_of(3) || indices.iter().any(|&index| index >= positions.len()) {
+        return Err(invalid_input(
+            "mesh indices are not triangles of its vertices",
+        ));
+    }
+    if let Some(materials) = materials {
+        if materials.triangle_materials.len() != indices.len() / 3
+            || materials
+                .triangle_materials
+                .iter()
+                .any(|&material| material >= materials.names.len())
+        {
+            return Err(invalid_input("mesh needs one known material per triangle"));
+        }
+    }
+    writeln!(writer, "# surface-nets")?;
+    if let Some(materials) = materials {
+        writeln!(writer, "mtllib {}", materials.library)?;
+    }
+    for p in positions {
+        writeln!(writer, "v {} {} {}", p[0], p[1], p[2])?;
+    }
+    if let Some(uvs) = uvs {
+        for uv in uvs {
+            writeln!(writer, "vt {} {}", uv[0], uv[1])?;
+        }
+    }
+    for n in normals {
+        writeln!(writer, "vn {} {} {}", n[0], n[1], n[2])?;
+    }
+    let mut current_material = None;
+    for (triangle, corners) in indices.chunks(3).enumerate() {
+        if let Some(materials) = materials {
+            let material = materials.triangle_materials[triangle];
+            if current_material != Some(material) {
+                writeln!(writer, "usemtl {}", materials.names[material])?;
+                current_material = Some(material);
+            }
+        }
+        write!(writer, "f")?;
+        for &index in corners {
+            let index = index + 1;
+            if uvs.is_some() {
+                write!(writer, " {}/{}/{}", index, index, index)?;
+            } else {
+                write!(writer, " {}//{}", index, index)?;
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+// A minimal .mtl library to go with write_obj: one flat diffuse color per
+// material.
+pub fn write_mtl<W: Write>(writer: &mut W, materials: &[(&str, [f32; 3])]) -> io::Result<()> {
+    for &(name, color) in materials {
+        writeln!(writer, "newmtl {}", name)?;
+        writeln!(writer, "Kd {} {} {}", color[0], color[1], color[2])?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.5, 0.0]];
+    const NORMALS: [[f32; 3]; 3] = [[0.0, 0.0, 1.0]; 3];
+
+    #[test]
+    fn round_trip() {
+        let uvs = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]];
+        let materials = ObjMaterials {
+            library: "mesh.mtl",
+            names: &["red", "blue"],
+            triangle_materials: &[1],
+        };
+        let mut output = Vec::new();
+        write_obj(
+            &mut output,
+            &POSITIONS,
+            &NORMALS,
+            Some(&uvs),
+            &[0, 1, 2],
+            Some(&materials),
+        )
+        .unwrap();
+        let text = String::from_utf8(output).unwrap();
+        let mut positions = Vec::new();
+        let mut faces = Vec::new();
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("v") => positions.push(
+                    words
+                        .map(|word| word.parse::<f32>().unwrap())
+                        .collect::<Vec<_>>(),
+                ),
+                Some("f") => faces.extend(words.map(String::from)),
+                _ => {}
+            }
+        }
+        assert_eq!(positions.len(), 3);
+        for (read, written) in positions.iter().zip(&POSITIONS) {
+            assert_eq!(&read[..], &written[..]);
+        }
+        assert_eq!(faces, ["1/1/1", "2/2/2", "3/3/3"]);
+        assert!(text.contains("mtllib mesh.mtl\n"));
+        assert!(text.contains("usemtl blue\n"));
+        assert_eq!(text.matches("vn ").count(), 3);
+        assert_eq!(text.matches("vt ").count(), 3);
+    }
+
+    #[test]
+    fn inconsistent_meshes_are_refused() {
+        let materials = ObjMaterials {
+            library: "mesh.mtl",
+            names: &["red"],
+            triangle_materials: &[1],
+        };
+        let uvs = [[0.0, 0.0]];
+        let refused = |normals: &[[f32; 3]],
+                       uvs: Option<&[[f32; 2]]>,
+                       indices: &[usize],
+                       materials: Option<&ObjMaterials>| {
+            let mut output = Vec::new();
+            let error =
+                write_obj(&mut output, &POSITIONS, normals, uvs, indices, materials).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            assert!(output.is_empty());
+        };
+        refused(&NORMALS[..2], None, &[0, 1, 2], None);
+        refused(&NORMALS, Some(&uvs), &[0, 1, 2], None);
+        refused(&NORMALS, None, &[0, 1], None);
+        refused(&NORMALS, None, &[0, 1, 3], None);
+        refused(&NORMALS, None, &[0, 1, 2], Some(&materials));
+    }
+}
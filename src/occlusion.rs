@@ -0,0 +1,175 @@
+// Per-vertex ambient occlusion baked from the field the mesh came from.
+//
+// Uses the usual distance field trick: a point h away from the surface along
+// an open normal should have a field value of about h, and anything less
+// means there's geometry nearby. That's checked at a few steps along the
+// normal and along directions spread over the hemisphere around it, with
+// closer steps and directions nearer the normal counting for more.
+// This assumes the field is roughly a distance (like the scene and terrain
+// fields are); for fields that are scaled, adjust `strength`.
+
+use field::Field;
+use math::{add, cross, normalize, scale};
+
+pub struct Occlusion {
+    // How far from the surface to look, in the field's units.
+    pub distance: f32,
+    // Samples along each direction.
+    pub steps: usize,
+    // Extra directions over the hemisphere, on top of the normal itself.
+    pub directions: usize,
+    pub strength: f32,
+}
+
+impl Occlusion {
+    pub fn new(distance: f32) -> Occlusion {
+        Occlusion {
+            distance,
+            steps: 5,
+            directions: 8,
+            strength: 1.0,
+        }
+    }
+}
+
+// Returns 1 for fully open vertices, down to 0 for fully occluded ones. With
+// no distance to look over (zero or less) every vertex is open.
+// Positions must be in the field's space: for surface_net output sampled with
+// grid_sampler, map them with grid_to_world first.
+// None if there isn't exactly one normal per position.
+pub fn ambient_occlusion(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    field: &dyn Field,
+    occlusion: &Occlusion,
+) -> Option<Vec<f32>> {
+    if normals.len() != positions.len() {
+        return None;
+    }
+    if occlusion.distance.is_nan() || occlusion.distance <= 0.0 {
+        return Some(vec![1.0; positions.len()]);
+    }
+    let hemisphere = hemisphere_directions(occlusion.directions);
+    let values = positions
+        .iter()
+        .zip(normals)
+        .map(|(&position, &normal)| {
+            let (tangent, bitangent) = basis(normal);
+            // The normal itself, then the hemisphere directions rotated into
+            // the vertex's frame, each weighted by its cosine to the normal.
+            let mut total = 0.0;
+            let mut weights = 0.0;
+            let directions = Some([0.0, 0.0, 1.0])
+                .into_iter()
+                .chain(hemisphere.iter().cloned());
+            for local in directions {
+                let direction = add(
+                    add(scale(tangent, local[0]), scale(bitangent, local[1])),
+                    scale(normal, local[2]),
+                );
+                total += local[2] * march(field, position, direction, local[2], occlusion);
+                weights += local[2];
+            }
+            (1.0 - occlusion.strength * total / weights).clamp(0.0, 1.0)
+        })
+        .collect();
+    Some(values)
+}
+
+// Occlusion along one direction, 0 (open) to about 1 (blocked right away).
+// The vertex's own tangent plane is only h * cosine away from a point h along
+// a tilted direction, so that's the value an open surface gives.
+fn march(
+    field: &dyn Field,
+    position: [f32; 3],
+    direction: [f32; 3],
+    cosine: f32,
+    occlusion: &Occlusion,
+) -> f32 {
+    let mut occluded = 0.0;
+    let mut weight = 1.0;
+    let mut weights = 0.0;
+    for step in 1..=occlusion.steps {
+        let h = occlusion.distance * step as f32 / occlusion.steps as f32;
+        let open = h * cosine;
+        let value = field.get(add(position, scale(direction, h)));
+        occluded += weight * ((open - value) / open).clamp(0.0, 1.0);
+        weights += weight;
+        weight *= 0.5;
+    }
+    if weights == 0.0 {
+        0.0
+    } else {
+        occluded / weights
+    }
+}
+
+// Evenly spread unit directions over the +Z hemisphere (a Fibonacci spiral),
+// staying clear of the horizon, where samples would just hit the surface the
+// vertex is on.
+fn hemisphere_directions(count: usize) -> Vec<[f32; 3]> {
+    let golden_angle = ::std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+    (0..count)
+        .map(|i| {
+            let z = 1.0 - 0.8 * (i as f32 + 0.5) / count as f32;
+            let radius = (1.0 - z * z).sqrt();
+            let angle = golden_angle * i as f32;
+            [radius * angle.cos(), radius * angle.sin(), z]
+        })
+        .collect()
+}
+
+// Any two unit vectors perpendicular to the normal and each other.
+fn basis(normal: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+    let other = if normal[0].abs() < 0.9 {
+        [1.0, 0.0, 0.0]
+    } else {
+        [0.0, 1.0, 0.0]
+    };
+    let tangent = normalize(cross(normal, other));
+    (tangent, cross(normal, tangent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Solid below y = 0 and, for the corner, behind x = 0 as well.
+    fn floor(point: [f32; 3]) -> f32 {
+        point[1]
+    }
+
+    fn corner(point: [f32; 3]) -> f32 {
+        point[0].min(point[1])
+    }
+
+    #[test]
+    fn open_on_a_plane_and_occluded_in_a_corner() {
+        let occlusion = Occlusion::new(2.0);
+        let up = [0.0, 1.0, 0.0];
+        let open = ambient_occlusion(&[[3.0, 0.0, 1.0]], &[up], &floor, &occlusion).unwrap();
+        assert!((open[0] - 1.0).abs() < 1e-5, "{}", open[0]);
+
+        // Right in the corner, half way along the floor, and far away from
+        // the wall.
+        let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [10.0, 0.0, 0.0]];
+        let open = ambient_occlusion(&positions, &[up; 3], &corner, &occlusion).unwrap();
+        assert!(open[0] < open[1] && open[1] < 1.0, "{:?}", open);
+        assert!((open[2] - 1.0).abs() < 1e-5, "{:?}", open);
+
+        let mut stronger = Occlusion::new(2.0);
+        stronger.strength = 2.0;
+        let darker = ambient_occlusion(&positions, &[up; 3], &corner, &stronger).unwrap();
+        assert!(darker[0] < open[0]);
+
+        let none = ambient_occlusion(&positions, &[up; 3], &corner, &Occlusion::new(0.0));
+        assert_eq!(none, Some(vec![1.0; 3]));
+    }
+
+    #[test]
+    fn refuses_missing_normals() {
+        let occlusion = Occlusion::new(2.0);
+        let up = [0.0, 1.0, 0.0];
+        assert!(ambient_occlusion(&[[0.0; 3]; 2], &[up], &floor, &occlusion).is_none());
+    }
+}
@@ -0,0 +1,248 @@
+// Stanford PLY export, ASCII or binary, for the scanning and printing tools
+// that would rather have that than OBJ.
+
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+// Colors and material IDs are optional per-vertex properties, normals are
+// always written. Every property needs one value per vertex, otherwise this
+// returns an InvalidInput error without writing anything. So do indices that
+// don't fit in 32 bits.
+pub fn write_ply<W: Write>(
+    writer: &mut W,
+    format: PlyFormat,
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    colors: Option<&[[u8; 3]]>,
+    material_ids: Option<&[u32]>,
+    indices: &[usize],
+) -> io::Result<()> {
+    if normals.len() != positions.len() {
+        return Err(invalid_input("mesh needs one normal per vertex"));
+    }
+    if colors.is_some_and(|colors| colors.len() != positions.len()) {
+        return Err(invalid_input("mesh needs one color per vertex"));
+    }
+    if material_ids.is_some_and(|material_ids| material_ids.len() != positions.len()) {
+        return Err(invalid_input("mesh needs one material ID per vertex"));
+    }
+    if !indices.len().is_multiple_of(3) || indices.iter().any(|&index| index >= positions.len()) {
+        return Err(invalid_input(
+            "mesh indices are not triangles of its vertices",
+        ));
+    }
+    // The header declares the indices as uint, for ASCII as well.
+    if indices.iter().any(|&index| index > u32::MAX as usize) {
+        return Err(invalid_input(
+            "mesh has too many vertices for 32-bit indices",
+        ));
+    }
+    writeln!(writer, "ply")?;
+    match format {
+        PlyFormat::Ascii => writeln!(writer, "format ascii 1.0")?,
+        PlyFormat::BinaryLittleEndian => writeln!(writer, "format binary_little_endian 1.0")?,
+    }
+    writeln!(writer, "comment surface-nets")?;
+    writeln!(writer, "element vertex {}", positions.len())?;
+    for name in &["x", "y", "z", "nx", "ny", "nz"] {
+        writeln!(writer, "property float {}", name)?;
+    }
+    if colors.is_some() {
+        for name in &["red", "green", "blue"] {
+            writeln!(writer, "property uchar {}", name)?;
+        }
+    }
+    if material_ids.is_some() {
+        writeln!(writer, "property uint material_id")?;
+    }
+    writeln!(writer, "element face {}", indices.len() / 3)?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")?;
+    for (vertex, (p, n)) in positions.iter().zip(normals).enumerate() {
+        match format {
+            PlyFormat::Ascii => {
+                write!(
+                    writer,
+                    "{} {} {} {} {} {}",
+                    p[0], p[1], p[2], n[0], n[1], n[2]
+                )?;
+                if let Some(colors) = colors {
+                    let c = colors[vertex];
+                    write!(writer, " {} {} {}", c[0], c[1], c[2])?;
+                }
+                if let Some(material_ids) = material_ids {
+                    write!(writer, " {}", material_ids[vertex])?;
+                }
+                writeln!(writer)?;
+            }
+            PlyFormat::BinaryLittleEndian => {
+                for value in p.iter().chain(n) {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+                if let Some(colors) = colors {
+                    writer.write_all(&colors[vertex])?;
+                }
+                if let Some(material_ids) = material_ids {
+                    writer.write_all(&material_ids[vertex].to_le_bytes())?;
+                }
+            }
+        }
+    }
+    for triangle in indices.chunks(3) {
+        match format {
+            PlyFormat::Ascii => {
+                writeln!(writer, "3 {} {} {}", triangle[0], triangle[1], triangle[2])?;
+            }
+            PlyFormat::BinaryLittleEndian => {
+                writer.write_all(&[3])?;
+                for &index in triangle {
+                    writer.write_all(&(index as u32).to_le_bytes())?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.5, -2.0]];
+    const NORMALS: [[f32; 3]; 3] = [[0.0, 0.0, 1.0]; 3];
+    const COLORS: [[u8; 3]; 3] = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+    const MATERIAL_IDS: [u32; 3] = [7, 8, 9];
+
+    fn write(format: PlyFormat) -> Vec<u8> {
+        let mut output = Vec::new();
+        write_ply(
+            &mut output,
+            format,
+            &POSITIONS,
+            &NORMALS,
+            Some(&COLORS),
+            Some(&MATERIAL_IDS),
+            &[0, 1, 2],
+        )
+        .unwrap();
+        output
+    }
+
+    // Splits the output after end_header.
+    fn split_header(output: &[u8]) -> (String, &[u8]) {
+        let marker = b"end_header\n";
+        let end = output
+            .windows(marker.len())
+            .position(|window| window == marker)
+            .unwrap()
+            + marker.len();
+        (
+            String::from_utf8(output[..end].to_vec()).unwrap(),
+            &output[end..],
+        )
+    }
+
+    #[test]
+    fn ascii_round_trip() {
+        let output = write(PlyFormat::Ascii);
+        let (header, body) = split_header(&output);
+        assert!(header.contains("format ascii 1.0\n"));
+        assert!(header.contains("element vertex 3\n"));
+        assert!(header.contains("element face 1\n"));
+        let lines = std::str::from_utf8(body)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        for (vertex, line) in lines[..3].iter().enumerate() {
+            let values = line
+                .split_whitespace()
+                .map(|word| word.parse::<f32>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(&values[..3], &POSITIONS[vertex][..]);
+            assert_eq!(&values[3..6], &NORMALS[vertex][..]);
+            let color = [values[6] as u8, values[7] as u8, values[8] as u8];
+            assert_eq!(color, COLORS[vertex]);
+            assert_eq!(values[9] as u32, MATERIAL_IDS[vertex]);
+        }
+        assert_eq!(lines[3], "3 0 1 2");
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let output = write(PlyFormat::BinaryLittleEndian);
+        let (header, body) = split_header(&output);
+        assert!(header.contains("format binary_little_endian 1.0\n"));
+        // Six floats, three color bytes and a material ID per vertex, then a
+        // count byte and three indices per face.
+        let vertex_size = 6 * 4 + 3 + 4;
+        assert_eq!(body.len(), 3 * vertex_size + 1 + 3 * 4);
+        let float = |offset: usize| {
+            f32::from_le_bytes([
+                body[offset],
+                body[offset + 1],
+                body[offset + 2],
+                body[offset + 3],
+            ])
+        };
+        let uint = |offset: usize| {
+            u32::from_le_bytes([
+                body[offset],
+                body[offset + 1],
+                body[offset + 2],
+                body[offset + 3],
+            ])
+        };
+        for vertex in 0..3 {
+            let start = vertex * vertex_size;
+            for axis in 0..3 {
+                assert_eq!(float(start + axis * 4), POSITIONS[vertex][axis]);
+                assert_eq!(float(start + 12 + axis * 4), NORMALS[vertex][axis]);
+            }
+            assert_eq!(body[start + 24..start + 27], COLORS[vertex]);
+            assert_eq!(uint(start + 27), MATERIAL_IDS[vertex]);
+        }
+        let faces = 3 * vertex_size;
+        assert_eq!(body[faces], 3);
+        assert_eq!(
+            [uint(faces + 1), uint(faces + 5), uint(faces + 9)],
+            [0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn inconsistent_meshes_are_refused() {
+        let refused = |normals: &[[f32; 3]],
+                       colors: Option<&[[u8; 3]]>,
+                       material_ids: Option<&[u32]>,
+                       indices: &[usize]| {
+            let mut output = Vec::new();
+            let error = write_ply(
+                &mut output,
+                PlyFormat::Ascii,
+                &POSITIONS,
+                normals,
+                colors,
+                material_ids,
+                indices,
+            )
+            .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            assert!(output.is_empty());
+        };
+        refused(&NORMALS[..1], None, None, &[0, 1, 2]);
+        refused(&NORMALS, Some(&COLORS[..2]), None, &[0, 1, 2]);
+        refused(&NORMALS, None, Some(&MATERIAL_IDS[..2]), &[0, 1, 2]);
+        refused(&NORMALS, None, None, &[0, 1, 2, 0]);
+        refused(&NORMALS, None, None, &[0, 1, 5]);
+    }
+}
@@ -0,0 +1,432 @@
+// Ray queries, for picking the terrain under the cursor and line of sight
+// checks: sphere tracing for continuous fields and walking the cells of a
+// sampled grid. Meshes are raycast through their BVH, see Bvh::raycast.
+//
+// Directions don't need to be normalized; distances are always in world
+// units along the normalized direction. A zero or non-finite direction hits
+// nothing.
+
+use edge_crossing;
+use field::Field;
+use grid::Grid;
+use math::{add, length, normalize, scale, sub};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub position: [f32; 3],
+    // Pointing out of the solid, towards the air.
+    pub normal: [f32; 3],
+    pub distance: f32,
+    // The triangle hit, for mesh raycasts. None for fields and grids.
+    pub triangle: Option<usize>,
+}
+
+pub struct SphereTrace {
+    pub max_distance: f32,
+    // Values below this count as a hit.
+    pub epsilon: f32,
+    pub max_steps: usize,
+    // Fraction of the field's value to step each time. 1 is fine for exact
+    // distance fields, fields that overestimate the distance (noise,
+    // heightmaps with steep slopes, scaled shapes) need less or the ray
+    // tunnels through thin parts.
+    pub step_scale: f32,
+}
+
+impl SphereTrace {
+    pub fn new(max_distance: f32) -> SphereTrace {
+        SphereTrace {
+            max_distance,
+            epsilon: 1e-3,
+            max_steps: 256,
+            step_scale: 1.0,
+        }
+    }
+}
+
+// Sphere tracing (Hart, "Sphere Tracing: A Geometric Method for the
+// Antialiased Ray Tracing of Implicit Surfaces"): steps along the ray by the
+// field's value, which can't skip past the surface as long as the field never
+// overestimates the distance to it. Rays starting inside the solid hit at
+// distance 0. None as well if epsilon or step_scale aren't positive and
+// finite.
+pub fn sphere_trace(
+    field: &dyn Field,
+    origin: [f32; 3],
+    direction: [f32; 3],
+    settings: &SphereTrace,
+) -> Option<Hit> {
+    let positive = |value: f32| value > 0.0 && value.is_finite();
+    if !(positive(length(direction)) && positive(settings.epsilon) && positive(settings.step_scale))
+    {
+        return None;
+    }
+    let direction = normalize(direction);
+    let mut distance = 0.0;
+    for _ in 0..settings.max_steps {
+        let position = add(origin, scale(direction, distance));
+        let value = field.get(position);
+        if value < settings.epsilon {
+            return Some(Hit {
+                position,
+                normal: field_normal(field, position, settings.epsilon),
+                distance,
+                triangle: None,
+            });
+        }
+        distance += value * settings.step_scale;
+        if distance > settings.max_distance {
+            break;
+        }
+    }
+    None
+}
+
+// Walks the grid's cells along the ray (Amanatides and Woo, "A Fast Voxel
+// Traversal Algorithm for Ray Tracing") and stops at the first cell where the
+// interpolated value changes sign between where the ray enters and leaves it.
+// The crossing in between is interpolated linearly like surface_net does
+// along cell edges, so for rays along grid lines the hit is exactly the edge
+// crossing surface_net averages into its vertices. As in Grid::surface_net,
+// the grid is surrounded by background values, so solids touching its edge
+// are closed off.
+pub fn raycast_grid(
+    grid: &Grid,
+    origin: [f32; 3],
+    direction: [f32; 3],
+    max_distance: f32,
+) -> Option<Hit> {
+    let length = length(direction);
+    if !(length > 0.0 && length.is_finite()) {
+        return None;
+    }
+    let direction = normalize(direction);
+    // One cell of background all around the grid.
+    let min = sub(grid.origin, grid.spacing);
+    let cells = [grid.size[0] + 1, grid.size[1] + 1, grid.size[2] + 1];
+    let mut walk = CellWalk::new(min, grid.spacing, cells, origin, direction, max_distance)?;
+    let mut value = grid.sample(add(origin, scale(direction, walk.distance)));
+    if value < 0.0 {
+        let position = add(origin, scale(direction, walk.distance));
+        return Some(Hit {
+            position,
+            normal: grid_normal(grid, position),
+            distance: walk.distance,
+            triangle: None,
+        });
+    }
+    loop {
+        let entry = walk.distance;
+        let exit = walk.exit();
+        let exit_value = grid.sample(add(origin, scale(direction, exit)));
+        if let Some(interp) = edge_crossing(value, exit_value) {
+            let distance = entry + (exit - entry) * interp;
+            let position = add(origin, scale(direction, distance));
+            return Some(Hit {
+                position,
+                normal: grid_normal(grid, position),
+                distance,
+                triangle: None,
+            });
+        }
+        value = exit_value;
+        if !walk.step() {
+            return None;
+        }
+    }
+}
+
+// Steps through the cells of a grid in the order a ray passes through them,
+// keeping track of how far along the ray it is.
+struct CellWalk {
+    cell: [usize; 3],
+    // Distance at which the ray enters the current cell.
+    distance: f32,
+    // Distance at which the ray reaches the next cell boundary on each axis.
+    next: [f32; 3],
+    // Distance between cell boundaries on each axis.
+    delta: [f32; 3],
+    step: [isize; 3],
+    cells: [usize; 3],
+    end: f32,
+}
+
+impl CellWalk {
+    // None if the ray misses the grid within max_distance.
+    fn new(
+        min: [f32; 3],
+        cell_size: [f32; 3],
+        cells: [usize; 3],
+        origin: [f32; 3],
+        direction: [f32; 3],
+        max_distance: f32,
+    ) -> Option<CellWalk> {
+        // Clip the ray to the grid's bounds.
+        let mut start = 0.0f32;
+        let mut end = max_distance;
+        for axis in 0..3 {
+            let low = min[axis];
+            let high = min[axis] + cell_size[axis] * cells[axis] as f32;
+            if direction[axis] == 0.0 {
+                if origin[axis] < low || origin[axis] > high {
+                    return None;
+                }
+            } else {
+                let a = (low - origin[axis]) / direction[axis];
+                let b = (high - origin[axis]) / direction[axis];
+                start = start.max(a.min(b));
+                end = end.min(a.max(b));
+            }
+        }
+        if start > end {
+            return None;
+        }
+        let entry = add(origin, scale(direction, start));
+        let mut cell = [0; 3];
+        let mut next = [f32::INFINITY; 3];
+        let mut delta = [f32::INFINITY; 3];
+        let mut step = [0; 3];
+        for axis in 0..3 {
+            cell[axis] = cell_of(entry[axis], min[axis], cell_size[axis], cells[axis]);
+            if direction[axis] > 0.0 {
+                step[axis] = 1;
+                let boundary = min[axis] + (cell[axis] + 1) as f32 * cell_size[axis];
+                next[axis] = (boundary - origin[axis]) / direction[axis];
+                delta[axis] = cell_size[axis] / direction[axis];
+            } else if direction[axis] < 0.0 {
+                step[axis] = -1;
+                let boundary = min[axis] + cell[axis] as f32 * cell_size[axis];
+                next[axis] = (boundary - origin[axis]) / direction[axis];
+                delta[axis] = -cell_size[axis] / direction[axis];
+            }
+        }
+        Some(CellWalk {
+            cell,
+            distance: start,
+            next,
+            delta,
+            step,
+            cells,
+            end,
+        })
+    }
+
+    // Distance at which the ray leaves the current cell (or stops).
+    fn exit(&self) -> f32 {
+        self.next[0]
+            .min(self.next[1])
+            .min(self.next[2])
+            .min(self.end)
+    }
+
+    // Moves to the next cell, false once the ray leaves the grid or reaches
+    // its end.
+    fn step(&mut self) -> bool {
+        let axis = if self.next[0] <= self.next[1] && self.next[0] <= self.next[2] {
+            0
+        } else if self.next[1] <= self.next[2] {
+            1
+        } else {
+            2
+        };
+        if self.next[axis] >= self.end {
+            return false;
+        }
+        let cell = self.cell[axis] as isize + self.step[axis];
+        if cell < 0 || cell as usize >= self.cells[axis] {
+            return false;
+        }
+        self.cell[axis] = cell as usize;
+        self.distance = self.next[axis];
+        self.next[axis] += self.delta[axis];
+        true
+    }
+}
+
+fn cell_of(value: f32, min: f32, cell_size: f32, cells: usize) -> usize {
+    (((value - min) / cell_size).floor().max(0.0) as usize).min(cells - 1)
+}
+
+// Normalized gradient by central differences.
+fn field_normal(field: &dyn Field, point: [f32; 3], step: f32) -> [f32; 3] {
+    let mut gradient = [0.0; 3];
+    for (axis, value) in gradient.iter_mut().enumerate() {
+        let mut high = point;
+        let mut low = point;
+        high[axis] += step;
+        low[axis] -= step;
+        *value = field.get(high) - field.get(low);
+    }
+    normalize(gradient)
+}
+
+// Same, but with steps of a fraction of a cell, so the differences are of
+// the interpolated field and not a blur of several cells.
+fn grid_normal(grid: &Grid, point: [f32; 3]) -> [f32; 3] {
+    let mut gradient = [0.0; 3];
+    for (axis, value) in gradient.iter_mut().enumerate() {
+        let step = grid.spacing[axis] * 0.25;
+        let mut high = point;
+        let mut low = point;
+        high[axis] += step;
+        low[axis] -= step;
+        *value = (grid.sample(high) - grid.sample(low)) / step;
+    }
+    normalize(gradient)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use find_edge;
+    use math::dot;
+
+    const RADIUS: f32 = 2.6;
+
+    fn sphere(point: [f32; 3]) -> f32 {
+        length(point) - RADIUS
+    }
+
+    fn sphere_grid() -> Grid {
+        let mut grid = Grid::new([17; 3], [-4.0; 3], [0.5; 3]);
+        for z in 0..17 {
+            for y in 0..17 {
+                for x in 0..17 {
+                    let value = sphere(grid.position(x, y, z));
+                    grid.set(x, y, z, value);
+                }
+            }
+        }
+        grid
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3], tolerance: f32) {
+        let distance = length(sub(a, b));
+        assert!(distance <= tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn grid_hits_agree_with_find_edge() {
+        let grid = sphere_grid();
+        let value = |x: usize, y: usize, z: usize| grid.data[grid.index(x, y, z)];
+        let mut hits = 0;
+        for z in 4..13 {
+            for y in 0..17 {
+                // Along +x, from outside the grid.
+                let origin = [-6.0, grid.position(0, y, z)[1], grid.position(0, y, z)[2]];
+                let expected = (0..16).find_map(|x| {
+                    let point = find_edge(0, 1, value(x, y, z), value(x + 1, y, z))?;
+                    Some(grid.position(x, y, z)[0] + point[0] * grid.spacing[0])
+                });
+                let hit = raycast_grid(&grid, origin, [2.0, 0.0, 0.0], 100.0);
+                assert_eq!(hit.is_some(), expected.is_some());
+                if let (Some(hit), Some(expected)) = (hit, expected) {
+                    assert_close(hit.position, [expected, origin[1], origin[2]], 1e-4);
+                    assert!((hit.distance - (expected - origin[0])).abs() < 1e-4);
+                    assert!(hit.normal[0] < 0.0);
+                    hits += 1;
+                }
+
+                // Along -y, with the lines swapped around.
+                let origin = [grid.position(y, 0, z)[0], 6.0, grid.position(y, 0, z)[2]];
+                let expected = (0..16).rev().find_map(|i| {
+                    let point = find_edge(2, 0, value(y, i + 1, z), value(y, i, z))?;
+                    Some(grid.position(y, i, z)[1] + point[1] * grid.spacing[1])
+                });
+                let hit = raycast_grid(&grid, origin, [0.0, -1.0, 0.0], 100.0);
+                assert_eq!(hit.is_some(), expected.is_some());
+                if let (Some(hit), Some(expected)) = (hit, expected) {
+                    assert_close(hit.position, [origin[0], expected, origin[2]], 1e-4);
+                    assert!((hit.distance - (origin[1] - expected)).abs() < 1e-4);
+                    assert!(hit.normal[1] > 0.0);
+                    hits += 1;
+                }
+            }
+        }
+        assert!(hits > 50);
+    }
+
+    #[test]
+    fn rays_from_outside_the_grid() {
+        let grid = sphere_grid();
+        let origin = [-3.0, 9.0, 0.4];
+        let direction = [0.6, -1.8, -0.2];
+        let hit = raycast_grid(&grid, origin, direction, 100.0).unwrap();
+        let expected = sphere_trace(&sphere, origin, direction, &SphereTrace::new(100.0)).unwrap();
+        // The grid only has the sphere to within a fraction of a cell.
+        assert!((hit.distance - expected.distance).abs() < 0.1);
+        assert_close(hit.position, expected.position, 0.1);
+        assert!(dot(hit.normal, expected.normal) > 0.95);
+
+        // Pointing away from the grid, and stopping short of it.
+        assert_eq!(
+            raycast_grid(&grid, origin, scale(direction, -1.0), 100.0),
+            None
+        );
+        assert_eq!(raycast_grid(&grid, origin, direction, 4.0), None);
+    }
+
+    #[test]
+    fn misses() {
+        let grid = sphere_grid();
+        let settings = SphereTrace::new(100.0);
+        // Passing beside the sphere.
+        let origin = [-3.5, 3.0, 0.0];
+        let direction = [1.0, 0.0, 0.1];
+        assert_eq!(raycast_grid(&grid, origin, direction, 100.0), None);
+        assert_eq!(sphere_trace(&sphere, origin, direction, &settings), None);
+        // Pointing away from it, from inside the grid.
+        let origin = [0.0, 0.0, 3.0];
+        assert_eq!(raycast_grid(&grid, origin, [0.0, 0.0, 1.0], 100.0), None);
+        assert_eq!(
+            sphere_trace(&sphere, origin, [0.0, 0.0, 1.0], &settings),
+            None
+        );
+    }
+
+    #[test]
+    fn rays_starting_inside_the_solid() {
+        let grid = sphere_grid();
+        let origin = [0.3, -0.4, 0.2];
+        let hit = raycast_grid(&grid, origin, [1.0, 1.0, 0.0], 100.0).unwrap();
+        assert_eq!(hit.distance, 0.0);
+        assert_eq!(hit.position, origin);
+        let hit = sphere_trace(&sphere, origin, [1.0, 1.0, 0.0], &SphereTrace::new(100.0)).unwrap();
+        assert_eq!(hit.distance, 0.0);
+        assert_eq!(hit.position, origin);
+    }
+
+    #[test]
+    fn sphere_trace_hits_the_surface() {
+        let origin = [5.0, 1.0, -2.0];
+        let direction = [-5.0, -1.0, 2.0];
+        let hit = sphere_trace(&sphere, origin, direction, &SphereTrace::new(100.0)).unwrap();
+        assert!((hit.distance - (length(origin) - RADIUS)).abs() < 1e-3);
+        assert_close(hit.normal, normalize(origin), 1e-3);
+        assert_eq!(hit.triangle, None);
+        // Half steps get there too, just slower.
+        let mut settings = SphereTrace::new(100.0);
+        settings.step_scale = 0.5;
+        let slower = sphere_trace(&sphere, origin, direction, &settings).unwrap();
+        assert!((slower.distance - hit.distance).abs() < 1e-3);
+    }
+
+    #[test]
+    fn refuses_bad_rays() {
+        let grid = sphere_grid();
+        let origin = [-3.0, 0.0, 0.0];
+        let settings = SphereTrace::new(100.0);
+        for &direction in &[[0.0; 3], [f32::NAN, 1.0, 0.0], [f32::INFINITY, 0.0, 0.0]] {
+            assert_eq!(raycast_grid(&grid, origin, direction, 100.0), None);
+            assert_eq!(sphere_trace(&sphere, origin, direction, &settings), None);
+        }
+        let direction = [1.0, 0.0, 0.0];
+        assert!(sphere_trace(&sphere, origin, direction, &settings).is_some());
+        for &(epsilon, step_scale) in &[(-1e-3, 1.0), (0.0, 1.0), (1e-3, -1.0), (1e-3, 0.0)] {
+            let mut settings = SphereTrace::new(100.0);
+            settings.epsilon = epsilon;
+            settings.step_scale = step_scale;
+            assert_eq!(sphere_trace(&sphere, origin, direction, &settings), None);
+        }
+    }
+}
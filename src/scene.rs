@@ -0,0 +1,564 @@
+// A small s-expression language for describing SDF scenes without having to
+// recompile anything. A scene is a single expression, for example:
+//
+//   ; a lumpy ball with a bite taken out of it
+//   (difference
+//     (noise 0.5 0.2 1234
+//       (sphere 10))
+//     (translate 8 0 0 (box 4 4 4)))
+//
+// Primitives (all centered on the origin):
+//   (sphere radius)
+//   (box half_x half_y half_z)
+//   (torus major_radius minor_radius)    ; lies in the XZ plane
+//   (cylinder radius half_height)        ; along the Y axis
+//   (plane nx ny nz offset)              ; solid where dot(p, n) < offset
+// CSG (any number of children, at least one):
+//   (union a b ...)
+//   (intersection a b ...)
+//   (difference a b ...)                 ; a minus everything else
+//   (smooth-union k a b ...), (smooth-intersection k a b ...),
+//   (smooth-difference k a b ...)        ; k is the blend radius
+// Transforms:
+//   (translate x y z child)
+//   (scale factor child)
+//   (rotate x|y|z degrees child)
+// Noise:
+//   (noise amplitude frequency seed child) ; displaces the child's surface
+//
+// Anything after a ';' on a line is a comment.
+
+use std::error::Error;
+use std::fmt;
+
+use field::Field;
+use noise::Perlin;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    // Both 1-based, like every text editor.
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+pub struct Scene {
+    root: Node,
+}
+
+impl Scene {
+    pub fn parse(source: &str) -> Result<Scene, ParseError> {
+        let tokens = tokenize(source);
+        let mut position = 0;
+        let expr = parse_expr(&tokens, &mut position, source, 0)?;
+        if let Some(token) = tokens.get(position) {
+            return Err(token.error("expected end of file after the scene expression"));
+        }
+        Ok(Scene {
+            root: build_node(&expr)?,
+        })
+    }
+}
+
+impl Field for Scene {
+    fn get(&self, point: [f32; 3]) -> f32 {
+        self.root.get(point)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum TokenKind {
+    Open,
+    Close,
+    Atom(String),
+}
+
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.to_string(),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (line_index, text) in source.lines().enumerate() {
+        let mut chars = text.char_indices().peekable();
+        let mut column = 0;
+        while let Some((start, ch)) = chars.next() {
+            column += 1;
+            let (line, token_column) = (line_index + 1, column);
+            match ch {
+                ';' => break,
+                '(' => tokens.push(Token {
+                    kind: TokenKind::Open,
+                    line,
+                    column: token_column,
+                }),
+                ')' => tokens.push(Token {
+                    kind: TokenKind::Close,
+                    line,
+                    column: token_column,
+                }),
+                ch if ch.is_whitespace() => (),
+                _ => {
+                    let mut end = start + ch.len_utf8();
+                    while let Some(&(index, next)) = chars.peek() {
+                        if next.is_whitespace() || next == '(' || next == ')' || next == ';' {
+                            break;
+                        }
+                        chars.next();
+                        column += 1;
+                        end = index + next.len_utf8();
+                    }
+                    tokens.push(Token {
+                        kind: TokenKind::Atom(text[start..end].to_string()),
+                        line,
+                        column: token_column,
+                    })
+                }
+            }
+        }
+    }
+    tokens
+}
+
+// Untyped syntax tree, before we know what any of the words mean.
+enum Expr {
+    Atom(String, usize, usize),
+    List(Vec<Expr>, usize, usize),
+}
+
+impl Expr {
+    fn error(&self, message: &str) -> ParseError {
+        let (line, column) = match *self {
+            Expr::Atom(_, line, column) | Expr::List(_, line, column) => (line, column),
+        };
+        ParseError {
+            line,
+            column,
+            message: message.to_string(),
+        }
+    }
+}
+
+// Deeper nesting than this is an error rather than a stack overflow, here or
+// when building and evaluating the scene.
+const MAX_DEPTH: usize = 256;
+
+fn parse_expr(
+    tokens: &[Token],
+    position: &mut usize,
+    source: &str,
+    depth: usize,
+) -> Result<Expr, ParseError> {
+    let token = match tokens.get(*position) {
+        Some(token) => token,
+        None => return Err(end_of_file(source, "expected an expression")),
+    };
+    *position += 1;
+    match token.kind {
+        TokenKind::Atom(ref atom) => Ok(Expr::Atom(atom.clone(), token.line, token.column)),
+        TokenKind::Close => Err(token.error("unexpected ')'")),
+        TokenKind::Open => {
+            if depth >= MAX_DEPTH {
+                return Err(
+                    token.error(&format!("expressions nested more than {} deep", MAX_DEPTH))
+                );
+            }
+            let mut items = Vec::new();
+            loop {
+                match tokens.get(*position) {
+                    Some(&Token {
+                        kind: TokenKind::Close,
+                        ..
+                    }) => {
+                        *position += 1;
+                        return Ok(Expr::List(items, token.line, token.column));
+                    }
+                    Some(_) => items.push(parse_expr(tokens, position, source, depth + 1)?),
+                    None => {
+                        return Err(token.error("unclosed '(', reached end of file"));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn end_of_file(source: &str, message: &str) -> ParseError {
+    let line = source.lines().count().max(1);
+    let column = source.lines().last().map_or(0, |l| l.chars().count()) + 1;
+    ParseError {
+        line,
+        column,
+        message: message.to_string(),
+    }
+}
+
+enum Node {
+    Sphere(f32),
+    Box([f32; 3]),
+    Torus(f32, f32),
+    Cylinder(f32, f32),
+    Plane([f32; 3], f32),
+    Union(Vec<Node>),
+    Intersection(Vec<Node>),
+    Difference(Vec<Node>),
+    SmoothUnion(f32, Vec<Node>),
+    SmoothIntersection(f32, Vec<Node>),
+    SmoothDifference(f32, Vec<Node>),
+    Translate([f32; 3], Box<Node>),
+    Scale(f32, Box<Node>),
+    // (axis, sin, cos, child)
+    Rotate(usize, f32, f32, Box<Node>),
+    Noise(f32, f32, Perlin, Box<Node>),
+}
+
+fn build_node(expr: &Expr) -> Result<Node, ParseError> {
+    let items = match *expr {
+        Expr::List(ref items, ..) => items,
+        Expr::Atom(ref atom, ..) => {
+            return Err(expr.error(&format!("expected '(', found `{}`", atom)));
+        }
+    };
+    let name = match items.first() {
+        Some(Expr::Atom(name, ..)) => name.as_str(),
+        Some(other) => return Err(other.error("expected a shape or operation name")),
+        None => return Err(expr.error("empty expression")),
+    };
+    let args = &items[1..];
+    let node = match name {
+        "sphere" => {
+            let [radius] = numbers(expr, name, args)?;
+            Node::Sphere(radius)
+        }
+        "box" => Node::Box(numbers(expr, name, args)?),
+        "torus" => {
+            let [major, minor] = numbers(expr, name, args)?;
+            Node::Torus(major, minor)
+        }
+        "cylinder" => {
+            let [radius, half_height] = numbers(expr, name, args)?;
+            Node::Cylinder(radius, half_height)
+        }
+        "plane" => {
+            let [x, y, z, offset] = numbers(expr, name, args)?;
+            let length = (x * x + y * y + z * z).sqrt();
+            if length == 0.0 {
+                return Err(expr.error("plane normal must not be zero"));
+            }
+            Node::Plane([x / length, y / length, z / length], offset)
+        }
+        "union" => Node::Union(children(expr, name, args)?),
+        "intersection" => Node::Intersection(children(expr, name, args)?),
+        "difference" => Node::Difference(children(expr, name, args)?),
+        "smooth-union" | "smooth-intersection" | "smooth-difference" => {
+            if args.is_empty() {
+                return Err(expr.error(&format!("`{}` needs a blend radius", name)));
+            }
+            let k = number(&args[0])?;
+            if k <= 0.0 {
+                return Err(args[0].error("blend radius must be positive"));
+            }
+            let children = children(expr, name, &args[1..])?;
+            match name {
+                "smooth-union" => Node::SmoothUnion(k, children),
+                "smooth-intersection" => Node::SmoothIntersection(k, children),
+                _ => Node::SmoothDifference(k, children),
+            }
+        }
+        "translate" => {
+            let ([x, y, z], child) = numbers_then_child(expr, name, args)?;
+            Node::Translate([x, y, z], child)
+        }
+        "scale" => {
+            let ([factor], child) = numbers_then_child(expr, name, args)?;
+            if factor == 0.0 {
+                return Err(args[0].error("scale factor must not be zero"));
+            }
+            Node::Scale(factor, child)
+        }
+        "rotate" => {
+            if args.len() != 3 {
+                return Err(expr.error("`rotate` takes an axis, an angle in degrees and a shape"));
+            }
+            let axis = match args[0] {
+                Expr::Atom(ref axis, ..) if axis == "x" => 0,
+                Expr::Atom(ref axis, ..) if axis == "y" => 1,
+                Expr::Atom(ref axis, ..) if axis == "z" => 2,
+                _ => return Err(args[0].error("rotation axis must be `x`, `y` or `z`")),
+            };
+            let angle = number(&args[1])?.to_radians();
+            Node::Rotate(
+                axis,
+                angle.sin(),
+                angle.cos(),
+                Box::new(build_node(&args[2])?),
+            )
+        }
+        "noise" => {
+            let ([amplitude, frequency, _], child) = numbers_then_child(expr, name, args)?;
+            // Straight from the text, as f32 can't hold every u32.
+            let seed = match args[2] {
+                Expr::Atom(ref atom, ..) => atom.parse::<u32>().ok(),
+                Expr::List(..) => None,
+            };
+            let seed = match seed {
+                Some(seed) => seed,
+                None => {
+                    return Err(args[2].error("noise seed must be an integer from 0 to 4294967295"));
+                }
+            };
+            Node::Noise(amplitude, frequency, Perlin::new(seed), child)
+        }
+        _ => return Err(items[0].error(&format!("unknown shape or operation `{}`", name))),
+    };
+    Ok(node)
+}
+
+fn number(expr: &Expr) -> Result<f32, ParseError> {
+    match *expr {
+        Expr::Atom(ref atom, ..) => match atom.parse::<f32>() {
+            Ok(value) if value.is_finite() => Ok(value),
+            _ => Err(expr.error(&format!("expected a number, found `{}`", atom))),
+        },
+        Expr::List(..) => Err(expr.error("expected a number, found a list")),
+    }
+}
+
+fn numbers<A: Default + AsMut<[f32]>>(
+    expr: &Expr,
+    name: &str,
+    args: &[Expr],
+) -> Result<A, ParseError> {
+    let mut result = A::default();
+    {
+        let slots = result.as_mut();
+        if args.len() != slots.len() {
+            return Err(expr.error(&format!(
+                "`{}` takes {}, found {} arguments",
+                name,
+                count_of_numbers(slots.len()),
+                args.len()
+            )));
+        }
+        for (slot, arg) in slots.iter_mut().zip(args) {
+            *slot = number(arg)?;
+        }
+    }
+    Ok(result)
+}
+
+fn numbers_then_child<A: Default + AsMut<[f32]>>(
+    expr: &Expr,
+    name: &str,
+    args: &[Expr],
+) -> Result<(A, Box<Node>), ParseError> {
+    let count = A::default().as_mut().len();
+    if args.len() != count + 1 {
+        return Err(expr.error(&format!(
+            "`{}` takes {} and a shape, found {} arguments",
+            name,
+            count_of_numbers(count),
+            args.len()
+        )));
+    }
+    let values = numbers(expr, name, &args[..count])?;
+    Ok((values, Box::new(build_node(&args[count])?)))
+}
+
+fn count_of_numbers(count: usize) -> String {
+    if count == 1 {
+        "1 number".to_string()
+    } else {
+        format!("{} numbers", count)
+    }
+}
+
+fn children(expr: &Expr, name: &str, args: &[Expr]) -> Result<Vec<Node>, ParseError> {
+    if args.is_empty() {
+        return Err(expr.error(&format!("`{}` needs at least one shape", name)));
+    }
+    args.iter().map(build_node).collect()
+}
+
+impl Node {
+    fn get(&self, p: [f32; 3]) -> f32 {
+        match *self {
+            Node::Sphere(radius) => length(p) - radius,
+            Node::Box(half) => {
+                let q = [
+                    p[0].abs() - half[0],
+                    p[1].abs() - half[1],
+                    p[2].abs() - half[2],
+                ];
+                let outside = length([q[0].max(0.0), q[1].max(0.0), q[2].max(0.0)]);
+                outside + q[0].max(q[1]).max(q[2]).min(0.0)
+            }
+            Node::Torus(major, minor) => {
+                let ring = (p[0] * p[0] + p[2] * p[2]).sqrt() - major;
+                (ring * ring + p[1] * p[1]).sqrt() - minor
+            }
+            Node::Cylinder(radius, half_height) => {
+                let d = [
+                    (p[0] * p[0] + p[2] * p[2]).sqrt() - radius,
+                    p[1].abs() - half_height,
+                ];
+                let outside = (d[0].max(0.0).powi(2) + d[1].max(0.0).powi(2)).sqrt();
+                outside + d[0].max(d[1]).min(0.0)
+            }
+            Node::Plane(normal, offset) => {
+                p[0] * normal[0] + p[1] * normal[1] + p[2] * normal[2] - offset
+            }
+            Node::Union(ref children) => fold(children, p, f32::min),
+            Node::Intersection(ref children) => fold(children, p, f32::max),
+            Node::Difference(ref children) => fold(children, p, |a, b| a.max(-b)),
+            Node::SmoothUnion(k, ref children) => fold(children, p, |a, b| smooth_min(a, b, k)),
+            Node::SmoothIntersection(k, ref children) => {
+                fold(children, p, |a, b| -smooth_min(-a, -b, k))
+            }
+            Node::SmoothDifference(k, ref children) => {
+                fold(children, p, |a, b| -smooth_min(-a, b, k))
+            }
+            Node::Translate(offset, ref child) => {
+                child.get([p[0] - offset[0], p[1] - offset[1], p[2] - offset[2]])
+            }
+            Node::Scale(factor, ref child) => {
+                child.get([p[0] / factor, p[1] / factor, p[2] / factor]) * factor.abs()
+            }
+            Node::Rotate(axis, sin, cos, ref child) => {
+                // Rotate the point the opposite way to rotate the shape.
+                let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+                let mut q = p;
+                q[a] = p[a] * cos + p[b] * sin;
+                q[b] = -p[a] * sin + p[b] * cos;
+                child.get(q)
+            }
+            Node::Noise(amplitude, frequency, ref perlin, ref child) => {
+                let n = perlin.get([p[0] * frequency, p[1] * frequency, p[2] * frequency]);
+                child.get(p) + amplitude * n
+            }
+        }
+    }
+}
+
+fn fold(children: &[Node], p: [f32; 3], op: impl Fn(f32, f32) -> f32) -> f32 {
+    let first = children[0].get(p);
+    children[1..]
+        .iter()
+        .fold(first, |acc, child| op(acc, child.get(p)))
+}
+
+// Polynomial smooth minimum, see
+// https://iquilezles.org/articles/smin/
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+fn length(p: [f32; 3]) -> f32 {
+    (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> ParseError {
+        Scene::parse(source).err().unwrap()
+    }
+
+    #[test]
+    fn valid_scene() {
+        let scene = Scene::parse(
+            "; a ball with a bite taken out of it\n\
+             (difference\n\
+             \t(sphere 10)\n\
+             \t(translate 8 0 0 (box 4 4 4)))",
+        )
+        .unwrap();
+        assert_eq!(scene.get([-5.0, 0.0, 0.0]), -5.0);
+        assert!(scene.get([9.0, 0.0, 0.0]) > 0.0);
+        assert!(scene.get([0.0, 9.0, 0.0]) < 0.0);
+        assert!((scene.get([0.0, 15.0, 0.0]) - 5.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn transforms_and_noise() {
+        let scene = Scene::parse(
+            "(union (scale 2 (rotate z 90 (cylinder 1 3))) (noise 0.5 0.2 4294967295 (torus 20 2)))",
+        )
+        .unwrap();
+        // The cylinder's axis now runs along X, six units to either side.
+        assert!(scene.get([5.0, 0.0, 0.0]) < 0.0);
+        assert!(scene.get([0.0, 5.0, 0.0]) > 0.0);
+    }
+
+    #[test]
+    fn unbalanced_parentheses() {
+        let unclosed = error("(union\n  (sphere 1)");
+        assert_eq!((unclosed.line, unclosed.column), (1, 1));
+        assert!(unclosed.message.contains("unclosed"));
+        let extra = error("(sphere 1))");
+        assert_eq!((extra.line, extra.column), (1, 11));
+        let unexpected = error(")");
+        assert_eq!((unexpected.line, unexpected.column), (1, 1));
+        assert!(Scene::parse("").is_err());
+    }
+
+    #[test]
+    fn unknown_operator() {
+        let error = error("(union\n  (sphere 1)\n    (blob 2))");
+        assert_eq!((error.line, error.column), (3, 6));
+        assert_eq!(error.message, "unknown shape or operation `blob`");
+        assert_eq!(error.to_string(), "3:6: unknown shape or operation `blob`");
+    }
+
+    #[test]
+    fn errors_point_at_the_bad_token() {
+        let number = error("(translate 1 2\n   x (sphere 1))");
+        assert_eq!((number.line, number.column), (2, 4));
+        let axis = error("(rotate w 90 (sphere 1))");
+        assert_eq!((axis.line, axis.column), (1, 9));
+        let seed = error("(noise 1 1 -1 (sphere 1))");
+        assert_eq!((seed.line, seed.column), (1, 12));
+        let seed = error("(noise 1 1 4294967296 (sphere 1))");
+        assert_eq!((seed.line, seed.column), (1, 12));
+        let arguments = error("(sphere)");
+        assert_eq!((arguments.line, arguments.column), (1, 1));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth: usize| {
+            let mut source = "(translate 0 0 0 ".repeat(depth - 1);
+            source.push_str("(sphere 1)");
+            source.push_str(&")".repeat(depth - 1));
+            source
+        };
+        assert!(Scene::parse(&nested(MAX_DEPTH)).is_ok());
+        let too_deep = error(&nested(MAX_DEPTH + 1));
+        let column = MAX_DEPTH * "(translate 0 0 0 ".len() + 1;
+        assert_eq!((too_deep.line, too_deep.column), (1, column));
+        let error = error(&"(".repeat(100_000));
+        assert_eq!((error.line, error.column), (1, MAX_DEPTH + 1));
+    }
+}
@@ -0,0 +1,486 @@
+// Cross sections: cutting fields and meshes with a plane into 2D outlines,
+// for 3D printing previews and CAD-style section views, and SVG export of
+// the outlines.
+//
+// Outlines follow one convention throughout: walking along them, the solid
+// is on the left (seen from the side the plane's normal points to), so outer
+// outlines go counter-clockwise and the outlines of holes clockwise.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use edge_crossing;
+use field::Field;
+use math::{add, cross, dot, normalize, scale, sub};
+
+pub struct Plane {
+    pub origin: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+impl Plane {
+    pub fn new(origin: [f32; 3], normal: [f32; 3]) -> Plane {
+        Plane {
+            origin,
+            normal: normalize(normal),
+        }
+    }
+
+    // The plane's 2D axes (u, v) in the world, with u x v = normal.
+    pub fn axes(&self) -> ([f32; 3], [f32; 3]) {
+        let normal = normalize(self.normal);
+        // Any axis not too close to the normal will do.
+        let helper = if normal[0].abs() < 0.9 {
+            [1.0, 0.0, 0.0]
+        } else {
+            [0.0, 1.0, 0.0]
+        };
+        let u = normalize(cross(helper, normal));
+        (u, cross(normal, u))
+    }
+
+    // World position to 2D position on the plane (after projecting onto it).
+    pub fn to_plane(&self, point: [f32; 3]) -> [f32; 2] {
+        let (u, v) = self.axes();
+        let offset = sub(point, self.origin);
+        [dot(offset, u), dot(offset, v)]
+    }
+
+    pub fn to_world(&self, point: [f32; 2]) -> [f32; 3] {
+        let (u, v) = self.axes();
+        add(self.origin, add(scale(u, point[0]), scale(v, point[1])))
+    }
+}
+
+pub struct Polyline {
+    pub points: Vec<[f32; 2]>,
+    // Whether the last point connects back to the first, which isn't
+    // repeated at the end.
+    pub closed: bool,
+}
+
+// The 2D version of surface_net: one vertex per square cell the outline
+// passes through, at the average of where it crosses the cell's edges, and
+// a segment across every edge with a sign change. Sample (x, y) is at
+// (x, y), so the output is in grid units.
+// Outlines that run off the edge of the grid are left open, ending where
+// they cross its border. Cells where solid and air alternate around the
+// corners get one vertex that two outlines pass through.
+pub fn contours(size: [usize; 2], sdf: &dyn Fn(usize, usize) -> f32) -> Vec<Polyline> {
+    let mut values = Vec::with_capacity(size[0] * size[1]);
+    for y in 0..size[1] {
+        for x in 0..size[0] {
+            values.push(sdf(x, y));
+        }
+    }
+    let value = |x: usize, y: usize| values[y * size[0] + x];
+    let solid = |x: usize, y: usize| value(x, y) < 0.0;
+
+    let mut points = Vec::new();
+    let cells = [size[0].saturating_sub(1), size[1].saturating_sub(1)];
+    let mut cell_vertex = vec![usize::MAX; cells[0] * cells[1]];
+    for y in 0..cells[1] {
+        for x in 0..cells[0] {
+            let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+            let mut sum = [0.0, 0.0];
+            let mut count = 0;
+            for i in 0..4 {
+                let (a, b) = (corners[i], corners[(i + 1) % 4]);
+                if let Some(interp) = edge_crossing(value(a.0, a.1), value(b.0, b.1)) {
+                    sum[0] += a.0 as f32 + (b.0 as f32 - a.0 as f32) * interp;
+                    sum[1] += a.1 as f32 + (b.1 as f32 - a.1 as f32) * interp;
+                    count += 1;
+                }
+            }
+            if count > 0 {
+                cell_vertex[y * cells[0] + x] = points.len();
+                points.push([sum[0] / count as f32, sum[1] / count as f32]);
+            }
+        }
+    }
+
+    // Every edge with a sign change connects the vertices of the cells on
+    // either side of it, or where there's no cell (at the border) the
+    // crossing itself.
+    let mut segments = Vec::new();
+    let mut connect = |points: &mut Vec<[f32; 2]>,
+                       before: Option<(usize, usize)>,
+                       after: Option<(usize, usize)>,
+                       crossing: [f32; 2],
+                       forwards: bool| {
+        let mut vertex = |cell: Option<(usize, usize)>| match cell {
+            Some((x, y)) => cell_vertex[y * cells[0] + x],
+            None => {
+                points.push(crossing);
+                points.len() - 1
+            }
+        };
+        let (from, to) = (vertex(before), vertex(after));
+        segments.push(if forwards { (from, to) } else { (to, from) });
+    };
+    let cell = |x: usize, y: usize, exists: bool| if exists { Some((x, y)) } else { None };
+    for y in 0..size[1] {
+        for x in 0..size[0] {
+            // The edge along X, crossed from the cell below to the one above
+            // with the solid on the left if its start is solid.
+            if x + 1 < size[0] {
+                if let Some(interp) = edge_crossing(value(x, y), value(x + 1, y)) {
+                    connect(
+                        &mut points,
+                        cell(x, y.wrapping_sub(1), y > 0),
+                        cell(x, y, y + 1 < size[1]),
+                        [x as f32 + interp, y as f32],
+                        solid(x, y),
+                    );
+                }
+            }
+            // The edge along Y, crossed from the cell on the left to the
+            // one on the right with the solid on the left if its end is
+            // solid.
+            if y + 1 < size[1] {
+                if let Some(interp) = edge_crossing(value(x, y), value(x, y + 1)) {
+                    connect(
+                        &mut points,
+                        cell(x.wrapping_sub(1), y, x > 0),
+                        cell(x, y, x + 1 < size[0]),
+                        [x as f32, y as f32 + interp],
+                        solid(x, y + 1),
+                    );
+                }
+            }
+        }
+    }
+    chain(points, &segments)
+}
+
+// Cross section of a field over the rectangle from min to max in the plane's
+// 2D coordinates, sampled every spacing, with the output in the same
+// coordinates. Outlines leaving the rectangle are left open there. Grids are
+// fields too, and since they're surrounded by air, a rectangle covering the
+// whole grid gives closed outlines. Nothing if spacing isn't positive and
+// finite, or the rectangle has too many samples to count.
+pub fn slice_field(
+    field: &dyn Field,
+    plane: &Plane,
+    min: [f32; 2],
+    max: [f32; 2],
+    spacing: f32,
+) -> Vec<Polyline> {
+    let size = match sample_count(min, max, spacing) {
+        Some(size) => size,
+        None => return Vec::new(),
+    };
+    let sample = |x: usize, y: usize| {
+        field.get(plane.to_world([min[0] + x as f32 * spacing, min[1] + y as f32 * spacing]))
+    };
+    let mut polylines = contours(size, &sample);
+    for polyline in &mut polylines {
+        for point in &mut polyline.points {
+            *point = [min[0] + point[0] * spacing, min[1] + point[1] * spacing];
+        }
+    }
+    polylines
+}
+
+// Samples along each side of the rectangle, None if there's no sensible
+// number of them.
+fn sample_count(min: [f32; 2], max: [f32; 2], spacing: f32) -> Option<[usize; 2]> {
+    if !(spacing > 0.0 && spacing.is_finite()) {
+        return None;
+    }
+    let mut size = [0; 2];
+    for axis in 0..2 {
+        let cells = ((max[axis] - min[axis]) / spacing).ceil();
+        if !cells.is_finite() {
+            return None;
+        }
+        // An empty rectangle is a single sample.
+        size[axis] = (cells.max(0.0) as usize).checked_add(1)?;
+    }
+    size[0].checked_mul(size[1])?;
+    Some(size)
+}
+
+// Cross section of a mesh, in the plane's 2D coordinates. Closed meshes give
+// closed outlines, where the plane runs through holes in the mesh they're
+// left open. Vertices exactly on the plane count as being in front of it, so
+// every intersection is a clean crossing of triangle edges.
+pub fn slice_mesh(positions: &[[f32; 3]], indices: &[usize], plane: &Plane) -> Vec<Polyline> {
+    let normal = normalize(plane.normal);
+    let distances = positions
+        .iter()
+        .map(|&position| dot(sub(position, plane.origin), normal))
+        .collect::<Vec<_>>();
+    let in_front = |vertex: usize| distances[vertex] >= 0.0;
+    let mut points = Vec::new();
+    let mut crossings = HashMap::new();
+    let mut crossing = |a: usize, b: usize| {
+        // The same point for both triangles along the edge, whichever way
+        // round they have it.
+        let (a, b) = (a.min(b), a.max(b));
+        *crossings.entry((a, b)).or_insert_with(|| {
+            let interp = distances[a] / (distances[a] - distances[b]);
+            let [p, q] = [positions[a], positions[b]];
+            points.push(plane.to_plane(add(p, scale(sub(q, p), interp))));
+            points.len() - 1
+        })
+    };
+    let mut segments = Vec::new();
+    for corners in indices.chunks(3) {
+        // For a counter-clockwise (seen from outside) triangle, going from
+        // where its edges go behind the plane to where they come back out
+        // keeps the inside on the left.
+        let mut leaving = None;
+        let mut entering = None;
+        for i in 0..3 {
+            let (a, b) = (corners[i], corners[(i + 1) % 3]);
+            match (in_front(a), in_front(b)) {
+                (true, false) => leaving = Some(crossing(a, b)),
+                (false, true) => entering = Some(crossing(a, b)),
+                _ => {}
+            }
+        }
+        if let (Some(from), Some(to)) = (leaving, entering) {
+            segments.push((from, to));
+        }
+    }
+    chain(points, &segments)
+}
+
+// Outlines as SVG paths, filled with the even-odd rule so holes show, and
+// flipped vertically so that the plane's v axis points up like it does in
+// the plane. Coordinates stay in world units.
+pub fn write_svg<W: Write>(
+    writer: &mut W,
+    polylines: &[Polyline],
+    stroke_width: f32,
+) -> io::Result<()> {
+    let mut min = [f32::INFINITY; 2];
+    let mut max = [f32::NEG_INFINITY; 2];
+    for point in polylines.iter().flat_map(|polyline| &polyline.points) {
+        for axis in 0..2 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+        }
+    }
+    if min[0] > max[0] {
+        min = [0.0; 2];
+        max = [0.0; 2];
+    }
+    let margin = stroke_width;
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">",
+        min[0] - margin,
+        -max[1] - margin,
+        max[0] - min[0] + 2.0 * margin,
+        max[1] - min[1] + 2.0 * margin
+    )?;
+    for &closed in &[true, false] {
+        let mut path = String::new();
+        for polyline in polylines
+            .iter()
+            .filter(|polyline| polyline.closed == closed)
+        {
+            for (i, point) in polyline.points.iter().enumerate() {
+                let command = if i == 0 { 'M' } else { 'L' };
+                path.push_str(&format!("{}{} {} ", command, point[0], -point[1]));
+            }
+            if closed {
+                path.push_str("Z ");
+            }
+        }
+        if path.is_empty() {
+            continue;
+        }
+        let fill = if closed { "#cccccc" } else { "none" };
+        writeln!(
+            writer,
+            "<path d=\"{}\" fill=\"{}\" fill-rule=\"evenodd\" stroke=\"black\" stroke-width=\"{}\"/>",
+            path.trim_end(),
+            fill,
+            stroke_width
+        )?;
+    }
+    writeln!(writer, "</svg>")
+}
+
+// Joins directed segments between points into polylines: first the open
+// ones, from wherever more segments leave than arrive, then the loops.
+fn chain(points: Vec<[f32; 2]>, segments: &[(usize, usize)]) -> Vec<Polyline> {
+    let mut outgoing = vec![Vec::new(); points.len()];
+    let mut incoming = vec![0; points.len()];
+    for &(from, to) in segments.iter().rev() {
+        outgoing[from].push(to);
+        incoming[to] += 1;
+    }
+    let mut polylines = Vec::new();
+    for start in 0..points.len() {
+        while outgoing[start].len() > incoming[start] {
+            let mut chain = vec![points[start]];
+            let mut current = start;
+            while let Some(next) = outgoing[current].pop() {
+                chain.push(points[next]);
+                current = next;
+            }
+            polylines.push(Polyline {
+                points: chain,
+                closed: false,
+            });
+        }
+    }
+    // Everything left arrives at every point as often as it leaves, so
+    // these walks can only end back at the start.
+    for start in 0..points.len() {
+        while !outgoing[start].is_empty() {
+            let mut chain = Vec::new();
+            let mut current = start;
+            while let Some(next) = outgoing[current].pop() {
+                chain.push(points[current]);
+                current = next;
+                if current == start {
+                    break;
+                }
+            }
+            polylines.push(Polyline {
+                points: chain,
+                closed: true,
+            });
+        }
+    }
+    polylines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_meshes::sphere;
+
+    // Positive for counter-clockwise outlines.
+    fn signed_area(points: &[[f32; 2]]) -> f32 {
+        (0..points.len())
+            .map(|i| {
+                let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                a[0] * b[1] - a[1] * b[0]
+            })
+            .sum::<f32>()
+            * 0.5
+    }
+
+    fn circle(point: [f32; 3]) -> f32 {
+        (point[0] * point[0] + point[1] * point[1] + point[2] * point[2]).sqrt() - 0.8
+    }
+
+    #[test]
+    fn field_slice_of_a_sphere() {
+        let plane = Plane::new([0.0, 0.0, 0.1], [0.0, 0.0, 1.0]);
+        let polylines = slice_field(&circle, &plane, [-1.0; 2], [1.0; 2], 0.05);
+        assert_eq!(polylines.len(), 1);
+        assert!(polylines[0].closed);
+        // The circle the plane cuts out of the sphere, counter-clockwise.
+        let radius = (0.8f32 * 0.8 - 0.1 * 0.1).sqrt();
+        let area = signed_area(&polylines[0].points);
+        let expected = ::std::f32::consts::PI * radius * radius;
+        assert!(
+            (area - expected).abs() < expected * 0.02,
+            "{} != {}",
+            area,
+            expected
+        );
+        for point in &polylines[0].points {
+            assert!((point[0].hypot(point[1]) - radius).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn mesh_slice_of_a_sphere() {
+        let (positions, indices) = sphere();
+        let plane = Plane::new([8.0, 8.0, 8.3], [0.0, 0.0, 1.0]);
+        let polylines = slice_mesh(&positions, &indices, &plane);
+        assert_eq!(polylines.len(), 1);
+        assert!(polylines[0].closed);
+        // Surface nets puts the vertices a little inside the sphere.
+        let radius = (5.5f32 * 5.5 - 0.3 * 0.3).sqrt();
+        let area = signed_area(&polylines[0].points);
+        let expected = ::std::f32::consts::PI * radius * radius;
+        assert!(area < expected && area > expected * 0.9);
+        for point in &polylines[0].points {
+            let distance = point[0].hypot(point[1]);
+            assert!(distance < radius + 0.01 && distance > radius - 0.3);
+        }
+    }
+
+    #[test]
+    fn holes_go_clockwise() {
+        // A ring in the plane, from radius 0.35 to 0.85.
+        let ring = |point: [f32; 3]| (point[0].hypot(point[1]) - 0.6).abs() - 0.25;
+        let plane = Plane::new([0.0; 3], [0.0, 0.0, 1.0]);
+        let mut polylines = slice_field(&ring, &plane, [-1.0; 2], [1.0; 2], 0.05);
+        assert_eq!(polylines.len(), 2);
+        polylines.sort_by_key(|polyline| polyline.points.len());
+        assert!(polylines.iter().all(|polyline| polyline.closed));
+        assert!(signed_area(&polylines[0].points) < 0.0);
+        assert!(signed_area(&polylines[1].points) > 0.0);
+    }
+
+    #[test]
+    fn outlines_leaving_the_rectangle_are_open() {
+        // Only the half of the circle with u above 0.
+        let plane = Plane::new([0.0; 3], [0.0, 0.0, 1.0]);
+        let polylines = slice_field(&circle, &plane, [0.0, -1.0], [1.0, 1.0], 0.05);
+        assert_eq!(polylines.len(), 1);
+        let points = &polylines[0].points;
+        assert!(!polylines[0].closed);
+        // Counter-clockwise, so from the bottom end on the border round the
+        // right of the circle to the top one.
+        let (first, last) = (points[0], points[points.len() - 1]);
+        assert_eq!(first[0], 0.0);
+        assert_eq!(last[0], 0.0);
+        assert!((first[1] + 0.8).abs() < 0.01 && (last[1] - 0.8).abs() < 0.01);
+        assert!(points.iter().all(|point| point[0] >= 0.0));
+    }
+
+    #[test]
+    fn refuses_bad_spacing() {
+        let plane = Plane::new([0.0; 3], [0.0, 0.0, 1.0]);
+        for &spacing in &[0.0, -0.1, f32::NAN, f32::INFINITY] {
+            assert!(slice_field(&circle, &plane, [-1.0; 2], [1.0; 2], spacing).is_empty());
+        }
+        let huge = slice_field(&circle, &plane, [-1e30; 2], [1e30; 2], 1e-30);
+        assert!(huge.is_empty());
+    }
+
+    #[test]
+    fn svg_output() {
+        let polylines = vec![
+            Polyline {
+                points: vec![[0.0, 0.0], [2.0, 0.0], [2.0, 1.0]],
+                closed: true,
+            },
+            Polyline {
+                points: vec![[-1.0, 3.0], [0.5, 2.0]],
+                closed: false,
+            },
+        ];
+        let mut svg = Vec::new();
+        write_svg(&mut svg, &polylines, 0.1).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        let lines = svg.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"-1.1 -3.1 3.2 3.2\">",
+                "<path d=\"M0 -0 L2 -0 L2 -1 Z\" fill=\"#cccccc\" fill-rule=\"evenodd\" stroke=\"black\" stroke-width=\"0.1\"/>",
+                "<path d=\"M-1 -3 L0.5 -2\" fill=\"none\" fill-rule=\"evenodd\" stroke=\"black\" stroke-width=\"0.1\"/>",
+                "</svg>",
+            ]
+        );
+
+        let mut empty = Vec::new();
+        write_svg(&mut empty, &[], 1.0).unwrap();
+        assert_eq!(
+            String::from_utf8(empty).unwrap(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"-1 -1 2 2\">\n</svg>\n"
+        );
+    }
+}
@@ -0,0 +1,315 @@
+// Mesh smoothing, for taking the stair-stepping out of meshes of blocky or
+// low resolution fields.
+//
+// Plain Laplacian smoothing moves every vertex towards the average of its
+// neighbours, which also shrinks the whole mesh. HC-Laplacian (Vollmer, Mencl
+// and Müller, "Improved Laplacian Smoothing of Noisy Surface Meshes") and
+// Taubin's lambda/mu smoothing ("A Signal Processing Approach to Fair Surface
+// Design") both push back against the shrinking.
+//
+// Vertices on boundary edges are left where they are, so separately meshed
+// chunks still line up after smoothing.
+
+use std::collections::HashMap;
+
+use math::{add, cross, normalize, scale, sub};
+
+pub enum SmoothingMethod {
+    // Fraction of the way to move towards the neighbours' average each
+    // iteration, 0 to 1.
+    Laplacian(f32),
+    // alpha: how much to pull back towards the original positions (0 to 1,
+    // 0 is the usual choice). beta: how much of the pull back to spread to
+    // the neighbours (0 to 1, usually 0.5 or more).
+    HcLaplacian { alpha: f32, beta: f32 },
+    // A shrinking step of lambda, then an inflating one of mu, which must be
+    // negative and a bit larger: lambda 0.5 and mu -0.53 are typical.
+    Taubin { lambda: f32, mu: f32 },
+}
+
+// Grid layout of the field the mesh came from. When given, every vertex is
+// kept inside the grid cell it was placed in, so smoothing can't wander off
+// from the field: for surface_net output that's origin 0 and spacing 1, for
+// Grid::surface_net the grid's own origin and spacing.
+pub struct Cells {
+    pub origin: [f32; 3],
+    pub spacing: [f32; 3],
+}
+
+// Returns the smoothed positions and normals recomputed from them. None if the
+// indices aren't triangles of the vertices, Taubin's mu isn't negative (it
+// would shrink the mesh twice over) or the cells aren't a positive size.
+#[allow(clippy::type_complexity)]
+pub fn smooth(
+    positions: &[[f32; 3]],
+    indices: &[usize],
+    method: &SmoothingMethod,
+    iterations: usize,
+    cells: Option<&Cells>,
+) -> Option<(Vec<[f32; 3]>, Vec<[f32; 3]>)> {
+    let whole_triangles =
+        indices.len().is_multiple_of(3) && indices.iter().all(|&index| index < positions.len());
+    let inflates = match *method {
+        SmoothingMethod::Taubin { mu, .. } => mu < 0.0,
+        _ => true,
+    };
+    let sized = cells.is_none_or(|cells| {
+        cells
+            .spacing
+            .iter()
+            .all(|&spacing| spacing > 0.0 && spacing.is_finite())
+    });
+    if !(whole_triangles && inflates && sized) {
+        return None;
+    }
+    let (neighbours, fixed) = adjacency(positions.len(), indices);
+    let bounds = cells.map(|cells| {
+        positions
+            .iter()
+            .map(|position| {
+                let mut low = [0.0; 3];
+                let mut high = [0.0; 3];
+                for axis in 0..3 {
+                    let cell =
+                        ((position[axis] - cells.origin[axis]) / cells.spacing[axis]).floor();
+                    low[axis] = cells.origin[axis] + cell * cells.spacing[axis];
+                    high[axis] = low[axis] + cells.spacing[axis];
+                }
+                (low, high)
+            })
+            .collect::<Vec<_>>()
+    });
+    let constrain = |positions: &mut Vec<[f32; 3]>| {
+        if let Some(ref bounds) = bounds {
+            for (position, &(low, high)) in positions.iter_mut().zip(bounds) {
+                for axis in 0..3 {
+                    position[axis] = position[axis].clamp(low[axis], high[axis]);
+                }
+            }
+        }
+    };
+
+    let mut current = positions.to_vec();
+    for _ in 0..iterations {
+        match *method {
+            SmoothingMethod::Laplacian(lambda) => {
+                current = step(&current, &neighbours, &fixed, lambda);
+            }
+            SmoothingMethod::Taubin { lambda, mu } => {
+                current = step(&current, &neighbours, &fixed, lambda);
+                constrain(&mut current);
+                current = step(&current, &neighbours, &fixed, mu);
+            }
+            SmoothingMethod::HcLaplacian { alpha, beta } => {
+                let smoothed = step(&current, &neighbours, &fixed, 1.0);
+                // How far each vertex moved, measured from a mix of where it
+                // started and where it was before this iteration.
+                let differences = (0..positions.len())
+                    .map(|vertex| {
+                        sub(
+                            smoothed[vertex],
+                            add(
+                                scale(positions[vertex], alpha),
+                                scale(current[vertex], 1.0 - alpha),
+                            ),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                current = (0..positions.len())
+                    .map(|vertex| {
+                        if fixed[vertex] || neighbours[vertex].is_empty() {
+                            return smoothed[vertex];
+                        }
+                        let average = scale(
+                            neighbours[vertex]
+                                .iter()
+                                .fold([0.0; 3], |sum, &other| add(sum, differences[other])),
+                            1.0 / neighbours[vertex].len() as f32,
+                        );
+                        sub(
+                            smoothed[vertex],
+                            add(scale(differences[vertex], beta), scale(average, 1.0 - beta)),
+                        )
+                    })
+                    .collect();
+            }
+        }
+        constrain(&mut current);
+    }
+    let normals = vertex_normals(&current, indices);
+    Some((current, normals))
+}
+
+// Area-weighted average of the normals of the triangles around each vertex.
+pub fn vertex_normals(positions: &[[f32; 3]], indices: &[usize]) -> Vec<[f32; 3]> {
+    let mut normals = vec![[0.0; 3]; positions.len()];
+    for corners in indices.chunks(3) {
+        let [a, b, c] = [
+            positions[corners[0]],
+            positions[corners[1]],
+            positions[corners[2]],
+        ];
+        // Twice the area, which doesn't matter once normalized.
+        let normal = cross(sub(b, a), sub(c, a));
+        for &corner in corners {
+            normals[corner] = add(normals[corner], normal);
+        }
+    }
+    normals.into_iter().map(normalize).collect()
+}
+
+// Moves every vertex (but the fixed ones) the given fraction of the way
+// towards the average of its neighbours.
+fn step(
+    positions: &[[f32; 3]],
+    neighbours: &[Vec<usize>],
+    fixed: &[bool],
+    factor: f32,
+) -> Vec<[f32; 3]> {
+    positions
+        .iter()
+        .enumerate()
+        .map(|(vertex, &position)| {
+            if fixed[vertex] || neighbours[vertex].is_empty() {
+                return position;
+            }
+            let sum = neighbours[vertex]
+                .iter()
+                .fold([0.0; 3], |sum, &other| add(sum, positions[other]));
+            let average = scale(sum, 1.0 / neighbours[vertex].len() as f32);
+            add(position, scale(sub(average, position), factor))
+        })
+        .collect()
+}
+
+// Neighbours of every vertex, and which vertices are on boundary edges.
+fn adjacency(vertex_count: usize, indices: &[usize]) -> (Vec<Vec<usize>>, Vec<bool>) {
+    let mut edges = HashMap::new();
+    for corners in indices.chunks(3) {
+        for i in 0..3 {
+            let (a, b) = (corners[i], corners[(i + 1) % 3]);
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    let mut neighbours = vec![Vec::new(); vertex_count];
+    let mut fixed = vec![false; vertex_count];
+    for (&(a, b), &count) in &edges {
+        neighbours[a].push(b);
+        neighbours[b].push(a);
+        if count == 1 {
+            fixed[a] = true;
+            fixed[b] = true;
+        }
+    }
+    // Sorted so that results don't depend on HashMap's iteration order.
+    for list in &mut neighbours {
+        list.sort_unstable();
+    }
+    (neighbours, fixed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::{dot, length};
+    use test_meshes::sphere;
+    use validate::validate;
+
+    fn mean_radius(positions: &[[f32; 3]]) -> f32 {
+        let total = positions
+            .iter()
+            .map(|&position| length(sub(position, [8.0; 3])))
+            .sum::<f32>();
+        total / positions.len() as f32
+    }
+
+    #[test]
+    fn closed_sphere_stays_a_valid_solid() {
+        let (positions, indices) = sphere();
+        let methods = [
+            SmoothingMethod::Laplacian(0.5),
+            SmoothingMethod::HcLaplacian {
+                alpha: 0.0,
+                beta: 0.5,
+            },
+            SmoothingMethod::Taubin {
+                lambda: 0.5,
+                mu: -0.53,
+            },
+        ];
+        let mut radii = Vec::new();
+        for method in &methods {
+            let (smoothed, normals) = smooth(&positions, &indices, method, 10, None).unwrap();
+            assert_eq!(normals.len(), smoothed.len());
+            assert!(validate(&smoothed, &indices).is_valid_solid());
+            // Normals still point outwards.
+            for (&position, &normal) in smoothed.iter().zip(&normals) {
+                assert!(dot(sub(position, [8.0; 3]), normal) > 0.0);
+            }
+            radii.push(mean_radius(&smoothed));
+        }
+        // Plain Laplacian shrinks the sphere, the other two hardly do.
+        let original = mean_radius(&positions);
+        assert!(radii[0] < original - 0.2, "{:?} {}", radii, original);
+        for &radius in &radii[1..] {
+            assert!((radius - original).abs() < 0.1, "{:?} {}", radii, original);
+        }
+    }
+
+    #[test]
+    fn vertices_stay_in_their_cells() {
+        let (positions, indices) = sphere();
+        let method = SmoothingMethod::Laplacian(1.0);
+        let (free, _) = smooth(&positions, &indices, &method, 20, None).unwrap();
+        let cells = Cells {
+            origin: [0.0; 3],
+            spacing: [1.0; 3],
+        };
+        let (clamped, _) = smooth(&positions, &indices, &method, 20, Some(&cells)).unwrap();
+        // Faces of the cell count as inside it.
+        let same_cell = |a: [f32; 3], b: [f32; 3]| {
+            (0..3).all(|axis| b[axis] >= a[axis].floor() && b[axis] <= a[axis].floor() + 1.0)
+        };
+        assert!(positions.iter().zip(&free).any(|(&a, &b)| !same_cell(a, b)));
+        assert!(positions
+            .iter()
+            .zip(&clamped)
+            .all(|(&a, &b)| same_cell(a, b)));
+        assert!(positions != clamped);
+    }
+
+    #[test]
+    fn boundary_vertices_are_fixed() {
+        let (positions, indices) = sphere();
+        let indices = indices[6..].to_vec();
+        let boundary = validate(&positions, &indices).boundary_loops.concat();
+        let method = SmoothingMethod::Taubin {
+            lambda: 0.5,
+            mu: -0.53,
+        };
+        let (smoothed, _) = smooth(&positions, &indices, &method, 10, None).unwrap();
+        assert!(!boundary.is_empty());
+        for vertex in boundary {
+            assert_eq!(smoothed[vertex], positions[vertex]);
+        }
+    }
+
+    #[test]
+    fn refuses_bad_input() {
+        let (positions, indices) = sphere();
+        let laplacian = SmoothingMethod::Laplacian(0.5);
+        assert!(smooth(&positions, &indices[1..], &laplacian, 1, None).is_none());
+        let mut out_of_range = indices.clone();
+        out_of_range[0] = positions.len();
+        assert!(smooth(&positions, &out_of_range, &laplacian, 1, None).is_none());
+        for &mu in &[0.53, 0.0, f32::NAN] {
+            let taubin = SmoothingMethod::Taubin { lambda: 0.5, mu };
+            assert!(smooth(&positions, &indices, &taubin, 1, None).is_none());
+        }
+        let cells = Cells {
+            origin: [0.0; 3],
+            spacing: [1.0, 0.0, 1.0],
+        };
+        assert!(smooth(&positions, &indices, &laplacian, 1, Some(&cells)).is_none());
+    }
+}
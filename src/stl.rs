@@ -0,0 +1,130 @@
+// Binary STL export, for 3D printing.
+
+use std::io::{self, Write};
+
+use math::{cross, normalize, sub, triangle};
+use validate::{validate, TopologyReport};
+
+// STL has no shared vertices, so only positions and indices are needed: the
+// facet normals are recomputed from the triangles.
+// Slicers tend to choke on meshes with holes, so with check_watertight this
+// refuses to write anything (and returns an InvalidInput error) unless every
+// edge is shared by exactly two consistently wound triangles. Indices that
+// aren't whole triangles of the given positions are an InvalidInput error too.
+pub fn write_stl<W: Write>(
+    writer: &mut W,
+    positions: &[[f32; 3]],
+    indices: &[usize],
+    check_watertight: bool,
+) -> io::Result<()> {
+    if !indices.len().is_multiple_of(3) || indices.iter().any(|&index| index >= positions.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "mesh indices are not triangles of its vertices",
+        ));
+    }
+    if check_watertight {
+        let report = validate(positions, indices);
+        if !watertight(&report) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "mesh is not watertight ({} boundary loops, {} non-manifold edges, {} inconsistent edges)",
+                    report.boundary_loops.len(),
+                    report.non_manifold_edges.len(),
+                    report.inconsistent_edges.len()
+                ),
+            ));
+        }
+    }
+    let mut header = [0; 80];
+    header[..12].copy_from_slice(b"surface-nets");
+    writer.write_all(&header)?;
+    writer.write_all(&((indices.len() / 3) as u32).to_le_bytes())?;
+    for corners in indices.chunks(3) {
+        let [a, b, c] = triangle(positions, corners);
+        let normal = normalize(cross(sub(b, a), sub(c, a)));
+        for vector in &[normal, a, b, c] {
+            for value in vector {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        // "Attribute byte count", which nothing uses.
+        writer.write_all(&[0, 0])?;
+    }
+    Ok(())
+}
+
+pub fn is_watertight(positions: &[[f32; 3]], indices: &[usize]) -> bool {
+    watertight(&validate(positions, indices))
+}
+
+// No holes, no edges shared by more than two triangles and no flipped
+// triangles. Degenerate triangles and pinched vertices don't bother slicers.
+fn watertight(report: &TopologyReport) -> bool {
+    report.is_closed()
+        && report.non_manifold_edges.is_empty()
+        && report.inconsistent_edges.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_meshes::sphere;
+
+    fn float(bytes: &[u8], offset: usize) -> f32 {
+        f32::from_le_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ])
+    }
+
+    #[test]
+    fn round_trip() {
+        let (positions, indices) = sphere();
+        let mut output = Vec::new();
+        write_stl(&mut output, &positions, &indices, true).unwrap();
+        let triangles = indices.len() / 3;
+        assert_eq!(output.len(), 80 + 4 + triangles * 50);
+        assert_eq!(&output[..12], b"surface-nets");
+        let count = u32::from_le_bytes([output[80], output[81], output[82], output[83]]);
+        assert_eq!(count as usize, triangles);
+        for (facet, corners) in indices.chunks(3).enumerate() {
+            let start = 84 + facet * 50;
+            let [a, b, c] = triangle(&positions, corners);
+            let normal = normalize(cross(sub(b, a), sub(c, a)));
+            for (vector, expected) in [normal, a, b, c].iter().enumerate() {
+                for (axis, &value) in expected.iter().enumerate() {
+                    assert_eq!(float(&output, start + vector * 12 + axis * 4), value);
+                }
+            }
+        }
+        assert!(is_watertight(&positions, &indices));
+    }
+
+    #[test]
+    fn holes_fail_the_watertight_check() {
+        let (positions, mut indices) = sphere();
+        indices.truncate(indices.len() - 3);
+        assert!(!is_watertight(&positions, &indices));
+        let mut output = Vec::new();
+        let error = write_stl(&mut output, &positions, &indices, true).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(output.is_empty());
+        write_stl(&mut output, &positions, &indices, false).unwrap();
+        assert!(!output.is_empty());
+    }
+
+    #[test]
+    fn invalid_indices_are_refused() {
+        let positions = [[0.0; 3]; 3];
+        for indices in &[&[0, 1][..], &[0, 1, 3][..]] {
+            let mut output = Vec::new();
+            let error = write_stl(&mut output, &positions, indices, false).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            assert!(output.is_empty());
+        }
+    }
+}
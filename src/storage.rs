@@ -0,0 +1,610 @@
+// Compact binary storage for sampled volumes, e.g. for save games or level
+// editors.
+//
+// Values are first quantized to 8 or 16 bits within a narrow band around the
+// surface: everything further than `band` from the surface is clamped, which
+// loses nothing surface_net cares about and turns most of the volume into
+// runs of identical values. The grid is then split into cubic chunks, each
+// stored with whichever of these is smallest:
+//   - uniform: a single value for the whole chunk
+//   - run-length: (run length, value) pairs
+//   - palette: the distinct values, then bit-packed indices into them
+//   - raw: every value
+// Every chunk has its own header with a checksum, so corrupt files are
+// reported as InvalidData errors instead of producing garbage (or panicking).
+//
+// Layout, all little endian:
+//   header: "SNVG", version u16, quantization u8 (1 or 2 bytes per value),
+//           0u8, size 3 x u32, origin 3 x f32, spacing 3 x f32, band f32,
+//           chunk size u32, checksum u32 (of everything before it)
+//   chunks, in Z, Y, X order: encoding u8, payload length u32,
+//           checksum u32 (of the payload), payload
+
+use std::io::{self, Read, Write};
+
+use grid::Grid;
+
+const MAGIC: &[u8; 4] = b"SNVG";
+const VERSION: u16 = 1;
+// Chunks are at most this many samples along each axis.
+const MAX_CHUNK_SIZE: usize = 256;
+// Refuse to allocate anything bigger than this many samples, however the
+// header was damaged.
+const MAX_SAMPLES: usize = 1 << 31;
+
+const UNIFORM: u8 = 0;
+const RUN_LENGTH: u8 = 1;
+const PALETTE: u8 = 2;
+const RAW: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantization {
+    I8,
+    I16,
+}
+
+impl Quantization {
+    fn max(self) -> i16 {
+        match self {
+            Quantization::I8 => i8::MAX as i16,
+            Quantization::I16 => i16::MAX,
+        }
+    }
+
+    fn bytes(self) -> usize {
+        match self {
+            Quantization::I8 => 1,
+            Quantization::I16 => 2,
+        }
+    }
+}
+
+// The exact contents of a stored volume. Writing and reading one back gives
+// an identical QuantizedGrid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizedGrid {
+    pub size: [usize; 3],
+    pub origin: [f32; 3],
+    pub spacing: [f32; 3],
+    pub quantization: Quantization,
+    // Distance that maps to the largest quantized value.
+    pub band: f32,
+    // X varies fastest. Always within +-quantization's max.
+    pub data: Vec<i16>,
+}
+
+impl QuantizedGrid {
+    pub fn new(grid: &Grid, quantization: Quantization, band: f32) -> QuantizedGrid {
+        let max = quantization.max() as f32;
+        let data = grid
+            .data
+            .iter()
+            .map(|&value| (value / band * max).round().clamp(-max, max) as i16)
+            .collect();
+        QuantizedGrid {
+            size: grid.size,
+            origin: grid.origin,
+            spacing: grid.spacing,
+            quantization,
+            band,
+            data,
+        }
+    }
+
+    pub fn to_grid(&self) -> Grid {
+        let mut grid = Grid::new(self.size, self.origin, self.spacing);
+        let scale = self.band / self.quantization.max() as f32;
+        for (value, &quantized) in grid.data.iter_mut().zip(&self.data) {
+            *value = quantized as f32 * scale;
+        }
+        grid.background = self.band;
+        grid
+    }
+}
+
+pub fn write_grid<W: Write>(
+    writer: &mut W,
+    grid: &QuantizedGrid,
+    chunk_size: usize,
+) -> io::Result<()> {
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(invalid_input("chunk size must be from 1 to 256"));
+    }
+    // Same limits as read_grid, so that everything written can be read back.
+    let total = grid.size[0]
+        .checked_mul(grid.size[1])
+        .and_then(|total| total.checked_mul(grid.size[2]))
+        .filter(|&total| total <= MAX_SAMPLES)
+        .ok_or_else(|| invalid_input("volume is too large to store"))?;
+    if grid.data.len() != total {
+        return Err(invalid_input("grid data does not match its size"));
+    }
+    if !(grid.band > 0.0 && grid.band.is_finite()) {
+        return Err(invalid_input("band must be positive and finite"));
+    }
+    let max = grid.quantization.max();
+    if grid.data.iter().any(|&value| value < -max || value > max) {
+        return Err(invalid_input("value outside of the quantization range"));
+    }
+    let mut header = Vec::new();
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.push(grid.quantization.bytes() as u8);
+    header.push(0);
+    for &size in &grid.size {
+        header.extend_from_slice(&(size as u32).to_le_bytes());
+    }
+    for value in grid.origin.iter().chain(&grid.spacing) {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    header.extend_from_slice(&grid.band.to_le_bytes());
+    header.extend_from_slice(&(chunk_size as u32).to_le_bytes());
+    let checksum = fnv1a(&header);
+    header.extend_from_slice(&checksum.to_le_bytes());
+    writer.write_all(&header)?;
+
+    for chunk in 0..chunk_count(grid.size, chunk_size) {
+        let (low, high) = chunk_range(grid.size, chunk_size, chunk);
+        let values = chunk_values(grid, low, high);
+        let (encoding, payload) = encode(&values, grid.quantization);
+        writer.write_all(&[encoding])?;
+        writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+        writer.write_all(&fnv1a(&payload).to_le_bytes())?;
+        writer.write_all(&payload)?;
+    }
+    Ok(())
+}
+
+pub fn read_grid<R: Read>(reader: &mut R) -> io::Result<QuantizedGrid> {
+    let mut header = [0; 56];
+    read_exact(reader, &mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid_data("not a stored volume (bad magic)"));
+    }
+    if u32_at(&header, 52) != fnv1a(&header[..52]) {
+        return Err(invalid_data("volume header checksum mismatch"));
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        return Err(invalid_data(&format!(
+            "unsupported volume version {}",
+            version
+        )));
+    }
+    let quantization = match header[6] {
+        1 => Quantization::I8,
+        2 => Quantization::I16,
+        _ => return Err(invalid_data("unknown quantization")),
+    };
+    let size = [
+        u32_at(&header, 8) as usize,
+        u32_at(&header, 12) as usize,
+        u32_at(&header, 16) as usize,
+    ];
+    let float = |offset| f32::from_bits(u32_at(&header, offset));
+    let origin = [float(20), float(24), float(28)];
+    let spacing = [float(32), float(36), float(40)];
+    let band = float(44);
+    if !(band > 0.0 && band.is_finite()) {
+        return Err(invalid_data("band is out of range"));
+    }
+    let chunk_size = u32_at(&header, 48) as usize;
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(invalid_data("chunk size is out of range"));
+    }
+    let total = size[0]
+        .checked_mul(size[1])
+        .and_then(|total| total.checked_mul(size[2]))
+        .filter(|&total| total <= MAX_SAMPLES)
+        .ok_or_else(|| invalid_data("volume size is out of range"))?;
+
+    // Everything is decoded before the full grid is allocated, so that a
+    // truncated file fails without first allocating for its claimed size.
+    // Uniform chunks stay a single value until then.
+    let max = quantization.max();
+    let mut decoded = Vec::new();
+    for chunk in 0..chunk_count(size, chunk_size) {
+        let (low, high) = chunk_range(size, chunk_size, chunk);
+        let count = (high[0] - low[0]) * (high[1] - low[1]) * (high[2] - low[2]);
+        let mut chunk_header = [0; 9];
+        read_exact(reader, &mut chunk_header)?;
+        let length = u32_at(&chunk_header, 1) as usize;
+        // Nothing is ever stored bigger than raw.
+        if length > count * quantization.bytes() {
+            return Err(invalid_data("chunk payload is too long"));
+        }
+        let mut payload = vec![0; length];
+        read_exact(reader, &mut payload)?;
+        if u32_at(&chunk_header, 5) != fnv1a(&payload) {
+            return Err(invalid_data("chunk checksum mismatch"));
+        }
+        let values = decode(chunk_header[0], &payload, count, quantization)?;
+        if values.iter().any(|&value| value < -max || value > max) {
+            return Err(invalid_data("value outside of the quantization range"));
+        }
+        decoded.push(values);
+    }
+
+    let mut data = vec![0; total];
+    for (chunk, values) in decoded.iter().enumerate() {
+        let (low, high) = chunk_range(size, chunk_size, chunk);
+        let mut index = 0;
+        for z in low[2]..high[2] {
+            for y in low[1]..high[1] {
+                for x in low[0]..high[0] {
+                    // A uniform chunk is a single value.
+                    data[(z * size[1] + y) * size[0] + x] = values[index.min(values.len() - 1)];
+                    index += 1;
+                }
+            }
+        }
+    }
+    Ok(QuantizedGrid {
+        size,
+        origin,
+        spacing,
+        quantization,
+        band,
+        data,
+    })
+}
+
+fn chunk_count(size: [usize; 3], chunk_size: usize) -> usize {
+    size.iter().map(|&size| size.div_ceil(chunk_size)).product()
+}
+
+// (low, high) sample range of the chunk'th chunk in storage order.
+fn chunk_range(size: [usize; 3], chunk_size: usize, chunk: usize) -> ([usize; 3], [usize; 3]) {
+    let counts = [size[0].div_ceil(chunk_size), size[1].div_ceil(chunk_size)];
+    let low = [
+        chunk % counts[0] * chunk_size,
+        chunk / counts[0] % counts[1] * chunk_size,
+        chunk / counts[0] / counts[1] * chunk_size,
+    ];
+    let high = [
+        (low[0] + chunk_size).min(size[0]),
+        (low[1] + chunk_size).min(size[1]),
+        (low[2] + chunk_size).min(size[2]),
+    ];
+    (low, high)
+}
+
+fn chunk_values(grid: &QuantizedGrid, low: [usize; 3], high: [usize; 3]) -> Vec<i16> {
+    let mut values = Vec::new();
+    for z in low[2]..high[2] {
+        for y in low[1]..high[1] {
+            let row = (z * grid.size[1] + y) * grid.size[0];
+            values.extend_from_slice(&grid.data[row + low[0]..row + high[0]]);
+        }
+    }
+    values
+}
+
+fn encode(values: &[i16], quantization: Quantization) -> (u8, Vec<u8>) {
+    let first = values[0];
+    if values.iter().all(|&value| value == first) {
+        let mut payload = Vec::new();
+        push_value(&mut payload, first, quantization);
+        return (UNIFORM, payload);
+    }
+    let mut best = (RAW, Vec::new());
+    for &value in values {
+        push_value(&mut best.1, value, quantization);
+    }
+    let run_length = encode_run_length(values, quantization);
+    if run_length.len() < best.1.len() {
+        best = (RUN_LENGTH, run_length);
+    }
+    if let Some(palette) = encode_palette(values, quantization) {
+        if palette.len() < best.1.len() {
+            best = (PALETTE, palette);
+        }
+    }
+    best
+}
+
+// Runs are at most u16::MAX long, longer ones are split.
+fn encode_run_length(values: &[i16], quantization: Quantization) -> Vec<u8> {
+    let mut payload = Vec::new();
+    let mut index = 0;
+    while index < values.len() {
+        let value = values[index];
+        let mut run = 1;
+        while index + run < values.len() && values[index + run] == value && run < u16::MAX as usize
+        {
+            run += 1;
+        }
+        payload.extend_from_slice(&(run as u16).to_le_bytes());
+        push_value(&mut payload, value, quantization);
+        index += run;
+    }
+    payload
+}
+
+// Only for chunks with at most 256 distinct values.
+fn encode_palette(values: &[i16], quantization: Quantization) -> Option<Vec<u8>> {
+    let mut palette = values.to_vec();
+    palette.sort_unstable();
+    palette.dedup();
+    if palette.len() > 256 {
+        return None;
+    }
+    let bits = palette_bits(palette.len());
+    let mut payload = vec![(palette.len() - 1) as u8];
+    for &value in &palette {
+        push_value(&mut payload, value, quantization);
+    }
+    let mut packed = vec![0u8; (values.len() * bits as usize).div_ceil(8)];
+    for (index, value) in values.iter().enumerate() {
+        let entry = palette.binary_search(value).unwrap();
+        for bit in 0..bits as usize {
+            if entry >> bit & 1 != 0 {
+                let position = index * bits as usize + bit;
+                packed[position / 8] |= 1 << (position % 8);
+            }
+        }
+    }
+    payload.extend(packed);
+    Some(payload)
+}
+
+// Uniform chunks decode to their single value, everything else to all
+// `count` values.
+fn decode(
+    encoding: u8,
+    payload: &[u8],
+    count: usize,
+    quantization: Quantization,
+) -> io::Result<Vec<i16>> {
+    let bytes = quantization.bytes();
+    match encoding {
+        UNIFORM => {
+            if payload.len() != bytes {
+                return Err(invalid_data("uniform chunk has the wrong length"));
+            }
+            Ok(vec![value_at(payload, 0, quantization)])
+        }
+        RAW => {
+            if payload.len() != count * bytes {
+                return Err(invalid_data("raw chunk has the wrong length"));
+            }
+            Ok((0..count)
+                .map(|index| value_at(payload, index * bytes, quantization))
+                .collect())
+        }
+        RUN_LENGTH => {
+            if !payload.len().is_multiple_of(2 + bytes) {
+                return Err(invalid_data("run-length chunk has the wrong length"));
+            }
+            let mut values = Vec::with_capacity(count);
+            for run in payload.chunks_exact(2 + bytes) {
+                let length = u16::from_le_bytes([run[0], run[1]]) as usize;
+                if length == 0 || values.len() + length > count {
+                    return Err(invalid_data("run-length chunk has the wrong length"));
+                }
+                let value = value_at(run, 2, quantization);
+                values.extend((0..length).map(|_| value));
+            }
+            if values.len() != count {
+                return Err(invalid_data("run-length chunk has the wrong length"));
+            }
+            Ok(values)
+        }
+        PALETTE => {
+            let entries = *payload
+                .first()
+                .ok_or_else(|| invalid_data("palette chunk has the wrong length"))?
+                as usize
+                + 1;
+            let bits = palette_bits(entries) as usize;
+            let packed_start = 1 + entries * bytes;
+            if payload.len() != packed_start + (count * bits).div_ceil(8) {
+                return Err(invalid_data("palette chunk has the wrong length"));
+            }
+            let palette = (0..entries)
+                .map(|entry| value_at(payload, 1 + entry * bytes, quantization))
+                .collect::<Vec<_>>();
+            let packed = &payload[packed_start..];
+            let mut values = Vec::with_capacity(count);
+            for index in 0..count {
+                let mut entry = 0;
+                for bit in 0..bits {
+                    let position = index * bits + bit;
+                    entry |= ((packed[position / 8] >> (position % 8)) as usize & 1) << bit;
+                }
+                let value = palette
+                    .get(entry)
+                    .ok_or_else(|| invalid_data("palette index out of range"))?;
+                values.push(*value);
+            }
+            Ok(values)
+        }
+        _ => Err(invalid_data(&format!(
+            "unknown chunk encoding {}",
+            encoding
+        ))),
+    }
+}
+
+fn palette_bits(entries: usize) -> u32 {
+    (usize::BITS - (entries - 1).leading_zeros()).max(1)
+}
+
+fn push_value(payload: &mut Vec<u8>, value: i16, quantization: Quantization) {
+    match quantization {
+        Quantization::I8 => payload.push(value as i8 as u8),
+        Quantization::I16 => payload.extend_from_slice(&value.to_le_bytes()),
+    }
+}
+
+fn value_at(bytes: &[u8], offset: usize, quantization: Quantization) -> i16 {
+    match quantization {
+        Quantization::I8 => bytes[offset] as i8 as i16,
+        Quantization::I16 => i16::from_le_bytes([bytes[offset], bytes[offset + 1]]),
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash = 0x811c_9dc5u32;
+    for &byte in bytes {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+// read_exact, but running out of data is corruption rather than an
+// UnexpectedEof, so that all format problems are reported the same way.
+fn read_exact<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<()> {
+    reader.read_exact(buffer).map_err(|error| {
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            invalid_data("stored volume is truncated")
+        } else {
+            error
+        }
+    })
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(quantization: Quantization, band: f32) -> QuantizedGrid {
+        let mut grid = Grid::new([20, 13, 9], [1.0, -2.0, 0.5], [0.5, 0.5, 1.0]);
+        for z in 0..9 {
+            for y in 0..13 {
+                for x in 0..20 {
+                    let p = grid.position(x, y, z);
+                    let (dx, dy, dz) = (p[0] - 5.0, p[1] - 1.0, p[2] - 4.0);
+                    grid.set(x, y, z, (dx * dx + dy * dy + dz * dz).sqrt() - 3.0);
+                }
+            }
+        }
+        QuantizedGrid::new(&grid, quantization, band)
+    }
+
+    fn write(grid: &QuantizedGrid, chunk_size: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_grid(&mut bytes, grid, chunk_size).unwrap();
+        bytes
+    }
+
+    fn assert_invalid(bytes: &[u8]) {
+        let error = read_grid(&mut &bytes[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn round_trip() {
+        for &quantization in &[Quantization::I8, Quantization::I16] {
+            let grid = sphere(quantization, 1.5);
+            for &chunk_size in &[1, 4, 7, 256] {
+                let bytes = write(&grid, chunk_size);
+                assert_eq!(read_grid(&mut &bytes[..]).unwrap(), grid);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_of_noise() {
+        // Every value different, so most chunks end up raw.
+        let mut grid = sphere(Quantization::I16, 1.5);
+        let mut state = 12345u32;
+        for value in &mut grid.data {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            *value = (state >> 16) as i16 / 2;
+        }
+        let bytes = write(&grid, 8);
+        assert_eq!(read_grid(&mut &bytes[..]).unwrap(), grid);
+    }
+
+    #[test]
+    fn mostly_uniform_grids_are_small() {
+        // Most samples are clamped to +-band, in runs or whole chunks.
+        let grid = sphere(Quantization::I8, 0.5);
+        let bytes = write(&grid, 8);
+        assert!(bytes.len() < grid.data.len() / 2);
+    }
+
+    #[test]
+    fn corruption_is_invalid_data() {
+        let bytes = write(&sphere(Quantization::I8, 1.5), 4);
+        assert_invalid(&[]);
+        assert_invalid(&bytes[..40]);
+        assert_invalid(&bytes[..bytes.len() - 1]);
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert_invalid(&magic);
+        let mut header = bytes.clone();
+        header[10] ^= 1;
+        assert_invalid(&header);
+        let mut payload = bytes.clone();
+        let last = payload.len() - 1;
+        payload[last] ^= 0x55;
+        assert_invalid(&payload);
+    }
+
+    #[test]
+    fn damaged_header_fields_are_invalid_data() {
+        let bytes = write(&sphere(Quantization::I8, 1.5), 4);
+        // Rewrites a header field and fixes up the checksum, so that only the
+        // field itself is wrong.
+        let patched = |offset: usize, value: u32| {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            let checksum = fnv1a(&bytes[..52]);
+            bytes[52..56].copy_from_slice(&checksum.to_le_bytes());
+            bytes
+        };
+        assert_invalid(&patched(48, 0));
+        assert_invalid(&patched(48, 257));
+        assert_invalid(&patched(8, u32::MAX));
+        assert_invalid(&patched(8, 40));
+        assert_invalid(&patched(44, 0.0f32.to_bits()));
+        assert_invalid(&patched(44, f32::NAN.to_bits()));
+    }
+
+    #[test]
+    fn inconsistent_grids_are_refused() {
+        let mut grid = sphere(Quantization::I8, 1.5);
+        for &chunk_size in &[0, 257] {
+            let error = write_grid(&mut Vec::new(), &grid, chunk_size).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        let refused = |grid: &QuantizedGrid| {
+            let mut bytes = Vec::new();
+            let error = write_grid(&mut bytes, grid, 4).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            assert!(bytes.is_empty());
+        };
+        for &band in &[0.0, -1.5, f32::NAN, f32::INFINITY] {
+            let mut banded = grid.clone();
+            banded.band = band;
+            refused(&banded);
+        }
+        for &value in &[200, -128] {
+            let mut out_of_range = grid.clone();
+            out_of_range.data[7] = value;
+            refused(&out_of_range);
+        }
+        grid.data.pop();
+        refused(&grid);
+    }
+}
@@ -0,0 +1,207 @@
+// Per-vertex tangents for normal mapping, as [x, y, z, w] where the bitangent
+// is cross(normal, tangent) * w, which is the layout glTF and most engines
+// use.
+//
+// Two flavours:
+//   - triplanar_tangents, aligned with the box projection texture axes of the
+//     normal's dominant axis. These only depend on the normal, so chunks
+//     meshed separately agree exactly along their seams.
+//   - uv_tangents, from a mesh's own UVs, in the spirit of MikkTSpace:
+//     angle-weighted per-triangle tangents, Gram-Schmidt orthogonalized
+//     against the vertex normal. A vertex on a chunk seam only sees its own
+//     chunk's triangles, so for an exact match between chunks mesh them with
+//     a cell of overlap or use triplanar_tangents.
+
+use math::{cross, dot, normalize, scale, sub, triangle};
+
+// Texture (u, v) directions for box/triplanar projection onto the plane of the
+// normal's dominant axis. They're picked so that cross(u, v) points along the
+// normal, so textures are never mirrored when seen from outside.
+pub fn box_projection_axes(normal: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+    let abs = [normal[0].abs(), normal[1].abs(), normal[2].abs()];
+    if abs[0] >= abs[1] && abs[0] >= abs[2] {
+        let sign = normal[0].signum();
+        ([0.0, 0.0, -sign], [0.0, 1.0, 0.0])
+    } else if abs[1] >= abs[2] {
+        let sign = normal[1].signum();
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -sign])
+    } else {
+        let sign = normal[2].signum();
+        ([sign, 0.0, 0.0], [0.0, 1.0, 0.0])
+    }
+}
+
+pub fn triplanar_tangents(normals: &[[f32; 3]]) -> Vec<[f32; 4]> {
+    normals
+        .iter()
+        .map(|&normal| {
+            let (u, v) = box_projection_axes(normal);
+            orthogonalize(normal, u, v)
+        })
+        .collect()
+}
+
+// None unless there's one normal and UV per vertex, and the indices are
+// triangles of the vertices.
+pub fn uv_tangents(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    uvs: &[[f32; 2]],
+    indices: &[usize],
+) -> Option<Vec<[f32; 4]>> {
+    if normals.len() != positions.len()
+        || uvs.len() != positions.len()
+        || !indices.len().is_multiple_of(3)
+        || indices.iter().any(|&index| index >= positions.len())
+    {
+        return None;
+    }
+    let mut tangents = vec![[0.0; 3]; positions.len()];
+    let mut bitangents = vec![[0.0; 3]; positions.len()];
+    for corners in indices.chunks(3) {
+        let points = triangle(positions, corners);
+        let edge1 = sub(points[1], points[0]);
+        let edge2 = sub(points[2], points[0]);
+        let uv0 = uvs[corners[0]];
+        let duv1 = [uvs[corners[1]][0] - uv0[0], uvs[corners[1]][1] - uv0[1]];
+        let duv2 = [uvs[corners[2]][0] - uv0[0], uvs[corners[2]][1] - uv0[1]];
+        let determinant = duv1[0] * duv2[1] - duv2[0] * duv1[1];
+        if determinant == 0.0 {
+            // Degenerate UVs, the triangle says nothing about the tangent.
+            continue;
+        }
+        // Only directions matter, so the 1/determinant scale is dropped but
+        // its sign is kept.
+        let sign = determinant.signum();
+        let tangent = normalize(scale(
+            sub(scale(edge1, duv2[1]), scale(edge2, duv1[1])),
+            sign,
+        ));
+        let bitangent = normalize(scale(
+            sub(scale(edge2, duv1[0]), scale(edge1, duv2[0])),
+            sign,
+        ));
+        // Weighted by the triangle's angle at each corner, like MikkTSpace,
+        // so that the result doesn't depend on how a surface is triangulated.
+        for i in 0..3 {
+            let to_next = normalize(sub(points[(i + 1) % 3], points[i]));
+            let to_previous = normalize(sub(points[(i + 2) % 3], points[i]));
+            let angle = dot(to_next, to_previous).clamp(-1.0, 1.0).acos();
+            let vertex = corners[i];
+            for axis in 0..3 {
+                tangents[vertex][axis] += tangent[axis] * angle;
+                bitangents[vertex][axis] += bitangent[axis] * angle;
+            }
+        }
+    }
+    let tangents = normals
+        .iter()
+        .zip(tangents.iter().zip(&bitangents))
+        .map(|(&normal, (&tangent, &bitangent))| {
+            if dot(tangent, tangent) == 0.0 {
+                // Nothing usable around this vertex, fall back to the
+                // triplanar direction rather than a zero tangent.
+                let (u, v) = box_projection_axes(normal);
+                orthogonalize(normal, u, v)
+            } else {
+                orthogonalize(normal, tangent, bitangent)
+            }
+        })
+        .collect();
+    Some(tangents)
+}
+
+// Tangent from u made perpendicular to the normal, with w saying which way v
+// points relative to cross(normal, tangent).
+fn orthogonalize(normal: [f32; 3], u: [f32; 3], v: [f32; 3]) -> [f32; 4] {
+    let mut tangent = normalize(sub(u, scale(normal, dot(normal, u))));
+    if dot(tangent, tangent) == 0.0 {
+        // u is parallel to the normal, any perpendicular direction will do.
+        let (axis, _) = box_projection_axes(normal);
+        tangent = normalize(sub(axis, scale(normal, dot(normal, axis))));
+    }
+    let w = if dot(cross(normal, tangent), v) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    [tangent[0], tangent[1], tangent[2], w]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_frame(normal: [f32; 3], tangent: [f32; 4], v: [f32; 3]) {
+        let xyz = [tangent[0], tangent[1], tangent[2]];
+        assert!(dot(xyz, normal).abs() < 1e-5, "{:?} {:?}", normal, tangent);
+        assert!((dot(xyz, xyz) - 1.0).abs() < 1e-5);
+        // The bitangent must point the same way as the texture's v.
+        let bitangent = scale(cross(normal, xyz), tangent[3]);
+        assert!(dot(bitangent, v) > 0.0, "{:?} {:?}", normal, tangent);
+    }
+
+    #[test]
+    fn triplanar_frames() {
+        let normals = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+            [0.6, 0.3, -0.2],
+            [-0.1, -0.9, 0.4],
+            [0.3, 0.3, -0.9],
+        ]
+        .iter()
+        .map(|&normal| normalize(normal))
+        .collect::<Vec<_>>();
+        for (&normal, &tangent) in normals.iter().zip(&triplanar_tangents(&normals)) {
+            let (u, v) = box_projection_axes(normal);
+            assert!(dot(cross(u, v), normal) > 0.0);
+            assert!(dot([tangent[0], tangent[1], tangent[2]], u) > 0.0);
+            assert_frame(normal, tangent, v);
+        }
+    }
+
+    #[test]
+    fn frames_follow_the_uvs() {
+        // A slightly bent quad facing +Z, textured with u along X and v
+        // along Y, and then with u mirrored.
+        let positions = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.2],
+            [1.0, 1.0, 0.2],
+            [0.0, 1.0, 0.0],
+        ];
+        let normals = [normalize([-0.2, 0.0, 1.0]); 4];
+        let indices = [0, 1, 2, 0, 2, 3];
+        for &mirror in &[1.0, -1.0] {
+            let uvs = positions
+                .iter()
+                .map(|p| [p[0] * mirror, p[1]])
+                .collect::<Vec<_>>();
+            let tangents = uv_tangents(&positions, &normals, &uvs, &indices).unwrap();
+            for (&normal, &tangent) in normals.iter().zip(&tangents) {
+                assert!(tangent[0] * mirror > 0.9);
+                assert_eq!(tangent[3], mirror);
+                assert_frame(normal, tangent, [0.0, 1.0, 0.0]);
+            }
+        }
+        // Degenerate UVs fall back to the triplanar frame.
+        let tangents = uv_tangents(&positions, &normals, &[[0.5; 2]; 4], &indices).unwrap();
+        assert_eq!(tangents[0], triplanar_tangents(&normals[..1])[0]);
+    }
+
+    #[test]
+    fn refuses_inconsistent_meshes() {
+        let positions = [[0.0; 3]; 3];
+        let normals = [[0.0, 0.0, 1.0]; 3];
+        let uvs = [[0.0; 2]; 3];
+        assert!(uv_tangents(&positions, &normals[..2], &uvs, &[0, 1, 2]).is_none());
+        assert!(uv_tangents(&positions, &normals, &uvs[..2], &[0, 1, 2]).is_none());
+        assert!(uv_tangents(&positions, &normals, &uvs, &[0, 1]).is_none());
+        assert!(uv_tangents(&positions, &normals, &uvs, &[0, 1, 3]).is_none());
+    }
+}
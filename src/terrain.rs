@@ -0,0 +1,242 @@
+// Procedural terrain: fractal noise (fBm or ridged multifractal), optional
+// domain warping, a height bias that turns the noise into ground with air
+// above it, and caves carved out of that with another noise field.
+// Everything is derived from one seed, so the same seed always gives the same
+// world.
+
+use field::Field;
+use noise::Perlin;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Octaves {
+    pub octaves: u32,
+    // Frequency of the first octave, in cycles per world unit.
+    pub frequency: f32,
+    // Each octave's frequency is the previous one's times lacunarity...
+    pub lacunarity: f32,
+    // ...and its amplitude is the previous one's times gain.
+    pub gain: f32,
+}
+
+impl Octaves {
+    pub fn new(octaves: u32, frequency: f32) -> Octaves {
+        Octaves {
+            octaves,
+            frequency,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Fractal {
+    // Smooth rolling hills.
+    Fbm(Octaves),
+    // Sharp ridges and creases, like mountain ranges.
+    Ridged(Octaves),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Caves {
+    pub octaves: Octaves,
+    // How wide the tunnels are, as a fraction of the noise range: noise values
+    // within [-width, width] are hollowed out.
+    pub width: f32,
+}
+
+pub struct Terrain {
+    pub fractal: Fractal,
+    // World-space height of the noise, i.e. how far hills rise above and
+    // valleys sink below ground_height.
+    pub amplitude: f32,
+    // How strongly the field is pulled towards a ground plane at
+    // ground_height (along Y). Zero gives a plain 3d noise field with no
+    // ground at all, 1 gives roughly correct distances near the surface.
+    pub height_bias: f32,
+    pub ground_height: f32,
+    // How far (in world units) the domain is pushed around before sampling
+    // the fractal. Zero disables warping.
+    pub warp_strength: f32,
+    pub warp: Octaves,
+    pub caves: Option<Caves>,
+    terrain_noise: Perlin,
+    warp_noise: [Perlin; 3],
+    cave_noise: Perlin,
+}
+
+impl Terrain {
+    // Defaults to gently warped fBm hills with a ground plane at height 0 and
+    // no caves. All the public fields can be changed afterwards.
+    pub fn new(seed: u32) -> Terrain {
+        // Derive a different seed for each noise layer, otherwise they would
+        // all line up with each other.
+        let layer = |index: u32| Perlin::new(seed.wrapping_mul(7919).wrapping_add(index));
+        Terrain {
+            fractal: Fractal::Fbm(Octaves::new(5, 1.0 / 64.0)),
+            amplitude: 16.0,
+            height_bias: 1.0,
+            ground_height: 0.0,
+            warp_strength: 8.0,
+            warp: Octaves::new(2, 1.0 / 128.0),
+            caves: None,
+            terrain_noise: layer(0),
+            warp_noise: [layer(1), layer(2), layer(3)],
+            cave_noise: layer(4),
+        }
+    }
+}
+
+impl Field for Terrain {
+    fn get(&self, point: [f32; 3]) -> f32 {
+        let warped = if self.warp_strength != 0.0 {
+            domain_warp(&self.warp_noise, point, &self.warp, self.warp_strength)
+        } else {
+            point
+        };
+        let height = match self.fractal {
+            Fractal::Fbm(ref octaves) => fbm(&self.terrain_noise, warped, octaves),
+            Fractal::Ridged(ref octaves) => ridged(&self.terrain_noise, warped, octaves),
+        };
+        let mut value =
+            self.height_bias * (point[1] - self.ground_height) - self.amplitude * height;
+        // The noise changes by about `frequency` per world unit, so divide by
+        // that to get something resembling a distance. Without a positive
+        // frequency there's nothing to divide by, and no caves.
+        if let Some(caves) = self.caves.filter(|caves| caves.octaves.frequency > 0.0) {
+            let tunnel = fbm(&self.cave_noise, point, &caves.octaves);
+            let carve = (caves.width - tunnel.abs()) / caves.octaves.frequency;
+            value = value.max(carve);
+        }
+        value
+    }
+}
+
+// Fractal Brownian motion: a sum of octaves of noise, normalized to roughly
+// [-1, 1].
+pub fn fbm(noise: &Perlin, point: [f32; 3], octaves: &Octaves) -> f32 {
+    let mut frequency = octaves.frequency;
+    let mut amplitude = 1.0;
+    let mut sum = 0.0;
+    let mut total_amplitude = 0.0;
+    for _ in 0..octaves.octaves {
+        sum += amplitude * noise.get(scale(point, frequency));
+        total_amplitude += amplitude;
+        frequency *= octaves.lacunarity;
+        amplitude *= octaves.gain;
+    }
+    if total_amplitude == 0.0 {
+        0.0
+    } else {
+        sum / total_amplitude
+    }
+}
+
+// Ridged multifractal (after Musgrave): each octave is folded with 1 - |n| to
+// make creases, and weighted by the previous octave so detail collects on the
+// ridges instead of in the valleys. Normalized to roughly [-1, 1].
+pub fn ridged(noise: &Perlin, point: [f32; 3], octaves: &Octaves) -> f32 {
+    let mut frequency = octaves.frequency;
+    let mut amplitude = 1.0;
+    let mut weight = 1.0;
+    let mut sum = 0.0;
+    let mut total_amplitude = 0.0;
+    for _ in 0..octaves.octaves {
+        let fold = 1.0 - noise.get(scale(point, frequency)).abs();
+        let signal = fold * fold * weight;
+        weight = (signal * 2.0).clamp(0.0, 1.0);
+        sum += amplitude * signal;
+        total_amplitude += amplitude;
+        frequency *= octaves.lacunarity;
+        amplitude *= octaves.gain;
+    }
+    if total_amplitude == 0.0 {
+        0.0
+    } else {
+        sum / total_amplitude * 2.0 - 1.0
+    }
+}
+
+// Offsets the point by a vector of three independent fBm fields, which bends
+// straight features of whatever is sampled at the result into swirls.
+pub fn domain_warp(
+    noise: &[Perlin; 3],
+    point: [f32; 3],
+    octaves: &Octaves,
+    strength: f32,
+) -> [f32; 3] {
+    [
+        point[0] + strength * fbm(&noise[0], point, octaves),
+        point[1] + strength * fbm(&noise[1], point, octaves),
+        point[2] + strength * fbm(&noise[2], point, octaves),
+    ]
+}
+
+fn scale(point: [f32; 3], factor: f32) -> [f32; 3] {
+    [point[0] * factor, point[1] * factor, point[2] * factor]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> Vec<[f32; 3]> {
+        let mut points = Vec::new();
+        for x in 0..8 {
+            for z in 0..8 {
+                for y in -4..4 {
+                    points.push([x as f32 * 13.0, y as f32 * 5.0, z as f32 * 11.0]);
+                }
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn same_seed_same_world() {
+        let a = Terrain::new(7);
+        let b = Terrain::new(7);
+        let c = Terrain::new(8);
+        assert!(points().iter().all(|&p| a.get(p) == b.get(p)));
+        assert!(points().iter().any(|&p| a.get(p) != c.get(p)));
+    }
+
+    #[test]
+    fn ground_below_air_above() {
+        let terrain = Terrain::new(1);
+        for &[x, _, z] in &points() {
+            assert!(terrain.get([x, 100.0, z]) > 0.0);
+            assert!(terrain.get([x, -100.0, z]) < 0.0);
+        }
+    }
+
+    #[test]
+    fn caves_carve_air_out_of_the_ground() {
+        let mut terrain = Terrain::new(3);
+        let underground: Vec<_> = points()
+            .into_iter()
+            .map(|[x, y, z]| [x, y - 60.0, z])
+            .collect();
+        assert!(underground.iter().all(|&p| terrain.get(p) < 0.0));
+        terrain.caves = Some(Caves {
+            octaves: Octaves::new(3, 1.0 / 16.0),
+            width: 0.2,
+        });
+        assert!(underground.iter().any(|&p| terrain.get(p) > 0.0));
+    }
+
+    #[test]
+    fn caves_without_frequency_carve_nothing() {
+        let plain = Terrain::new(5);
+        let mut terrain = Terrain::new(5);
+        for &frequency in &[0.0, -1.0, f32::NAN] {
+            terrain.caves = Some(Caves {
+                octaves: Octaves::new(3, frequency),
+                width: 0.2,
+            });
+            for &p in &points() {
+                assert_eq!(terrain.get(p), plain.get(p));
+            }
+        }
+    }
+}
@@ -0,0 +1,16 @@
+// Meshes shared by the tests of several modules.
+
+use surface_net;
+
+// A closed, manifold sphere of radius 5.5 around (8, 8, 8), in grid units.
+pub fn sphere() -> (Vec<[f32; 3]>, Vec<usize>) {
+    let (positions, _, indices) = surface_net(
+        16,
+        &|x, y, z| {
+            let (x, y, z) = (x as f32 - 8.0, y as f32 - 8.0, z as f32 - 8.0);
+            (x * x + y * y + z * z).sqrt() - 5.5
+        },
+        false,
+    );
+    (positions, indices)
+}
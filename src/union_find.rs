@@ -0,0 +1,15 @@
+// Disjoint sets over 0..n, for the modules that need to group vertices,
+// edges or triangles into connected pieces.
+
+pub fn find(parents: &mut [usize], mut item: usize) -> usize {
+    while parents[item] != item {
+        parents[item] = parents[parents[item]];
+        item = parents[item];
+    }
+    item
+}
+
+pub fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    parents[a] = b;
+}
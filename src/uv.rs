@@ -0,0 +1,178 @@
+// Automatic texture coordinates by box (dominant axis) projection.
+//
+// Every vertex is projected onto the plane of its normal's dominant axis.
+// Triangles whose corners disagree on that axis all get the axis of their
+// summed normals, and the corners that disagree are replaced by duplicates
+// carrying the other projection, so every triangle is textured from a single
+// plane and seams are sharp rather than smeared.
+//
+// UVs are world position times texture_scale (UV units per world unit), so
+// chunks whose positions are in world space tile continuously.
+
+use std::collections::HashMap;
+
+use math::{add, dot};
+use tangents::box_projection_axes;
+
+const FACE_NORMALS: [[f32; 3]; 6] = [
+    [1.0, 0.0, 0.0],
+    [-1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, -1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [0.0, 0.0, -1.0],
+];
+
+// Returns (vertices, uvs, indices) for the new mesh: vertices[i] is the input
+// vertex that new vertex i is a copy of (the first positions.len() are the
+// input vertices themselves, duplicates come after), so positions, normals
+// and any other attribute can be carried over with remap.
+// For tangents to match, compute them from the new mesh with uv_tangents
+// rather than with triplanar_tangents: duplicates keep their original normal.
+// None unless there's one normal per vertex and the indices are triangles of
+// the vertices.
+#[allow(clippy::type_complexity)]
+pub fn box_uvs(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    indices: &[usize],
+    texture_scale: f32,
+) -> Option<(Vec<usize>, Vec<[f32; 2]>, Vec<usize>)> {
+    if normals.len() != positions.len()
+        || !indices.len().is_multiple_of(3)
+        || indices.iter().any(|&index| index >= positions.len())
+    {
+        return None;
+    }
+    let faces = normals
+        .iter()
+        .map(|&normal| dominant_face(normal))
+        .collect::<Vec<_>>();
+    let mut vertices = (0..positions.len()).collect::<Vec<_>>();
+    let mut vertex_faces = faces.clone();
+    let mut duplicates = HashMap::new();
+    let mut new_indices = Vec::with_capacity(indices.len());
+    for corners in indices.chunks(3) {
+        let face =
+            if faces[corners[0]] == faces[corners[1]] && faces[corners[0]] == faces[corners[2]] {
+                faces[corners[0]]
+            } else {
+                let normal = corners
+                    .iter()
+                    .fold([0.0; 3], |sum, &corner| add(sum, normals[corner]));
+                dominant_face(normal)
+            };
+        for &corner in corners {
+            if faces[corner] == face {
+                new_indices.push(corner);
+            } else {
+                let duplicate = *duplicates.entry((corner, face)).or_insert_with(|| {
+                    vertices.push(corner);
+                    vertex_faces.push(face);
+                    vertices.len() - 1
+                });
+                new_indices.push(duplicate);
+            }
+        }
+    }
+    let uvs = vertices
+        .iter()
+        .zip(&vertex_faces)
+        .map(|(&vertex, &face)| {
+            let (u, v) = box_projection_axes(FACE_NORMALS[face]);
+            let position = positions[vertex];
+            [
+                dot(position, u) * texture_scale,
+                dot(position, v) * texture_scale,
+            ]
+        })
+        .collect();
+    Some((vertices, uvs, new_indices))
+}
+
+// Per-vertex values for the mesh returned by box_uvs.
+pub fn remap<T: Copy>(values: &[T], vertices: &[usize]) -> Vec<T> {
+    vertices.iter().map(|&vertex| values[vertex]).collect()
+}
+
+// Index into FACE_NORMALS, breaking ties the same way as box_projection_axes.
+fn dominant_face(normal: [f32; 3]) -> usize {
+    let abs = [normal[0].abs(), normal[1].abs(), normal[2].abs()];
+    let axis = if abs[0] >= abs[1] && abs[0] >= abs[2] {
+        0
+    } else if abs[1] >= abs[2] {
+        1
+    } else {
+        2
+    };
+    axis * 2 + if normal[axis] < 0.0 { 1 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use surface_net;
+
+    // A cube with slightly rounded edges, so that the normals along them
+    // are mixed and triangles straddle faces.
+    fn rounded_cube(x: usize, y: usize, z: usize) -> f32 {
+        let q = [x, y, z]
+            .iter()
+            .map(|&i| (i as f32 - 7.5).abs() - 4.0)
+            .collect::<Vec<_>>();
+        let outside = q.iter().map(|&q| q.max(0.0).powi(2)).sum::<f32>().sqrt();
+        outside + q[0].max(q[1]).max(q[2]).min(0.0) - 1.5
+    }
+
+    #[test]
+    fn every_triangle_is_projected_from_one_face() {
+        let (positions, normals, indices) = surface_net(16, &rounded_cube, false);
+        let (vertices, uvs, new_indices) = box_uvs(&positions, &normals, &indices, 0.5).unwrap();
+        assert_eq!(
+            &vertices[..positions.len()],
+            &(0..positions.len()).collect::<Vec<_>>()[..]
+        );
+        assert_eq!(uvs.len(), vertices.len());
+        assert_eq!(new_indices.len(), indices.len());
+        assert!(vertices.len() > positions.len());
+
+        let project = |vertex: usize, face: usize| {
+            let (u, v) = box_projection_axes(FACE_NORMALS[face]);
+            let position = positions[vertices[vertex]];
+            [dot(position, u) * 0.5, dot(position, v) * 0.5]
+        };
+        let mut faces = vec![None; vertices.len()];
+        for (corners, original) in new_indices.chunks(3).zip(indices.chunks(3)) {
+            // The duplicates stand in for the same corners.
+            for (&corner, &original) in corners.iter().zip(original) {
+                assert_eq!(vertices[corner], original);
+            }
+            let face = (0..6)
+                .find(|&face| {
+                    corners
+                        .iter()
+                        .all(|&corner| uvs[corner] == project(corner, face))
+                })
+                .unwrap();
+            // A vertex is only ever used with one projection, so triangles
+            // that share it agree on its UV.
+            for &corner in corners {
+                assert!(faces[corner].is_none_or(|other| other == face));
+                faces[corner] = Some(face);
+            }
+        }
+        // And no vertex is duplicated for the same projection twice.
+        let unique = vertices.iter().zip(&faces).collect::<HashSet<_>>();
+        assert_eq!(unique.len(), vertices.len());
+    }
+
+    #[test]
+    fn refuses_inconsistent_meshes() {
+        let positions = [[0.0; 3]; 3];
+        let normals = [[0.0, 0.0, 1.0]; 3];
+        assert!(box_uvs(&positions, &normals[..2], &[0, 1, 2], 1.0).is_none());
+        assert!(box_uvs(&positions, &normals, &[0, 1], 1.0).is_none());
+        assert!(box_uvs(&positions, &normals, &[0, 1, 3], 1.0).is_none());
+    }
+}
@@ -0,0 +1,305 @@
+// Topology checks for meshes, to catch non-manifold output (like surface nets
+// gives for checkerboard-like fields) in code rather than by eye.
+
+use std::collections::{HashMap, HashSet};
+
+use math::{cross, dot, length, sub, triangle};
+use union_find::{find, union};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopologyReport {
+    // Vertices used by at least one triangle.
+    pub vertex_count: usize,
+    pub edge_count: usize,
+    // Triangles that were checked, which leaves out the ones below.
+    pub triangle_count: usize,
+    // Triangles that use a vertex past the end of positions. They're left
+    // out of everything else.
+    pub out_of_range_triangles: Vec<usize>,
+    // Indices after the last whole triangle, which are ignored.
+    pub leftover_indices: usize,
+    // Connected components, counting vertices connected through triangles.
+    pub components: usize,
+    // Edges shared by more than two triangles, as (smaller, larger) index.
+    pub non_manifold_edges: Vec<(usize, usize)>,
+    // Vertices where the triangles around them don't form a single fan, like
+    // two cones touching at their tips.
+    pub non_manifold_vertices: Vec<usize>,
+    // Each loop of edges that belong to only one triangle, as vertices in
+    // order.
+    pub boundary_loops: Vec<Vec<usize>>,
+    // Triangles that repeat a vertex or have (next to) no area.
+    pub degenerate_triangles: Vec<usize>,
+    // Edges whose two triangles both go the same way along them, meaning one
+    // of the two is flipped.
+    pub inconsistent_edges: Vec<(usize, usize)>,
+    // Triangles with the same three vertices as an earlier one, whichever
+    // order they're in.
+    pub duplicate_triangles: Vec<usize>,
+    // V - E + F.
+    pub euler_characteristic: isize,
+    // Total number of handles over all components, from
+    // V - E + F = 2 * components - 2 * genus - boundary loops. Only
+    // meaningful for manifold, consistently wound meshes, so it's None
+    // otherwise.
+    pub genus: Option<usize>,
+}
+
+impl TopologyReport {
+    pub fn is_manifold(&self) -> bool {
+        self.non_manifold_edges.is_empty() && self.non_manifold_vertices.is_empty()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.boundary_loops.is_empty()
+    }
+
+    // Manifold, closed, consistently wound and free of degenerate or
+    // duplicate triangles: fine to 3D print or to voxelize.
+    pub fn is_valid_solid(&self) -> bool {
+        self.is_manifold()
+            && self.is_closed()
+            && self.inconsistent_edges.is_empty()
+            && self.degenerate_triangles.is_empty()
+            && self.duplicate_triangles.is_empty()
+            && self.out_of_range_triangles.is_empty()
+            && self.leftover_indices == 0
+    }
+}
+
+pub fn validate(positions: &[[f32; 3]], indices: &[usize]) -> TopologyReport {
+    let mut triangle_count = 0;
+    let mut out_of_range_triangles = Vec::new();
+    // Directed edges, and the triangles around each undirected one.
+    let mut directed = HashMap::new();
+    let mut edges = HashMap::new();
+    let mut vertex_triangles = HashMap::new();
+    let mut degenerate_triangles = Vec::new();
+    let mut duplicate_triangles = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, corners) in indices.chunks_exact(3).enumerate() {
+        if corners.iter().any(|&corner| corner >= positions.len()) {
+            out_of_range_triangles.push(index);
+            continue;
+        }
+        triangle_count += 1;
+        let mut sorted = [corners[0], corners[1], corners[2]];
+        sorted.sort_unstable();
+        if !seen.insert(sorted) {
+            duplicate_triangles.push(index);
+        }
+        if sorted[0] == sorted[1] || sorted[1] == sorted[2] {
+            degenerate_triangles.push(index);
+        } else {
+            let [a, b, c] = triangle(positions, corners);
+            let longest = dot(sub(b, a), sub(b, a))
+                .max(dot(sub(c, b), sub(c, b)))
+                .max(dot(sub(a, c), sub(a, c)));
+            // Twice the area against the longest edge squared, so that the
+            // test doesn't depend on the mesh's scale.
+            if length(cross(sub(b, a), sub(c, a))) <= 1e-6 * longest {
+                degenerate_triangles.push(index);
+            }
+        }
+        for i in 0..3 {
+            let (from, to) = (corners[i], corners[(i + 1) % 3]);
+            *directed.entry((from, to)).or_insert(0) += 1;
+            edges
+                .entry((from.min(to), from.max(to)))
+                .or_insert_with(Vec::new)
+                .push(index);
+            vertex_triangles
+                .entry(corners[i])
+                .or_insert_with(Vec::new)
+                .push(index);
+        }
+    }
+
+    let mut non_manifold_edges = Vec::new();
+    let mut inconsistent_edges = Vec::new();
+    let mut boundary_next = HashMap::new();
+    for (&(a, b), around) in &edges {
+        match around.len() {
+            1 => {
+                // Follow the boundary the same way as the triangle does.
+                let (from, to) = if directed.contains_key(&(a, b)) {
+                    (a, b)
+                } else {
+                    (b, a)
+                };
+                boundary_next.entry(from).or_insert_with(Vec::new).push(to);
+            }
+            2 => {
+                if directed.get(&(a, b)) != Some(&1) || directed.get(&(b, a)) != Some(&1) {
+                    inconsistent_edges.push((a, b));
+                }
+            }
+            _ => non_manifold_edges.push((a, b)),
+        }
+    }
+    non_manifold_edges.sort_unstable();
+    inconsistent_edges.sort_unstable();
+
+    let mut non_manifold_vertices = vertex_triangles
+        .iter()
+        .filter(|&(&vertex, around)| fan_count(vertex, around, indices) > 1)
+        .map(|(&vertex, _)| vertex)
+        .collect::<Vec<_>>();
+    non_manifold_vertices.sort_unstable();
+
+    let boundary_loops = boundary_loops(boundary_next);
+
+    let vertex_count = vertex_triangles.len();
+    let components = components(indices, positions.len());
+    let euler_characteristic =
+        vertex_count as isize - edges.len() as isize + triangle_count as isize;
+    let doubled_genus =
+        2 * components as isize - boundary_loops.len() as isize - euler_characteristic;
+    let genus = if non_manifold_edges.is_empty()
+        && non_manifold_vertices.is_empty()
+        && inconsistent_edges.is_empty()
+        && doubled_genus >= 0
+        && doubled_genus % 2 == 0
+    {
+        Some(doubled_genus as usize / 2)
+    } else {
+        None
+    };
+
+    TopologyReport {
+        vertex_count,
+        edge_count: edges.len(),
+        triangle_count,
+        out_of_range_triangles,
+        leftover_indices: indices.len() % 3,
+        components,
+        non_manifold_edges,
+        non_manifold_vertices,
+        boundary_loops,
+        degenerate_triangles,
+        inconsistent_edges,
+        duplicate_triangles,
+        euler_characteristic,
+        genus,
+    }
+}
+
+// Number of separate fans the triangles around a vertex make, where two
+// triangles are in the same fan if they share an edge at the vertex.
+fn fan_count(vertex: usize, around: &[usize], indices: &[usize]) -> usize {
+    let mut parents = (0..around.len()).collect::<Vec<_>>();
+    let mut by_neighbour = HashMap::new();
+    for (slot, &triangle) in around.iter().enumerate() {
+        for &other in &indices[triangle * 3..triangle * 3 + 3] {
+            if other != vertex {
+                if let Some(&first) = by_neighbour.get(&other) {
+                    union(&mut parents, slot, first);
+                } else {
+                    by_neighbour.insert(other, slot);
+                }
+            }
+        }
+    }
+    (0..around.len())
+        .filter(|&slot| find(&mut parents, slot) == slot)
+        .count()
+}
+
+// Chains boundary edges into loops. Where several boundary loops touch at a
+// vertex the split between them is arbitrary, but the number of loops is
+// right.
+fn boundary_loops(mut next: HashMap<usize, Vec<usize>>) -> Vec<Vec<usize>> {
+    let mut starts = next.keys().cloned().collect::<Vec<_>>();
+    starts.sort_unstable();
+    let mut loops = Vec::new();
+    for start in starts {
+        while next.get(&start).is_some_and(|targets| !targets.is_empty()) {
+            let mut boundary = vec![start];
+            let mut current = start;
+            while let Some(to) = next.get_mut(&current).and_then(|targets| targets.pop()) {
+                if to == start {
+                    break;
+                }
+                boundary.push(to);
+                current = to;
+            }
+            loops.push(boundary);
+        }
+    }
+    loops
+}
+
+fn components(indices: &[usize], vertex_count: usize) -> usize {
+    let mut parents = (0..vertex_count).collect::<Vec<_>>();
+    let mut used = vec![false; vertex_count];
+    for corners in indices.chunks_exact(3) {
+        if corners.iter().any(|&corner| corner >= vertex_count) {
+            continue;
+        }
+        for &corner in corners {
+            used[corner] = true;
+        }
+        union(&mut parents, corners[0], corners[1]);
+        union(&mut parents, corners[0], corners[2]);
+    }
+    (0..vertex_count)
+        .filter(|&vertex| used[vertex] && find(&mut parents, vertex) == vertex)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_meshes::sphere;
+
+    #[test]
+    fn sphere_is_a_valid_solid() {
+        let (positions, indices) = sphere();
+        let report = validate(&positions, &indices);
+        assert!(report.is_valid_solid(), "{:?}", report);
+        assert_eq!(report.components, 1);
+        assert_eq!(report.euler_characteristic, 2);
+        assert_eq!(report.genus, Some(0));
+    }
+
+    #[test]
+    fn removed_triangle_leaves_a_boundary_loop() {
+        let (positions, mut indices) = sphere();
+        let removed = indices.drain(..3).collect::<Vec<_>>();
+        let report = validate(&positions, &indices);
+        assert!(report.is_manifold());
+        assert!(!report.is_closed());
+        assert_eq!(report.boundary_loops.len(), 1);
+        let mut boundary = report.boundary_loops[0].clone();
+        boundary.sort_unstable();
+        let mut expected = removed;
+        expected.sort_unstable();
+        assert_eq!(boundary, expected);
+        assert_eq!(report.genus, Some(0));
+    }
+
+    #[test]
+    fn flipped_triangle_is_inconsistent() {
+        let (positions, mut indices) = sphere();
+        indices.swap(0, 1);
+        let report = validate(&positions, &indices);
+        assert_eq!(report.inconsistent_edges.len(), 3);
+        assert!(!report.is_valid_solid());
+        assert_eq!(report.genus, None);
+    }
+
+    #[test]
+    fn bad_indices_are_reported() {
+        let (positions, mut indices) = sphere();
+        let triangles = indices.len() / 3;
+        indices.extend_from_slice(&[0, 1, positions.len(), 2, 3]);
+        let report = validate(&positions, &indices);
+        assert_eq!(report.out_of_range_triangles, vec![triangles]);
+        assert_eq!(report.leftover_indices, 2);
+        assert_eq!(report.triangle_count, triangles);
+        // Everything else is about the rest of the mesh, which is fine.
+        assert!(report.is_manifold() && report.is_closed());
+        assert_eq!(report.genus, Some(0));
+        assert!(!report.is_valid_solid());
+    }
+}
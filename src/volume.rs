@@ -0,0 +1,549 @@
+// Readers for scanned/simulated volume files: headerless .raw, NRRD
+// (.nrrd/.nhdr) and MetaImage (.mha/.mhd). Only uncompressed 3d scalar
+// volumes are supported. Malformed or unsupported files are reported as
+// InvalidData errors.
+//
+// Volumes hold intensities (densities, temperatures, ...), not distances,
+// so they have to be thresholded with Volume::to_grid before meshing.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use grid::Grid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElementType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+impl ElementType {
+    pub fn size(self) -> usize {
+        match self {
+            ElementType::U8 | ElementType::I8 => 1,
+            ElementType::U16 | ElementType::I16 => 2,
+            ElementType::U32 | ElementType::I32 | ElementType::F32 => 4,
+            ElementType::F64 => 8,
+        }
+    }
+}
+
+// Everything needed to interpret headerless voxel data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawHeader {
+    pub size: [usize; 3],
+    pub element: ElementType,
+    pub big_endian: bool,
+    pub spacing: [f32; 3],
+    pub origin: [f32; 3],
+}
+
+pub struct Volume {
+    pub size: [usize; 3],
+    pub spacing: [f32; 3],
+    pub origin: [f32; 3],
+    // Converted to f32 regardless of the file's element type. X varies
+    // fastest, like in the files themselves.
+    pub data: Vec<f32>,
+}
+
+impl Volume {
+    // Anything denser than iso_level becomes solid. The result is in
+    // intensity units rather than a distance, which is fine for surface_net
+    // as only the interpolation between neighbouring samples matters.
+    pub fn to_grid(&self, iso_level: f32) -> Grid {
+        let mut grid = Grid::new(self.size, self.origin, self.spacing);
+        for (value, &intensity) in grid.data.iter_mut().zip(&self.data) {
+            *value = iso_level - intensity;
+        }
+        // Fall off to air right outside the volume, at about the same rate as
+        // the data does.
+        let (low, high) = self
+            .data
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), &v| {
+                (low.min(v), high.max(v))
+            });
+        grid.background = (iso_level - low).max(high - iso_level).max(1.0);
+        grid
+    }
+}
+
+pub fn read_raw<P: AsRef<Path>>(path: P, header: &RawHeader) -> io::Result<Volume> {
+    let bytes = fs::read(path)?;
+    let data = decode(
+        &bytes,
+        header.element,
+        header.big_endian,
+        count(header.size, header.element)?,
+    )?;
+    Ok(Volume {
+        size: header.size,
+        spacing: header.spacing,
+        origin: header.origin,
+        data,
+    })
+}
+
+// Reads both attached (.nrrd) and detached (.nhdr + data file) NRRD files.
+pub fn read_nrrd<P: AsRef<Path>>(path: P) -> io::Result<Volume> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    if !bytes.starts_with(b"NRRD") {
+        return Err(invalid_data("not a NRRD file (missing NRRD magic)"));
+    }
+    let mut position = 0;
+    let mut fields = Vec::new();
+    // The header ends at the first empty line (or the end of a detached
+    // header file).
+    while let Some(line) = next_line(&bytes, &mut position) {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with('#') || line.starts_with("NRRD") {
+            continue;
+        }
+        if let Some(split) = line.find(": ") {
+            let (key, value) = (&line[..split], &line[split + 2..]);
+            fields.push((key.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|field| field.0 == name)
+            .map(|field| field.1.as_str())
+    };
+    let required = |name: &str| {
+        field(name).ok_or_else(|| invalid_data(&format!("NRRD header is missing `{}`", name)))
+    };
+
+    if required("dimension")? != "3" {
+        return Err(invalid_data(
+            "only 3-dimensional NRRD volumes are supported",
+        ));
+    }
+    let size = parse_three(required("sizes")?, "NRRD sizes")?;
+    let element = nrrd_type(required("type")?)?;
+    let encoding = required("encoding")?;
+    if encoding != "raw" {
+        return Err(invalid_data(&format!(
+            "unsupported NRRD encoding `{}`, only raw is supported",
+            encoding
+        )));
+    }
+    let big_endian = field("endian") == Some("big");
+    let mut spacing = [1.0; 3];
+    if let Some(spacings) = field("spacings") {
+        spacing = parse_three(spacings, "NRRD spacings")?;
+    } else if let Some(directions) = field("space directions") {
+        // One vector per axis, e.g. (0.5,0,0) (0,0.5,0) (0,0,2). Rotated
+        // volumes aren't supported, only the length of each vector is used.
+        let vectors = parse_vectors(directions)?;
+        if vectors.len() != 3 {
+            return Err(invalid_data("NRRD space directions must have 3 vectors"));
+        }
+        for axis in 0..3 {
+            let v = vectors[axis];
+            spacing[axis] = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        }
+    }
+    let mut origin = [0.0; 3];
+    if let Some(space_origin) = field("space origin") {
+        let vectors = parse_vectors(space_origin)?;
+        origin = *vectors
+            .first()
+            .ok_or_else(|| invalid_data("malformed NRRD space origin"))?;
+    }
+    let byte_skip = field("byte skip")
+        .map(|skip| {
+            skip.parse::<i64>()
+                .map_err(|_| invalid_data("malformed NRRD byte skip"))
+        })
+        .unwrap_or(Ok(0))?;
+
+    let data_bytes = match field("data file").or_else(|| field("datafile")) {
+        Some(file) => fs::read(path.with_file_name(file))?,
+        None => bytes[position..].to_vec(),
+    };
+    let count = count(size, element)?;
+    let data_bytes = skip_bytes(&data_bytes, byte_skip, count * element.size())?;
+    let data = decode(data_bytes, element, big_endian, count)?;
+    Ok(Volume {
+        size,
+        spacing,
+        origin,
+        data,
+    })
+}
+
+// Reads MetaImage files, both .mha (header and data in one file) and .mhd
+// (data in a separate file named by ElementDataFile).
+pub fn read_metaimage<P: AsRef<Path>>(path: P) -> io::Result<Volume> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    let mut position = 0;
+    let mut fields = Vec::new();
+    // ElementDataFile is always the last header line.
+    while let Some(line) = next_line(&bytes, &mut position) {
+        if let Some(split) = line.find('=') {
+            let key = line[..split].trim().to_string();
+            let value = line[split + 1..].trim().to_string();
+            let last = key == "ElementDataFile";
+            fields.push((key, value));
+            if last {
+                break;
+            }
+        }
+    }
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|field| field.0 == name)
+            .map(|field| field.1.as_str())
+    };
+    let required = |name: &str| {
+        field(name).ok_or_else(|| invalid_data(&format!("MetaImage header is missing `{}`", name)))
+    };
+
+    if required("NDims")? != "3" {
+        return Err(invalid_data(
+            "only 3-dimensional MetaImage volumes are supported",
+        ));
+    }
+    if field("CompressedData").is_some_and(is_true) {
+        return Err(invalid_data("compressed MetaImage data is not supported"));
+    }
+    let size = parse_three(required("DimSize")?, "MetaImage DimSize")?;
+    let element = metaimage_type(required("ElementType")?)?;
+    let big_endian = field("BinaryDataByteOrderMSB")
+        .or_else(|| field("ElementByteOrderMSB"))
+        .is_some_and(is_true);
+    let spacing = match field("ElementSpacing").or_else(|| field("ElementSize")) {
+        Some(spacing) => parse_three(spacing, "MetaImage ElementSpacing")?,
+        None => [1.0; 3],
+    };
+    let origin = match field("Offset")
+        .or_else(|| field("Origin"))
+        .or_else(|| field("Position"))
+    {
+        Some(origin) => parse_three(origin, "MetaImage Offset")?,
+        None => [0.0; 3],
+    };
+    let header_size = field("HeaderSize")
+        .map(|size| {
+            size.parse::<i64>()
+                .map_err(|_| invalid_data("malformed MetaImage HeaderSize"))
+        })
+        .unwrap_or(Ok(0))?;
+
+    let data_bytes = match required("ElementDataFile")? {
+        "LOCAL" => bytes[position..].to_vec(),
+        file => fs::read(path.with_file_name(file))?,
+    };
+    let count = count(size, element)?;
+    let data_bytes = skip_bytes(&data_bytes, header_size, count * element.size())?;
+    let data = decode(data_bytes, element, big_endian, count)?;
+    Ok(Volume {
+        size,
+        spacing,
+        origin,
+        data,
+    })
+}
+
+// Number of samples, checked so that the data's length in bytes fits in a
+// usize too. decode and the callers multiply by the element size freely.
+fn count(size: [usize; 3], element: ElementType) -> io::Result<usize> {
+    size[0]
+        .checked_mul(size[1])
+        .and_then(|count| count.checked_mul(size[2]))
+        .filter(|count| count.checked_mul(element.size()).is_some())
+        .ok_or_else(|| invalid_data("volume dimensions too large"))
+}
+
+fn decode(
+    bytes: &[u8],
+    element: ElementType,
+    big_endian: bool,
+    count: usize,
+) -> io::Result<Vec<f32>> {
+    let element_size = element.size();
+    if bytes.len() < count * element_size {
+        return Err(invalid_data(&format!(
+            "volume data is truncated: expected {} bytes, found {}",
+            count * element_size,
+            bytes.len()
+        )));
+    }
+    let data = bytes[..count * element_size]
+        .chunks_exact(element_size)
+        .map(|chunk| {
+            let mut raw = [0; 8];
+            raw[..element_size].copy_from_slice(chunk);
+            // Normalize to little endian.
+            if big_endian {
+                raw[..element_size].reverse();
+            }
+            match element {
+                ElementType::U8 => raw[0] as f32,
+                ElementType::I8 => raw[0] as i8 as f32,
+                ElementType::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f32,
+                ElementType::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f32,
+                ElementType::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f32,
+                ElementType::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f32,
+                ElementType::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]),
+                ElementType::F64 => f64::from_le_bytes(raw) as f32,
+            }
+        })
+        .collect();
+    Ok(data)
+}
+
+// Both NRRD and MetaImage use -1 to mean "the data is at the very end of the
+// file, skip whatever comes before it".
+fn skip_bytes(bytes: &[u8], skip: i64, data_length: usize) -> io::Result<&[u8]> {
+    let start = if skip < 0 {
+        bytes.len().saturating_sub(data_length)
+    } else {
+        skip as usize
+    };
+    bytes
+        .get(start..)
+        .ok_or_else(|| invalid_data("header skips past the end of the data"))
+}
+
+fn next_line(bytes: &[u8], position: &mut usize) -> Option<String> {
+    if *position >= bytes.len() {
+        return None;
+    }
+    let start = *position;
+    let end = bytes[start..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |offset| start + offset);
+    *position = (end + 1).min(bytes.len());
+    let line = String::from_utf8_lossy(&bytes[start..end]);
+    Some(line.trim_end_matches('\r').to_string())
+}
+
+fn parse_three<T: std::str::FromStr + Copy + Default>(
+    value: &str,
+    what: &str,
+) -> io::Result<[T; 3]> {
+    let values = value
+        .split_whitespace()
+        .map(|v| v.parse::<T>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid_data(&format!("malformed {}", what)))?;
+    if values.len() != 3 {
+        return Err(invalid_data(&format!("{} must have 3 values", what)));
+    }
+    Ok([values[0], values[1], values[2]])
+}
+
+// Parses NRRD vectors like "(1,0,0) (0,1,0) (0,0,1)".
+fn parse_vectors(value: &str) -> io::Result<Vec<[f32; 3]>> {
+    value
+        .split(')')
+        .map(|part| part.trim().trim_start_matches('('))
+        .filter(|part| !part.is_empty())
+        .map(|part| parse_three(&part.replace(',', " "), "NRRD vector"))
+        .collect()
+}
+
+fn is_true(value: &str) -> bool {
+    value.eq_ignore_ascii_case("true")
+}
+
+fn nrrd_type(name: &str) -> io::Result<ElementType> {
+    let element = match name {
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => ElementType::U8,
+        "signed char" | "int8" | "int8_t" => ElementType::I8,
+        "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+            ElementType::U16
+        }
+        "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => {
+            ElementType::I16
+        }
+        "uint" | "unsigned int" | "uint32" | "uint32_t" => ElementType::U32,
+        "int" | "signed int" | "int32" | "int32_t" => ElementType::I32,
+        "float" => ElementType::F32,
+        "double" => ElementType::F64,
+        _ => return Err(invalid_data(&format!("unsupported NRRD type `{}`", name))),
+    };
+    Ok(element)
+}
+
+fn metaimage_type(name: &str) -> io::Result<ElementType> {
+    let element = match name {
+        "MET_UCHAR" => ElementType::U8,
+        "MET_CHAR" => ElementType::I8,
+        "MET_USHORT" => ElementType::U16,
+        "MET_SHORT" => ElementType::I16,
+        "MET_UINT" => ElementType::U32,
+        "MET_INT" => ElementType::I32,
+        "MET_FLOAT" => ElementType::F32,
+        "MET_DOUBLE" => ElementType::F64,
+        _ => {
+            return Err(invalid_data(&format!(
+                "unsupported MetaImage ElementType `{}`",
+                name
+            )))
+        }
+    };
+    Ok(element)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::process;
+
+    // Writes a file to the temp directory, named so that parallel test runs
+    // don't trip over each other.
+    fn temp_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("surface-nets-{}-{}", process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn samples() -> Vec<i16> {
+        (0..24).map(|i| i * 100 - 1000).collect()
+    }
+
+    fn check(volume: &Volume) {
+        assert_eq!(volume.size, [2, 3, 4]);
+        let expected = samples().iter().map(|&v| v as f32).collect::<Vec<_>>();
+        assert_eq!(volume.data, expected);
+    }
+
+    fn assert_invalid(result: io::Result<Volume>) {
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let bytes = samples()
+            .iter()
+            .flat_map(|v| v.to_be_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let path = temp_file("raw_round_trip.raw", &bytes);
+        let header = RawHeader {
+            size: [2, 3, 4],
+            element: ElementType::I16,
+            big_endian: true,
+            spacing: [1.0, 2.0, 3.0],
+            origin: [0.5; 3],
+        };
+        let volume = read_raw(&path, &header).unwrap();
+        check(&volume);
+        assert_eq!(volume.spacing, [1.0, 2.0, 3.0]);
+        assert_eq!(volume.origin, [0.5; 3]);
+
+        assert_invalid(read_raw(
+            &path,
+            &RawHeader {
+                size: [2, 3, 5],
+                ..header
+            },
+        ));
+        assert_invalid(read_raw(
+            &path,
+            &RawHeader {
+                size: [usize::MAX, 2, 2],
+                ..header
+            },
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn nrrd_round_trip() {
+        let mut bytes = b"NRRD0004\n# comment\ndimension: 3\ntype: short\nsizes: 2 3 4\n\
+            encoding: raw\nendian: little\n\
+            space directions: (0.5,0,0) (0,0.5,0) (0,0,2)\nspace origin: (1,2,3)\n\n"
+            .to_vec();
+        for value in samples() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let path = temp_file("nrrd_round_trip.nrrd", &bytes);
+        let volume = read_nrrd(&path).unwrap();
+        check(&volume);
+        assert_eq!(volume.spacing, [0.5, 0.5, 2.0]);
+        assert_eq!(volume.origin, [1.0, 2.0, 3.0]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn detached_metaimage_round_trip() {
+        let data = samples()
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let data_path = temp_file("metaimage_round_trip.raw", &data);
+        let header = format!(
+            "ObjectType = Image\nNDims = 3\nDimSize = 2 3 4\nElementType = MET_SHORT\n\
+             ElementSpacing = 1 1 0.25\nElementDataFile = {}\n",
+            data_path.file_name().unwrap().to_str().unwrap()
+        );
+        let path = temp_file("metaimage_round_trip.mhd", header.as_bytes());
+        let volume = read_metaimage(&path).unwrap();
+        check(&volume);
+        assert_eq!(volume.spacing, [1.0, 1.0, 0.25]);
+        fs::remove_file(path).unwrap();
+        fs::remove_file(data_path).unwrap();
+    }
+
+    #[test]
+    fn malformed_nrrd() {
+        let headers: &[&[u8]] = &[
+            b"not a nrrd\n\n",
+            b"NRRD0004\ntype: short\nsizes: 1 1 1\nencoding: raw\n\n\0\0",
+            b"NRRD0004\ndimension: 2\ntype: short\nsizes: 1 1\nencoding: raw\n\n\0\0",
+            b"NRRD0004\ndimension: 3\ntype: quaternion\nsizes: 1 1 1\nencoding: raw\n\n\0\0",
+            b"NRRD0004\ndimension: 3\ntype: short\nsizes: 1 1 1\nencoding: gzip\n\n\0\0",
+            b"NRRD0004\ndimension: 3\ntype: short\nsizes: 2 2 2\nencoding: raw\n\n\0\0",
+            b"NRRD0004\ndimension: 3\ntype: short\nsizes: 1 x 1\nencoding: raw\n\n\0\0",
+            b"NRRD0004\ndimension: 3\ntype: double\n\
+              sizes: 4294967296 4294967296 4294967296\nencoding: raw\n\n\0\0",
+            b"NRRD0004\ndimension: 3\ntype: double\n\
+              sizes: 2305843009213693952 1 1\nencoding: raw\n\n\0\0",
+        ];
+        for (index, header) in headers.iter().enumerate() {
+            let path = temp_file(&format!("malformed_{}.nrrd", index), header);
+            assert_invalid(read_nrrd(&path));
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn malformed_metaimage() {
+        let headers: &[&[u8]] = &[
+            b"NDims = 3\nElementType = MET_SHORT\nElementDataFile = LOCAL\n\0\0",
+            b"NDims = 3\nDimSize = 1 1 1\nElementType = MET_SHORT\nCompressedData = True\n\
+              ElementDataFile = LOCAL\n\0\0",
+            b"NDims = 3\nDimSize = 1 1 1\nElementType = MET_SHORT\nHeaderSize = 100\n\
+              ElementDataFile = LOCAL\n\0\0",
+            b"NDims = 3\nDimSize = 4294967296 4294967296 4294967296\n\
+              ElementType = MET_SHORT\nElementDataFile = LOCAL\n\0\0",
+        ];
+        for (index, header) in headers.iter().enumerate() {
+            let path = temp_file(&format!("malformed_{}.mha", index), header);
+            assert_invalid(read_metaimage(&path));
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
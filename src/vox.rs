@@ -0,0 +1,370 @@
+// MagicaVoxel .vox import, for meshing voxel art into smooth surfaces.
+// Format reference:
+// https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt
+// Only the models and the palette are read, the scene graph (node transforms,
+// groups, layers) and materials are skipped. Coordinates are kept as they are
+// in the file, where Z is up.
+
+use std::io::{self, Read};
+
+use grid::Grid;
+
+const MAX_MODEL_SIZE: usize = 256;
+
+pub struct VoxModel {
+    pub size: [usize; 3],
+    // Palette index of each voxel, 0 for empty. X varies fastest.
+    pub voxels: Vec<u8>,
+}
+
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    // RGBA, indexed by the voxels' palette indices.
+    pub palette: Vec<[u8; 4]>,
+}
+
+pub fn read_vox<R: Read>(reader: &mut R) -> io::Result<VoxFile> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.len() < 8 || &bytes[..4] != b"VOX " {
+        return Err(invalid_data("not a MagicaVoxel file (missing VOX magic)"));
+    }
+    let mut position = 8;
+    let (id, content) = chunk(&bytes, &mut position)?;
+    if id != b"MAIN" || !content.is_empty() {
+        return Err(invalid_data("expected a MAIN chunk"));
+    }
+    let mut models = Vec::new();
+    let mut size = None;
+    let mut palette = default_palette();
+    // The rest of the file is MAIN's children, all at the same level.
+    while position < bytes.len() {
+        let (id, content) = chunk(&bytes, &mut position)?;
+        match id {
+            b"SIZE" => {
+                if content.len() < 12 {
+                    return Err(invalid_data("SIZE chunk is truncated"));
+                }
+                let model_size = [
+                    read_u32(content, 0) as usize,
+                    read_u32(content, 4) as usize,
+                    read_u32(content, 8) as usize,
+                ];
+                // Voxel coordinates are single bytes, so models can't be any
+                // bigger than this.
+                if model_size.iter().any(|&size| size > MAX_MODEL_SIZE) {
+                    return Err(invalid_data("model size is larger than 256 voxels"));
+                }
+                size = Some(model_size);
+            }
+            b"XYZI" => {
+                // Every XYZI chunk is preceded by the SIZE chunk of its model.
+                let size = size
+                    .take()
+                    .ok_or_else(|| invalid_data("XYZI chunk without a SIZE chunk"))?;
+                if content.len() < 4 {
+                    return Err(invalid_data("XYZI chunk is truncated"));
+                }
+                let count = read_u32(content, 0) as usize;
+                let voxel_data = content
+                    .get(4..4 + count.saturating_mul(4))
+                    .ok_or_else(|| invalid_data("XYZI chunk is truncated"))?;
+                let voxel_count = size[0]
+                    .checked_mul(size[1])
+                    .and_then(|count| count.checked_mul(size[2]))
+                    .ok_or_else(|| invalid_data("model size is too large"))?;
+                let mut voxels = vec![0; voxel_count];
+                for voxel in voxel_data.chunks_exact(4) {
+                    let (x, y, z) = (voxel[0] as usize, voxel[1] as usize, voxel[2] as usize);
+                    if x >= size[0] || y >= size[1] || z >= size[2] {
+                        return Err(invalid_data("voxel outside of its model's size"));
+                    }
+                    voxels[(z * size[1] + y) * size[0] + x] = voxel[3];
+                }
+                models.push(VoxModel { size, voxels });
+            }
+            b"RGBA" => {
+                if content.len() < 256 * 4 {
+                    return Err(invalid_data("RGBA chunk is truncated"));
+                }
+                // Stored color i is palette index i + 1, index 0 is always
+                // empty.
+                palette[0] = [0; 4];
+                for index in 0..255 {
+                    let color = &content[index * 4..index * 4 + 4];
+                    palette[index + 1] = [color[0], color[1], color[2], color[3]];
+                }
+            }
+            // PACK just says how many models there are, and everything else
+            // is scene graph or material data.
+            _ => (),
+        }
+    }
+    Ok(VoxFile { models, palette })
+}
+
+impl VoxModel {
+    // Occupancy as a field: solid voxels are -0.5, empty ones 0.5, so the
+    // surface passes exactly halfway between them. One unit per voxel.
+    pub fn to_grid(&self) -> Grid {
+        let mut grid = Grid::new(self.size, [0.0; 3], [1.0; 3]);
+        grid.background = 0.5;
+        for (value, &voxel) in grid.data.iter_mut().zip(&self.voxels) {
+            *value = if voxel == 0 { 0.5 } else { -0.5 };
+        }
+        grid
+    }
+
+    pub fn voxel(&self, x: isize, y: isize, z: isize) -> u8 {
+        if x < 0
+            || y < 0
+            || z < 0
+            || x as usize >= self.size[0]
+            || y as usize >= self.size[1]
+            || z as usize >= self.size[2]
+        {
+            0
+        } else {
+            self.voxels[(z as usize * self.size[1] + y as usize) * self.size[0] + x as usize]
+        }
+    }
+
+    // Smooth mesh of the model, plus a color per vertex: the average of the
+    // solid voxels at the corners of the cell the vertex was made from.
+    // Voxels that are past the end of the palette don't count towards it.
+    #[allow(clippy::type_complexity)]
+    pub fn surface_net(
+        &self,
+        palette: &[[u8; 4]],
+    ) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<[u8; 4]>, Vec<usize>) {
+        let (positions, normals, indices) = self.to_grid().surface_net();
+        let colors = positions
+            .iter()
+            .map(|position| {
+                let cell = [
+                    position[0].floor() as isize,
+                    position[1].floor() as isize,
+                    position[2].floor() as isize,
+                ];
+                let mut sum = [0u32; 4];
+                let mut count = 0;
+                for corner in 0..8 {
+                    let voxel = self.voxel(
+                        cell[0] + (corner & 1),
+                        cell[1] + ((corner >> 1) & 1),
+                        cell[2] + ((corner >> 2) & 1),
+                    );
+                    if voxel == 0 {
+                        continue;
+                    }
+                    if let Some(voxel_color) = palette.get(voxel as usize) {
+                        for (total, &channel) in sum.iter_mut().zip(voxel_color) {
+                            *total += channel as u32;
+                        }
+                        count += 1;
+                    }
+                }
+                let mut color = [0; 4];
+                for (channel, total) in color.iter_mut().zip(&sum) {
+                    *channel = total.checked_div(count).unwrap_or(0) as u8;
+                }
+                color
+            })
+            .collect();
+        (positions, normals, colors, indices)
+    }
+}
+
+// Returns the chunk's (id, content) and moves to its first child, or to the
+// next chunk if it has no children. MAIN's children are just read as the
+// chunks after it.
+fn chunk<'a>(bytes: &'a [u8], position: &mut usize) -> io::Result<(&'a [u8], &'a [u8])> {
+    let header = bytes
+        .get(*position..*position + 12)
+        .ok_or_else(|| invalid_data("chunk header is truncated"))?;
+    let content_size = read_u32(header, 4) as usize;
+    let content_start = *position + 12;
+    let children_start = content_start
+        .checked_add(content_size)
+        .ok_or_else(|| invalid_data("chunk size is out of range"))?;
+    let content = bytes
+        .get(content_start..children_start)
+        .ok_or_else(|| invalid_data("chunk content is truncated"))?;
+    *position = children_start;
+    Ok((&header[..4], content))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+// MagicaVoxel's built in palette, used when a file has no RGBA chunk: a 6x6x6
+// color cube (without black), then ramps of red, green, blue and gray.
+fn default_palette() -> Vec<[u8; 4]> {
+    let levels = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    let mut palette = vec![[0, 0, 0, 0]];
+    for &r in &levels {
+        for &g in &levels {
+            for &b in &levels {
+                palette.push([r, g, b, 0xff]);
+            }
+        }
+    }
+    // The cube ends with black, which isn't in the palette.
+    palette.pop();
+    for &v in &ramp {
+        palette.push([v, 0, 0, 0xff]);
+    }
+    for &v in &ramp {
+        palette.push([0, v, 0, 0xff]);
+    }
+    for &v in &ramp {
+        palette.push([0, 0, v, 0xff]);
+    }
+    for &v in &ramp {
+        palette.push([v, v, v, 0xff]);
+    }
+    palette
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(content);
+        bytes
+    }
+
+    fn size(size: [u32; 3]) -> Vec<u8> {
+        let content = size
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        chunk(b"SIZE", &content)
+    }
+
+    fn xyzi(voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut content = (voxels.len() as u32).to_le_bytes().to_vec();
+        for voxel in voxels {
+            content.extend_from_slice(voxel);
+        }
+        chunk(b"XYZI", &content)
+    }
+
+    fn file(children: &[Vec<u8>]) -> Vec<u8> {
+        let children = children.concat();
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend_from_slice(&150u32.to_le_bytes());
+        bytes.extend_from_slice(b"MAIN");
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as u32).to_le_bytes());
+        bytes.extend(children);
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut palette = vec![0; 256 * 4];
+        palette[..4].copy_from_slice(&[10, 20, 30, 255]);
+        let bytes = file(&[
+            chunk(b"PACK", &2u32.to_le_bytes()),
+            size([2, 3, 4]),
+            xyzi(&[[0, 0, 0, 1], [1, 2, 3, 7]]),
+            size([1, 1, 1]),
+            xyzi(&[]),
+            chunk(b"RGBA", &palette),
+        ]);
+        let vox = read_vox(&mut &bytes[..]).unwrap();
+        assert_eq!(vox.models.len(), 2);
+        let model = &vox.models[0];
+        assert_eq!(model.size, [2, 3, 4]);
+        assert_eq!(model.voxels.iter().filter(|&&v| v != 0).count(), 2);
+        assert_eq!(model.voxel(0, 0, 0), 1);
+        assert_eq!(model.voxel(1, 2, 3), 7);
+        assert_eq!(model.voxel(2, 0, 0), 0);
+        assert_eq!(vox.models[1].voxels, [0]);
+        assert_eq!(vox.palette[0], [0; 4]);
+        assert_eq!(vox.palette[1], [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn default_palette_without_rgba() {
+        let bytes = file(&[size([1, 1, 1]), xyzi(&[[0, 0, 0, 1]])]);
+        let vox = read_vox(&mut &bytes[..]).unwrap();
+        assert_eq!(vox.palette.len(), 256);
+        assert_eq!(vox.palette[1], [0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn malformed() {
+        let mut truncated = file(&[size([1, 1, 1]), xyzi(&[[0, 0, 0, 1]])]);
+        truncated.pop();
+        let inputs = [
+            b"VOX".to_vec(),
+            b"RIFF\x96\0\0\0".to_vec(),
+            truncated,
+            file(&[xyzi(&[[0, 0, 0, 1]])]),
+            file(&[size([2, 2, 2]), xyzi(&[[2, 0, 0, 1]])]),
+            file(&[size([257, 1, 1]), xyzi(&[])]),
+            file(&[size([u32::MAX; 3]), xyzi(&[])]),
+            file(&[chunk(b"SIZE", &[1, 0, 0, 0])]),
+            file(&[size([1, 1, 1]), chunk(b"XYZI", &[5, 0, 0, 0, 0, 0, 0, 1])]),
+            file(&[chunk(b"RGBA", &[0; 16])]),
+        ];
+        for input in &inputs {
+            let error = read_vox(&mut &input[..]).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn colored_surface_net() {
+        let bytes = file(&[size([2, 1, 1]), xyzi(&[[0, 0, 0, 1], [1, 0, 0, 2]])]);
+        let vox = read_vox(&mut &bytes[..]).unwrap();
+        let model = &vox.models[0];
+        let palette = [[0; 4], [200, 0, 0, 255], [0, 100, 0, 255]];
+        let (positions, normals, colors, indices) = model.surface_net(&palette);
+        assert_eq!(normals.len(), positions.len());
+        assert_eq!(colors.len(), positions.len());
+        assert!(!indices.is_empty());
+        for (position, color) in positions.iter().zip(&colors) {
+            // Vertices next to only one of the voxels get its color, the ones
+            // in between get the average.
+            let expected = if position[0] < 0.0 {
+                [200, 0, 0, 255]
+            } else if position[0] >= 1.0 {
+                [0, 100, 0, 255]
+            } else {
+                [100, 50, 0, 255]
+            };
+            assert_eq!(*color, expected, "{:?}", position);
+        }
+
+        // Voxel 2 has no color in a shorter palette.
+        let (positions, _, colors, _) = model.surface_net(&palette[..2]);
+        for (position, color) in positions.iter().zip(&colors) {
+            let expected = if position[0] >= 1.0 {
+                [0; 4]
+            } else {
+                [200, 0, 0, 255]
+            };
+            assert_eq!(*color, expected, "{:?}", position);
+        }
+        let (_, _, colors, _) = model.surface_net(&[]);
+        assert!(colors.iter().all(|color| *color == [0; 4]));
+    }
+}
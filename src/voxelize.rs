@@ -0,0 +1,417 @@
+// Mesh to signed distance grid conversion, the opposite of surface_net.
+// Going mesh -> grid -> mesh is a cheap way to repair (fill small holes, get
+// rid of self-intersections and non-manifold bits), offset and uniformly
+// remesh imported models, and the grid can be combined with procedural
+// fields before meshing.
+//
+// Distances are exact near the surface and propagated outwards with fast
+// sweeping (like Christopher Batty's SDFGen), so they're only approximate
+// far away from the mesh.
+
+use std::collections::VecDeque;
+
+use grid::Grid;
+use math::{closest_point_on_triangle, cross, dot, length, sub, triangle};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignMode {
+    // Count how many times a ray along +X crosses the mesh. Fast, and doesn't
+    // care about triangle winding, but holes in the mesh leak "inside" along
+    // whole rows of voxels.
+    RayParity,
+    // Generalized winding number (Jacobson et al. 2013): a point is inside if
+    // the mesh wraps around it more than halfway. Much more forgiving of
+    // holes and overlaps, but needs consistently wound triangles and is a lot
+    // slower.
+    WindingNumber,
+}
+
+// How many voxels around each triangle get exact distances before
+// propagation takes over.
+const EXACT_BAND: isize = 1;
+
+// Builds a grid covering the mesh plus `padding` voxels on each side, with
+// voxels `spacing` apart. Values are negative inside the mesh.
+// None if spacing isn't positive and finite, the indices aren't whole
+// triangles within positions, or the grid would have too many voxels to
+// count.
+pub fn voxelize(
+    positions: &[[f32; 3]],
+    indices: &[usize],
+    spacing: f32,
+    padding: usize,
+    sign_mode: SignMode,
+) -> Option<Grid> {
+    let whole_triangles =
+        indices.len().is_multiple_of(3) && indices.iter().all(|&index| index < positions.len());
+    if !(spacing > 0.0 && spacing.is_finite() && whole_triangles) {
+        return None;
+    }
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+    for &index in indices {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(positions[index][axis]);
+            max[axis] = max[axis].max(positions[index][axis]);
+        }
+    }
+    if indices.is_empty() {
+        min = [0.0; 3];
+        max = [0.0; 3];
+    }
+    let mut size = [0; 3];
+    let mut origin = [0.0; 3];
+    for axis in 0..3 {
+        let cells = ((max[axis] - min[axis]) / spacing).ceil();
+        if !cells.is_finite() {
+            return None;
+        }
+        size[axis] = (cells as usize)
+            .checked_add(1)?
+            .checked_add(padding.checked_mul(2)?)?;
+        origin[axis] = min[axis] - padding as f32 * spacing;
+    }
+    size.iter()
+        .try_fold(1usize, |total, &n| total.checked_mul(n))?;
+    let mut grid = Grid::new(size, origin, [spacing; 3]);
+    let closest = exact_distances(&mut grid, positions, indices);
+    sweep(&mut grid, positions, indices, closest);
+    match sign_mode {
+        SignMode::RayParity => sign_by_parity(&mut grid, positions, indices),
+        SignMode::WindingNumber => sign_by_winding_number(&mut grid, positions, indices),
+    }
+    Some(grid)
+}
+
+// The whole round trip: voxelize, move the surface outwards by `offset`
+// (inwards if negative), and mesh the result with surface_net. None for the
+// same input as voxelize, or an offset that isn't finite.
+#[allow(clippy::type_complexity)]
+pub fn remesh(
+    positions: &[[f32; 3]],
+    indices: &[usize],
+    spacing: f32,
+    offset: f32,
+    sign_mode: SignMode,
+) -> Option<(Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<usize>)> {
+    if !offset.is_finite() {
+        return None;
+    }
+    // Enough padding that a positive offset doesn't get clipped by the edge
+    // of the grid.
+    let padding = ((offset.max(0.0) / spacing).ceil() as usize).checked_add(2)?;
+    let mut grid = voxelize(positions, indices, spacing, padding, sign_mode)?;
+    for value in &mut grid.data {
+        *value -= offset;
+    }
+    Some(grid.surface_net())
+}
+
+const NO_TRIANGLE: usize = usize::MAX;
+
+// Offset (in voxels, along Y and Z) of the parity rays from the voxel centers.
+const RAY_NUDGE: [f32; 2] = [1.0e-3 * std::f32::consts::E, 1.0e-3 * std::f32::consts::PI];
+
+fn distance_to_triangle(
+    point: [f32; 3],
+    positions: &[[f32; 3]],
+    indices: &[usize],
+    tri: usize,
+) -> f32 {
+    let corners = triangle(positions, &indices[tri * 3..tri * 3 + 3]);
+    length(sub(point, closest_point_on_triangle(point, corners)))
+}
+
+// Exact unsigned distances for the voxels close to each triangle. Returns the
+// closest triangle to each voxel (or NO_TRIANGLE for the rest).
+fn exact_distances(grid: &mut Grid, positions: &[[f32; 3]], indices: &[usize]) -> Vec<usize> {
+    let mut closest = vec![NO_TRIANGLE; grid.data.len()];
+    for value in &mut grid.data {
+        *value = f32::INFINITY;
+    }
+    for tri in 0..indices.len() / 3 {
+        let corners = triangle(positions, &indices[tri * 3..tri * 3 + 3]);
+        let mut low = [0; 3];
+        let mut high = [0; 3];
+        for axis in 0..3 {
+            let to_grid = |v: f32| (v - grid.origin[axis]) / grid.spacing[axis];
+            let lowest = corners
+                .iter()
+                .map(|c| to_grid(c[axis]))
+                .fold(f32::INFINITY, f32::min);
+            let highest = corners
+                .iter()
+                .map(|c| to_grid(c[axis]))
+                .fold(f32::NEG_INFINITY, f32::max);
+            low[axis] = (lowest.floor() as isize - EXACT_BAND).max(0) as usize;
+            high[axis] =
+                (highest.ceil() as isize + EXACT_BAND).min(grid.size[axis] as isize - 1) as usize;
+        }
+        for z in low[2]..=high[2] {
+            for y in low[1]..=high[1] {
+                for x in low[0]..=high[0] {
+                    let point = grid.position(x, y, z);
+                    let distance = length(sub(point, closest_point_on_triangle(point, corners)));
+                    let index = grid.index(x, y, z);
+                    if distance < grid.data[index] {
+                        grid.data[index] = distance;
+                        closest[index] = tri;
+                    }
+                }
+            }
+        }
+    }
+    closest
+}
+
+// Fast sweeping: pass over the grid in all 8 diagonal directions, letting each
+// voxel try the closest triangles of its already-visited neighbours.
+fn sweep(grid: &mut Grid, positions: &[[f32; 3]], indices: &[usize], mut closest: Vec<usize>) {
+    let size = grid.size;
+    for _ in 0..2 {
+        for direction in 0..8 {
+            let steps = [
+                if direction & 1 == 0 { 1 } else { -1 },
+                if direction & 2 == 0 { 1 } else { -1 },
+                if direction & 4 == 0 { 1 } else { -1 },
+            ];
+            let range = |axis: usize| -> Vec<usize> {
+                if steps[axis] == 1 {
+                    (0..size[axis]).collect()
+                } else {
+                    (0..size[axis]).rev().collect()
+                }
+            };
+            let (xs, ys, zs) = (range(0), range(1), range(2));
+            for &z in &zs {
+                for &y in &ys {
+                    for &x in &xs {
+                        let index = grid.index(x, y, z);
+                        let point = grid.position(x, y, z);
+                        for neighbour in 1..8 {
+                            let step = |bit: usize, axis: usize, value: usize| {
+                                if neighbour & bit != 0 {
+                                    (value as isize - steps[axis]) as usize
+                                } else {
+                                    value
+                                }
+                            };
+                            let (nx, ny, nz) = (step(1, 0, x), step(2, 1, y), step(4, 2, z));
+                            // Going below zero wraps around to a huge number.
+                            if nx >= size[0] || ny >= size[1] || nz >= size[2] {
+                                continue;
+                            }
+                            let tri = closest[grid.index(nx, ny, nz)];
+                            if tri == NO_TRIANGLE {
+                                continue;
+                            }
+                            let distance = distance_to_triangle(point, positions, indices, tri);
+                            if distance < grid.data[index] {
+                                grid.data[index] = distance;
+                                closest[index] = tri;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// For every row of voxels along X, find where the row crosses each triangle
+// and flip inside/outside at each crossing.
+fn sign_by_parity(grid: &mut Grid, positions: &[[f32; 3]], indices: &[usize]) {
+    let size = grid.size;
+    let mut crossings = vec![Vec::new(); size[1] * size[2]];
+    for tri in 0..indices.len() / 3 {
+        let corners = triangle(positions, &indices[tri * 3..tri * 3 + 3]);
+        // Work in grid units from here on.
+        let local =
+            |corner: [f32; 3], axis: usize| (corner[axis] - grid.origin[axis]) / grid.spacing[axis];
+        let (ay, az, ax) = (
+            local(corners[0], 1),
+            local(corners[0], 2),
+            local(corners[0], 0),
+        );
+        let (by, bz, bx) = (
+            local(corners[1], 1),
+            local(corners[1], 2),
+            local(corners[1], 0),
+        );
+        let (cy, cz, cx) = (
+            local(corners[2], 1),
+            local(corners[2], 2),
+            local(corners[2], 0),
+        );
+        let low_y = ay.min(by).min(cy).floor().max(0.0) as usize;
+        let high_y = (ay.max(by).max(cy).ceil() as isize).min(size[1] as isize - 1);
+        let low_z = az.min(bz).min(cz).floor().max(0.0) as usize;
+        let high_z = (az.max(bz).max(cz).ceil() as isize).min(size[2] as isize - 1);
+        let area = (by - ay) * (cz - az) - (cy - ay) * (bz - az);
+        if area == 0.0 || high_y < 0 || high_z < 0 {
+            continue;
+        }
+        for z in low_z..=high_z as usize {
+            for y in low_y..=high_y as usize {
+                // Nudge the rows off the grid lines, so that they don't pass
+                // exactly through the edges and corners of grid-aligned meshes
+                // (like surface_net's own output), which would count the same
+                // crossing twice.
+                let (py, pz) = (y as f32 + RAY_NUDGE[0], z as f32 + RAY_NUDGE[1]);
+                // Barycentric coordinates of the row in the triangle's
+                // projection onto the YZ plane.
+                let w0 = ((by - py) * (cz - pz) - (cy - py) * (bz - pz)) / area;
+                let w1 = ((cy - py) * (az - pz) - (ay - py) * (cz - pz)) / area;
+                let w2 = 1.0 - w0 - w1;
+                if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                    continue;
+                }
+                crossings[z * size[1] + y].push(w0 * ax + w1 * bx + w2 * cx);
+            }
+        }
+    }
+    for z in 0..size[2] {
+        for y in 0..size[1] {
+            let row = &mut crossings[z * size[1] + y];
+            row.sort_by(f32::total_cmp);
+            let mut next_crossing = 0;
+            for x in 0..size[0] {
+                while next_crossing < row.len() && row[next_crossing] < x as f32 {
+                    next_crossing += 1;
+                }
+                if next_crossing % 2 == 1 {
+                    let index = grid.index(x, y, z);
+                    grid.data[index] = -grid.data[index];
+                }
+            }
+        }
+    }
+}
+
+// Only the voxels near the surface get the (expensive) exact winding number.
+// The rest are flood filled from them, which is fine because nothing can get
+// from inside to outside without going through the band.
+fn sign_by_winding_number(grid: &mut Grid, positions: &[[f32; 3]], indices: &[usize]) {
+    let band = grid.spacing[0] * 2.0;
+    let size = grid.size;
+    let mut inside = vec![None; grid.data.len()];
+    let mut queue = VecDeque::new();
+    for z in 0..size[2] {
+        for y in 0..size[1] {
+            for x in 0..size[0] {
+                let index = grid.index(x, y, z);
+                if grid.data[index] <= band {
+                    let point = grid.position(x, y, z);
+                    inside[index] = Some(winding_number(point, positions, indices) > 0.5);
+                    queue.push_back((x, y, z));
+                }
+            }
+        }
+    }
+    while let Some((x, y, z)) = queue.pop_front() {
+        let sign = inside[grid.index(x, y, z)];
+        let neighbours = [
+            (x.wrapping_sub(1), y, z),
+            (x + 1, y, z),
+            (x, y.wrapping_sub(1), z),
+            (x, y + 1, z),
+            (x, y, z.wrapping_sub(1)),
+            (x, y, z + 1),
+        ];
+        for &(nx, ny, nz) in &neighbours {
+            if nx >= size[0] || ny >= size[1] || nz >= size[2] {
+                continue;
+            }
+            let neighbour = grid.index(nx, ny, nz);
+            if inside[neighbour].is_none() {
+                inside[neighbour] = sign;
+                queue.push_back((nx, ny, nz));
+            }
+        }
+    }
+    for (value, inside) in grid.data.iter_mut().zip(inside) {
+        if inside == Some(true) {
+            *value = -*value;
+        }
+    }
+}
+
+// Sum of the solid angles of all triangles as seen from the point, divided by
+// 4 pi: 1 inside a closed, outwards-wound mesh, 0 outside. Uses the formula
+// from Van Oosterom and Strackee.
+fn winding_number(point: [f32; 3], positions: &[[f32; 3]], indices: &[usize]) -> f32 {
+    let mut total = 0.0f64;
+    for tri in indices.chunks(3) {
+        let a = sub(positions[tri[0]], point);
+        let b = sub(positions[tri[1]], point);
+        let c = sub(positions[tri[2]], point);
+        let (la, lb, lc) = (length(a), length(b), length(c));
+        let numerator = dot(a, cross(b, c));
+        let denominator = la * lb * lc + dot(a, b) * lc + dot(b, c) * la + dot(c, a) * lb;
+        total += 2.0 * (numerator as f64).atan2(denominator as f64);
+    }
+    (total / (4.0 * std::f64::consts::PI)) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validate::validate;
+
+    // An outwards-wound box from -1 to 1, but stretched along X so that it's
+    // not symmetric.
+    fn cuboid() -> (Vec<[f32; 3]>, Vec<usize>) {
+        let mut positions = Vec::new();
+        for i in 0..8 {
+            positions.push([
+                if i & 1 == 0 { -1.5 } else { 1.5 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+            ]);
+        }
+        let indices = vec![
+            0, 4, 6, 0, 6, 2, 1, 3, 7, 1, 7, 5, 0, 1, 5, 0, 5, 4, 2, 6, 7, 2, 7, 3, 0, 2, 3, 0, 3,
+            1, 4, 5, 7, 4, 7, 6,
+        ];
+        (positions, indices)
+    }
+
+    #[test]
+    fn remeshes_a_closed_mesh() {
+        let (positions, indices) = cuboid();
+        for &sign_mode in &[SignMode::RayParity, SignMode::WindingNumber] {
+            let grid = voxelize(&positions, &indices, 0.25, 2, sign_mode).unwrap();
+            assert!(grid.sample([0.0, 0.0, 0.0]) < -0.9);
+            assert!((grid.sample([1.0, 0.0, 0.0]) + 0.5).abs() < 1e-4);
+            assert!((grid.sample([2.0, 0.0, 0.0]) - 0.5).abs() < 1e-4);
+
+            let (new_positions, _, new_indices) =
+                remesh(&positions, &indices, 0.25, 0.25, sign_mode).unwrap();
+            assert!(validate(&new_positions, &new_indices).is_valid_solid());
+            for position in &new_positions {
+                let outside = [
+                    position[0].abs() - 1.5,
+                    position[1].abs() - 1.0,
+                    position[2].abs() - 1.0,
+                ];
+                let outside = outside.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                assert!(outside > 0.0 && outside < 0.5, "{:?}", position);
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_bad_input() {
+        let (positions, indices) = cuboid();
+        for &spacing in &[0.0, -0.25, f32::NAN, f32::INFINITY, 1e-30] {
+            assert!(voxelize(&positions, &indices, spacing, 2, SignMode::RayParity).is_none());
+        }
+        assert!(voxelize(&positions, &indices, 0.25, usize::MAX, SignMode::RayParity).is_none());
+        assert!(voxelize(&positions, &indices[..4], 0.25, 2, SignMode::WindingNumber).is_none());
+        let mut out_of_range = indices.clone();
+        out_of_range[5] = 8;
+        assert!(voxelize(&positions, &out_of_range, 0.25, 2, SignMode::RayParity).is_none());
+        assert!(remesh(&positions, &indices, 0.25, f32::NAN, SignMode::RayParity).is_none());
+        assert!(remesh(&positions, &indices, 1e-30, 1.0, SignMode::RayParity).is_none());
+    }
+}